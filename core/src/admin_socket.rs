@@ -0,0 +1,270 @@
+use crate::config::Settings;
+use anyhow::{Context, Result};
+use axum::{body::Body, http::StatusCode, Router};
+
+/// Credentials of the process on the other end of the admin socket, attached
+/// to every request received over it.
+#[derive(Debug, Clone)]
+pub struct LocalPeer {
+    pub uid: u32,
+    pub pid: Option<i32>,
+    pub trusted: bool,
+}
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn effective_uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn group_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return None;
+    }
+    Some(unsafe { (*group).gr_gid })
+}
+
+/// Whether the user `uid` is a member of group `gid`, as primary group or
+/// as a supplementary one listed in the group database.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn in_group(uid: u32, gid: u32) -> bool {
+    let user = unsafe { libc::getpwuid(uid) };
+    if user.is_null() {
+        return false;
+    }
+    if unsafe { (*user).pw_gid } == gid {
+        return true;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr((*user).pw_name) }.to_owned();
+
+    let group = unsafe { libc::getgrgid(gid) };
+    if group.is_null() {
+        return false;
+    }
+    let mut member = unsafe { (*group).gr_mem };
+    while !member.is_null() && !unsafe { *member }.is_null() {
+        if unsafe { std::ffi::CStr::from_ptr(*member) } == name.as_c_str() {
+            return true;
+        }
+        member = unsafe { member.add(1) };
+    }
+    false
+}
+
+/// Serve the Management API on a Unix socket (mode 0660) until the process
+/// exits. Peers are identified via `SO_PEERCRED`; root, the agent user and
+/// members of the socket's group are trusted and need no token.
+#[cfg(unix)]
+pub async fn serve(settings: &Settings, app: Router) -> Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tower::ServiceExt;
+
+    let path = std::path::Path::new(&settings.server.socket_path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // A previous run may have left the socket behind
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {:?}", path))?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind admin socket {:?}", path))?;
+    if let Some(group) = &settings.server.socket_group {
+        let gid =
+            group_id(group).ok_or_else(|| anyhow::anyhow!("Unknown socket group '{}'", group))?;
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+
+    let own_uid = effective_uid();
+    let socket_gid = std::fs::metadata(path)?.gid();
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("Admin socket accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let Ok(cred) = stream.peer_cred() else {
+            tracing::warn!("Rejected admin socket connection without peer credentials");
+            continue;
+        };
+        let peer = LocalPeer {
+            uid: cred.uid(),
+            pid: cred.pid(),
+            trusted: cred.uid() == 0
+                || cred.uid() == own_uid
+                || cred.gid() == socket_gid
+                || in_group(cred.uid(), socket_gid),
+        };
+
+        let app = app.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |mut req: hyper::Request<_>| {
+                req.extensions_mut().insert(peer.clone());
+                app.clone().oneshot(req.map(Body::new))
+            });
+
+            if let Err(e) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Admin socket connection ended: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn serve(_settings: &Settings, _app: Router) -> Result<()> {
+    tracing::info!("Admin socket is not supported on this platform");
+    Ok(())
+}
+
+/// Client used by CLI subcommands to reach a running daemon.
+pub struct DaemonClient {
+    socket_path: String,
+}
+
+impl DaemonClient {
+    /// Returns `None` when no daemon is listening on the admin socket.
+    #[cfg(unix)]
+    pub async fn connect(settings: &Settings) -> Option<Self> {
+        tokio::net::UnixStream::connect(&settings.server.socket_path)
+            .await
+            .ok()?;
+        Some(Self {
+            socket_path: settings.server.socket_path.clone(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub async fn connect(_settings: &Settings) -> Option<Self> {
+        None
+    }
+
+    /// Send a JSON request and return the status and response body.
+    #[cfg(unix)]
+    pub async fn request(
+        &self,
+        method: axum::http::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<(StatusCode, String)> {
+        use hyper_util::rt::TokioIo;
+
+        let stream = tokio::net::UnixStream::connect(&self.socket_path)
+            .await
+            .context("Failed to connect to the agent socket")?;
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .context("Admin socket handshake failed")?;
+        tokio::spawn(conn);
+
+        let body = match body {
+            Some(json) => Body::from(serde_json::to_vec(&json)?),
+            None => Body::empty(),
+        };
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri(path)
+            .header("host", "localhost")
+            .header("content-type", "application/json")
+            .body(body)?;
+
+        let res = sender.send_request(req).await?;
+        let status = res.status();
+        let bytes = axum::body::to_bytes(Body::new(res.into_body()), usize::MAX).await?;
+        Ok((status, String::from_utf8_lossy(&bytes).to_string()))
+    }
+
+    #[cfg(not(unix))]
+    pub async fn request(
+        &self,
+        _method: axum::http::Method,
+        _path: &str,
+        _body: Option<serde_json::Value>,
+    ) -> Result<(StatusCode, String)> {
+        anyhow::bail!(
+            "Admin socket is not supported on this platform ({})",
+            self.socket_path
+        )
+    }
+
+    /// Follow a server-sent events endpoint, calling `on_event(event, data)`
+    /// for each event until it returns `false` or the stream ends.
+    #[cfg(unix)]
+    pub async fn stream_events(
+        &self,
+        path: &str,
+        mut on_event: impl FnMut(&str, &str) -> bool,
+    ) -> Result<()> {
+        use futures::StreamExt;
+        use hyper_util::rt::TokioIo;
+
+        let stream = tokio::net::UnixStream::connect(&self.socket_path)
+            .await
+            .context("Failed to connect to the agent socket")?;
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .context("Admin socket handshake failed")?;
+        tokio::spawn(conn);
+
+        let req = hyper::Request::builder()
+            .uri(path)
+            .header("host", "localhost")
+            .header("accept", "text/event-stream")
+            .body(Body::empty())?;
+        let res = sender.send_request(req).await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let bytes = axum::body::to_bytes(Body::new(res.into_body()), usize::MAX).await?;
+            anyhow::bail!("{}: {}", status, String::from_utf8_lossy(&bytes));
+        }
+
+        let mut body = Body::new(res.into_body()).into_data_stream();
+        let mut buffer = String::new();
+        while let Some(chunk) = body.next().await {
+            buffer.push_str(&String::from_utf8_lossy(&chunk?));
+            while let Some(end) = buffer.find("\n\n") {
+                let raw: String = buffer.drain(..end + 2).collect();
+                let mut event = "message";
+                let mut data = Vec::new();
+                for line in raw.lines() {
+                    if let Some(value) = line.strip_prefix("event:") {
+                        event = value.trim();
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data.push(value.strip_prefix(' ').unwrap_or(value));
+                    }
+                }
+                if !data.is_empty() && !on_event(event, &data.join("\n")) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub async fn stream_events(
+        &self,
+        _path: &str,
+        _on_event: impl FnMut(&str, &str) -> bool,
+    ) -> Result<()> {
+        anyhow::bail!(
+            "Admin socket is not supported on this platform ({})",
+            self.socket_path
+        )
+    }
+}
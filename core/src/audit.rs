@@ -0,0 +1,480 @@
+use crate::config::Settings;
+use crate::{errors::AppError, state::AppState};
+use anyhow::{Context, Result};
+use axum::{
+    body::Body,
+    extract::{Query, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+const REDACTED: &str = "[redacted]";
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Request bodies above this size are summarized by length only.
+const MAX_SUMMARY_BODY: usize = 256 * 1024;
+
+/// Who performed an action. Set by the auth middleware (API) or the CLI.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Actor {
+    pub kind: String, // "signature", "token", "socket", "cli" or "anonymous"
+    pub id: String,
+}
+
+impl Actor {
+    pub fn signature(scheme: &str) -> Self {
+        Self {
+            kind: "signature".into(),
+            id: format!("worker-secret ({})", scheme),
+        }
+    }
+
+    pub fn token(name: &str, id: &str) -> Self {
+        Self {
+            kind: "token".into(),
+            id: format!("{} ({})", name, id),
+        }
+    }
+
+    pub fn socket(uid: u32) -> Self {
+        Self {
+            kind: "socket".into(),
+            id: format!("uid:{}", uid),
+        }
+    }
+
+    pub fn anonymous() -> Self {
+        Self {
+            kind: "anonymous".into(),
+            id: "standalone".into(),
+        }
+    }
+
+    pub fn cli() -> Self {
+        let user = std::env::var("SUDO_USER")
+            .or_else(|_| std::env::var("USER"))
+            .unwrap_or_else(|_| "unknown".into());
+        Self {
+            kind: "cli".into(),
+            id: user,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: Actor,
+    pub action: String, // "POST /projects/web/deploy", or "cli <command>"
+    pub project: Option<String>,
+    pub request: Value, // Summary with secrets redacted
+    pub status: Option<u16>,
+    pub outcome: String, // "success" or "failure"
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// Hash over the previous hash and every field except `hash` itself.
+    fn compute_hash(&self) -> Result<String> {
+        let mut unhashed = self.clone();
+        unhashed.hash = String::new();
+        Ok(hex::encode(Sha256::digest(serde_json::to_vec(&unhashed)?)))
+    }
+}
+
+/// Append-only, hash-chained JSONL log. Each entry embeds the hash of its
+/// predecessor, so edits or deletions break the chain (`zexio audit verify`).
+/// Writers take an exclusive file lock, so the daemon and CLI can share it.
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Append an entry, filling in sequence number and hashes.
+    pub async fn append(
+        &self,
+        actor: Actor,
+        action: String,
+        project: Option<String>,
+        request: Value,
+        status: Option<u16>,
+        success: bool,
+    ) -> Result<()> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open audit log {:?}", path))?;
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+            }
+
+            file.lock()?;
+            let (seq, prev_hash) = match last_entry(&mut file)? {
+                Some(last) => (last.seq + 1, last.hash),
+                None => (1, GENESIS_HASH.to_string()),
+            };
+
+            let mut entry = AuditEntry {
+                seq,
+                timestamp: Utc::now(),
+                actor,
+                action,
+                project,
+                request,
+                status,
+                outcome: if success { "success" } else { "failure" }.into(),
+                prev_hash,
+                hash: String::new(),
+            };
+            entry.hash = entry.compute_hash()?;
+
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            file.sync_data()?;
+            file.unlock()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    pub async fn read_all(&self) -> Result<Vec<AuditEntry>> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || -> Result<Vec<AuditEntry>> {
+            let file = match std::fs::File::open(&path) {
+                Ok(f) => f,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e.into()),
+            };
+            let mut entries = Vec::new();
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    entries.push(serde_json::from_str(&line)?);
+                }
+            }
+            Ok(entries)
+        })
+        .await?
+    }
+
+    /// Check the hash chain. Returns the number of entries, or the first
+    /// sequence number where the chain is broken.
+    pub async fn verify(&self) -> Result<std::result::Result<usize, u64>> {
+        let entries = self.read_all().await?;
+        let mut prev = GENESIS_HASH.to_string();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.seq != i as u64 + 1
+                || entry.prev_hash != prev
+                || entry.compute_hash()? != entry.hash
+            {
+                return Ok(Err(entry.seq));
+            }
+            prev = entry.hash.clone();
+        }
+        Ok(Ok(entries.len()))
+    }
+}
+
+/// Read the last entry of the log (file is positioned anywhere).
+fn last_entry(file: &mut std::fs::File) -> Result<Option<AuditEntry>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len == 0 {
+        return Ok(None);
+    }
+
+    // Entries are small; read a growing tail until it holds a full line
+    let mut tail = 4096u64;
+    loop {
+        let start = len.saturating_sub(tail);
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = Vec::new();
+        std::io::Read::read_to_end(file, &mut buf)?;
+        let text = String::from_utf8_lossy(&buf);
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty()).rev();
+        if let Some(last) = lines.next() {
+            if start == 0 || lines.next().is_some() {
+                return Ok(Some(
+                    serde_json::from_str(last).context("Corrupt audit log tail")?,
+                ));
+            }
+        }
+        if start == 0 {
+            return Ok(None);
+        }
+        tail *= 4;
+    }
+}
+
+/// Replace secret values in a request body before it is logged. Env maps
+/// keep their keys; command strings are kept since they are what's audited.
+pub fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let lower = k.to_ascii_lowercase();
+                    let redacted = if matches!(
+                        lower.as_str(),
+                        "environment" | "sealed_environment" | "set" | "sealed_set"
+                    ) {
+                        match v {
+                            Value::Object(vars) => Value::Object(
+                                vars.keys()
+                                    .map(|key| (key.clone(), Value::String(REDACTED.into())))
+                                    .collect(),
+                            ),
+                            _ => Value::String(REDACTED.into()),
+                        }
+                    } else if matches!(
+                        lower.as_str(),
+                        "value" | "sealed_value" | "encrypted_env" | "sealed_env" | "file"
+                    ) || lower.contains("secret")
+                        || lower.contains("password")
+                        || lower.contains("token")
+                        || lower.ends_with("_key")
+                    {
+                        Value::String(REDACTED.into())
+                    } else {
+                        redact(v)
+                    };
+                    (k.clone(), redacted)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        Value::String(s) if s.len() > 1024 => Value::String(format!(
+            "{}... ({} bytes)",
+            &s[..s.floor_char_boundary(1024)],
+            s.len()
+        )),
+        other => other.clone(),
+    }
+}
+
+fn summarize(query: Option<&str>, body: &[u8]) -> Value {
+    let body = if body.is_empty() {
+        Value::Null
+    } else if body.len() > MAX_SUMMARY_BODY {
+        serde_json::json!({ "bytes": body.len() })
+    } else {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(json) => redact(&json),
+            Err(_) => serde_json::json!({ "bytes": body.len() }),
+        }
+    };
+
+    match query {
+        Some(q) => serde_json::json!({ "query": q, "body": body }),
+        None => serde_json::json!({ "body": body }),
+    }
+}
+
+/// Project id targeted by a request path, if any.
+fn project_of(path: &str) -> Option<String> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["projects", id, ..] => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Record state-changing calls (and secret reveals) on protected routes.
+/// Runs inside the auth middleware, which attaches the `Actor`.
+pub async fn audit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let reveal = request
+        .uri()
+        .query()
+        .map(|q| q.split('&').any(|p| p == "reveal=true"))
+        .unwrap_or(false);
+    let method = request.method().clone();
+    if (method == Method::GET || method == Method::HEAD) && !reveal {
+        return Ok(next.run(request).await);
+    }
+
+    let actor = request
+        .extensions()
+        .get::<Actor>()
+        .cloned()
+        .unwrap_or_else(Actor::anonymous);
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let path = parts.uri.path().to_string();
+    let project = project_of(&path);
+    let summary = summarize(parts.uri.query(), &bytes);
+
+    let request = Request::from_parts(parts, Body::from(bytes));
+    let response = next.run(request).await;
+    let status = response.status();
+
+    if let Err(e) = state
+        .audit
+        .append(
+            actor,
+            format!("{} {}", method, path),
+            project,
+            summary,
+            Some(status.as_u16()),
+            status.is_success(),
+        )
+        .await
+    {
+        tracing::error!("Failed to write audit log: {}", e);
+    }
+
+    Ok(response)
+}
+
+/// Record a CLI action. Failures to write are logged, not fatal.
+pub async fn record_cli(
+    settings: &Settings,
+    action: &str,
+    project: Option<String>,
+    request: Value,
+    success: bool,
+) {
+    let log = AuditLog::new(&settings.storage.audit_log_path);
+    if let Err(e) = log
+        .append(
+            Actor::cli(),
+            format!("cli {}", action),
+            project,
+            redact(&request),
+            None,
+            success,
+        )
+        .await
+    {
+        tracing::warn!("Could not write audit log: {}", e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,  // Matches actor kind or id (substring)
+    pub action: Option<String>, // Substring of the action
+    pub project: Option<String>,
+    pub outcome: Option<String>, // "success" or "failure"
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, e: &AuditEntry) -> bool {
+        self.actor
+            .as_ref()
+            .map(|a| e.actor.kind == *a || e.actor.id.contains(a.as_str()))
+            .unwrap_or(true)
+            && self
+                .action
+                .as_ref()
+                .map(|a| e.action.contains(a.as_str()))
+                .unwrap_or(true)
+            && self
+                .project
+                .as_ref()
+                .map(|p| e.project.as_ref() == Some(p))
+                .unwrap_or(true)
+            && self
+                .outcome
+                .as_ref()
+                .map(|o| e.outcome == *o)
+                .unwrap_or(true)
+            && self.since.map(|s| e.timestamp >= s).unwrap_or(true)
+            && self.until.map(|u| e.timestamp <= u).unwrap_or(true)
+    }
+}
+
+/// Matching entries, newest first (default limit 100).
+pub async fn list_audit_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, AppError> {
+    let entries = state.audit.read_all().await?;
+    let limit = query.limit.unwrap_or(100);
+
+    Ok(Json(
+        entries
+            .into_iter()
+            .rev()
+            .filter(|e| query.matches(e))
+            .take(limit)
+            .collect(),
+    ))
+}
+
+/// Matching entries as JSON lines, oldest first, for offline verification.
+pub async fn export_audit_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut out = String::new();
+    for entry in state
+        .audit
+        .read_all()
+        .await?
+        .iter()
+        .filter(|e| query.matches(e))
+    {
+        out.push_str(&serde_json::to_string(entry).map_err(anyhow::Error::from)?);
+        out.push('\n');
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"audit.jsonl\"",
+            ),
+        ],
+        out,
+    ))
+}
+
+pub async fn verify(settings: &Settings) -> Result<()> {
+    match AuditLog::new(&settings.storage.audit_log_path)
+        .verify()
+        .await?
+    {
+        Ok(count) => println!("✅ Audit log intact ({} entries)", count),
+        Err(seq) => {
+            println!("❌ Audit log chain broken at entry {}", seq);
+            anyhow::bail!("Audit log verification failed");
+        }
+    }
+    Ok(())
+}
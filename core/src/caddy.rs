@@ -1,100 +1,164 @@
 use crate::config::CaddySettings;
-use anyhow::{Context, Result};
-use std::process::Command;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use tracing::{info, warn};
+use crate::storage::project_port;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::info;
 
+/// `@id` prefix of the routes owned by the agent. Other routes are left alone.
+const ROUTE_ID_PREFIX: &str = "zexio-domain-";
+
+/// Optional ingress for custom domains: routes are pushed to a running Caddy
+/// through its JSON Admin API, and Caddy terminates TLS for them.
 pub struct Caddy {
     settings: CaddySettings,
+    http: reqwest::Client,
+    sync_lock: Mutex<()>, // One sync at a time; each one reads the live config
 }
 
 impl Caddy {
-    pub fn new(settings: CaddySettings) -> Self {
-        Self { settings }
+    pub fn new(settings: CaddySettings) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self {
+            settings,
+            http,
+            sync_lock: Mutex::new(()),
+        })
     }
 
-    pub fn add_domain(&self, domain: &str, _project_id: &str, port: u16) -> Result<()> {
-        let caddyfile_path = &self.settings.caddyfile_path;
-        
-        // Ensure file exists
-        if !std::path::Path::new(caddyfile_path).exists() {
-            fs::write(caddyfile_path, "")?;
-        }
+    /// Make Caddy's routes match `domains` (host -> project id): missing
+    /// routes are added, changed ones replaced and stale ones removed.
+    /// Routes already up to date are not touched, so this is safe to call
+    /// on every change.
+    pub async fn sync(&self, domains: &HashMap<String, String>) -> Result<()> {
+        let _guard = self.sync_lock.lock().await;
+        self.ensure_server().await?;
 
-        let config_block = format!(
-            "\n{} {{\n    reverse_proxy localhost:{}\n}}\n",
-            domain, port
-        );
+        let routes_path = format!("config/apps/http/servers/{}/routes", self.settings.server);
+        let current: HashMap<String, Value> = self
+            .get(&routes_path)
+            .await?
+            .and_then(|routes| routes.as_array().cloned())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|route| {
+                let id = route.get("@id")?.as_str()?.to_string();
+                id.starts_with(ROUTE_ID_PREFIX).then_some((id, route))
+            })
+            .collect();
 
-        let current_content = fs::read_to_string(caddyfile_path)?;
-        if current_content.contains(&format!("{} {{", domain)) {
-            info!("Domain {} already exists in Caddyfile, skipping", domain);
-            return Ok(());
+        let mut wanted = HashSet::new();
+        for (domain, project_id) in domains {
+            let id = route_id(domain);
+            let route = route(&id, domain, project_port(project_id));
+            match current.get(&id) {
+                Some(existing) if *existing == route => {}
+                Some(_) => {
+                    self.send(reqwest::Method::PATCH, &format!("id/{}", id), Some(&route))
+                        .await?;
+                    info!("Updated Caddy route for {}", domain);
+                }
+                None => {
+                    self.send(reqwest::Method::POST, &routes_path, Some(&route))
+                        .await?;
+                    info!("Added Caddy route for {}", domain);
+                }
+            }
+            wanted.insert(id);
         }
 
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open(caddyfile_path)
-            .context("Failed to open Caddyfile for appending")?;
-
-        file.write_all(config_block.as_bytes())
-            .context("Failed to write to Caddyfile")?;
+        for id in current.keys().filter(|id| !wanted.contains(*id)) {
+            self.send(reqwest::Method::DELETE, &format!("id/{}", id), None)
+                .await?;
+            info!(
+                "Removed Caddy route for {}",
+                id.trim_start_matches(ROUTE_ID_PREFIX)
+            );
+        }
 
         Ok(())
     }
 
-    pub fn remove_domain(&self, domain: &str) -> Result<()> {
-        let caddyfile_path = &self.settings.caddyfile_path;
-        if !std::path::Path::new(caddyfile_path).exists() {
+    /// Create the HTTP server holding our routes if Caddy doesn't have it.
+    /// The rest of the running config is kept as is.
+    async fn ensure_server(&self) -> Result<()> {
+        let server_path = format!("config/apps/http/servers/{}", self.settings.server);
+        if self.get(&server_path).await?.is_some() {
             return Ok(());
         }
 
-        let current_content = fs::read_to_string(caddyfile_path)?;
-        
-        // Naive parser: find domain block and remove it
-        // Format assumption: "domain.com {" ... "}"
-        // This is fragile but works for the predictable format we generate.
-        let start_marker = format!("{} {{", domain);
-        if let Some(start_idx) = current_content.find(&start_marker) {
-            // Find the closing brace for this block
-            // Since our block is simple: "\n    reverse_proxy localhost:PORT\n}\n"
-            // We search for the next "}\n" after start_idx
-            if let Some(end_idx_offset) = current_content[start_idx..].find("\n}\n") {
-                 let end_idx = start_idx + end_idx_offset + 3; // +3 for "\n}\n" length
-                 
-                 let new_content = format!("{}{}", &current_content[..start_idx], &current_content[end_idx..]);
-                 fs::write(caddyfile_path, new_content.trim())?;
-                 info!("Removed domain {} from Caddyfile", domain);
-            } else {
-                 warn!("Could not find closing brace for domain {}, skipping removal", domain);
-            }
-        } else {
-             info!("Domain {} not found in Caddyfile", domain);
-        }
+        let mut config = self.get("config/").await?.unwrap_or_else(|| json!({}));
+        let servers = ["apps", "http", "servers"]
+            .iter()
+            .try_fold(&mut config, |node, key| {
+                let object = node.as_object_mut()?;
+                Some(object.entry(*key).or_insert_with(|| json!({})))
+            })
+            .and_then(Value::as_object_mut)
+            .context("Unexpected Caddy config layout")?;
+        servers.insert(
+            self.settings.server.clone(),
+            json!({ "listen": self.settings.listen, "routes": [] }),
+        );
 
+        self.send(reqwest::Method::POST, "load", Some(&config))
+            .await?;
+        info!(
+            "Created Caddy server {} listening on {:?}",
+            self.settings.server, self.settings.listen
+        );
         Ok(())
     }
 
-    pub fn reload(&self) -> Result<()> {
-        info!("Reloading Caddy...");
-        let status = Command::new("caddy")
-            .arg("reload")
-            .arg("--config")
-            .arg(&self.settings.caddyfile_path) // This might be wrong if this is an included file.
-            // If `caddyfile_path` is the MAIN Caddyfile, this is correct.
-            // If it is an imported file, we should reload the MAIN Caddyfile or just `caddy reload` if running as service.
-            // Assuming `caddy reload` picks up the active config or adapter.
-            // A safer bet for systemd managed caddy is `systemctl reload caddy`, but we might not have sudo.
-            // Let's assume we run `caddy run` or `caddy start` manually or user has configured it.
-            // The prompt says "Run `caddy reload`".
-            .status()
-            .context("Failed to execute caddy reload")?;
-
-        if !status.success() {
-            anyhow::bail!("Caddy reload failed");
+    /// `None` when nothing is configured at `path`.
+    async fn get(&self, path: &str) -> Result<Option<Value>> {
+        let res = self
+            .http
+            .get(self.url(path))
+            .send()
+            .await
+            .context("Caddy Admin API unreachable")?;
+        if !res.status().is_success() {
+            bail!("Caddy GET /{} failed: {}", path, res.text().await?);
+        }
+        let value: Value = res.json().await?;
+        Ok((!value.is_null()).then_some(value))
+    }
+
+    async fn send(&self, method: reqwest::Method, path: &str, body: Option<&Value>) -> Result<()> {
+        let mut req = self.http.request(method.clone(), self.url(path));
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+        let res = req.send().await.context("Caddy Admin API unreachable")?;
+        if !res.status().is_success() {
+            bail!("Caddy {} /{} failed: {}", method, path, res.text().await?);
         }
-        
         Ok(())
     }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.settings.admin_api.trim_end_matches('/'), path)
+    }
+}
+
+fn route_id(domain: &str) -> String {
+    format!("{}{}", ROUTE_ID_PREFIX, domain)
+}
+
+/// Proxy `domain` to the project's app. Caddy obtains its certificate.
+fn route(id: &str, domain: &str, port: u16) -> Value {
+    json!({
+        "@id": id,
+        "match": [{ "host": [domain] }],
+        "handle": [{
+            "handler": "reverse_proxy",
+            "upstreams": [{ "dial": format!("127.0.0.1:{}", port) }]
+        }],
+        "terminal": true
+    })
 }
@@ -1,5 +1,6 @@
 use config::{Config, ConfigError};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
 
@@ -9,6 +10,14 @@ pub struct Settings {
     pub storage: StorageSettings,
     pub secrets: SecretsSettings,
     pub cloud: CloudSettings,
+    pub snapshots: SnapshotSettings,
+    pub security: SecuritySettings,
+    pub exec: ExecSettings,
+    pub databases: DatabaseSettings,
+    #[serde(default)]
+    pub mesh: MeshSettings,
+    pub acme: AcmeSettings,
+    pub caddy: CaddySettings,
     pub debug: bool,
 }
 
@@ -16,7 +25,16 @@ pub struct Settings {
 pub struct ServerSettings {
     pub port: u16,
     pub mesh_port: u16,
-    pub host: String, // Internal bind host
+    pub mesh_tls_port: u16, // HTTPS for custom domains, when ACME is enabled
+    pub host: String,       // Internal bind host
+    pub tcp_enabled: bool,
+    pub tls_enabled: bool,
+    pub tls_cert_path: String, // Self-signed on first boot if missing
+    pub tls_key_path: String,
+    pub tls_client_ca_path: Option<String>, // Require client certificates from this CA
+    pub socket_path: String,                // Admin socket for local CLI/GUI
+    pub socket_group: Option<String>,       // Group allowed to use the admin socket
+    pub cors_origins: Vec<String>,          // Browser origins allowed to call the TCP API
     pub public_hostname: Option<String>,
     pub public_ip: Option<String>,
 }
@@ -24,6 +42,11 @@ pub struct ServerSettings {
 #[derive(Debug, Deserialize, Clone)]
 pub struct StorageSettings {
     pub projects_dir: String,
+    pub volumes_dir: String,
+    pub snapshots_dir: String,
+    pub audit_log_path: String,
+    pub services_path: String, // Inventory of installed system services
+    pub certs_dir: String,     // Mesh certificates, keys encrypted per project
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -33,11 +56,198 @@ pub struct SecretsSettings {
     #[allow(dead_code)]
     pub start_port: Option<u16>, // Unused but kept for compatibility
     pub worker_secret_path: String,
-    pub master_key_path: String,
+    pub master_key_path: String, // Legacy single key, imported into the keyring
+    pub keyring_path: String,
+    pub data_keys_dir: String,
+    pub node_key_path: String, // X25519 keypair for sealed secrets
+    pub tokens_path: String,   // Hashed API tokens
     pub identity_path: String,
     pub provisioning_token_path: String,
 }
 
+/// Optional S3-compatible target (AWS, MinIO, R2...) for project snapshots.
+/// Uploads are enabled when `s3_endpoint` and `s3_bucket` are set.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SnapshotSettings {
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+}
+
+/// Request signing policy for the protected Management API.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecuritySettings {
+    pub signature_max_skew_secs: u64,
+    pub accept_legacy_signatures: bool, // Body-only `X-Signature` (transition period)
+}
+
+/// Policy for commands run via `/services/install` and `/services/uninstall`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExecSettings {
+    pub mode: ExecMode,
+    #[serde(default)]
+    pub allowlist: Vec<String>, // Templates, e.g. "apt-get install -y {package}"
+    #[serde(default)]
+    pub recipes: HashMap<String, Recipe>,
+    pub recipes_dir: String, // Catalog overrides, one `<name>.json` per recipe
+    pub run_as: Option<String>, // Unprivileged user for commands
+    pub sandbox: bool,       // Run in a transient systemd unit
+    pub timeout_secs: u64,
+    pub max_output_bytes: usize,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecMode {
+    Disabled,   // No remote commands at all
+    Restricted, // Recipes and allowlisted templates only
+    Open,       // Any command (previous behaviour)
+}
+
+/// Install/uninstall scripts defined by the node admin. They take precedence
+/// over the recipe catalog and apply to any distro.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Recipe {
+    pub install: String,
+    pub uninstall: Option<String>,
+    pub status: Option<String>,
+    pub version: Option<String>,
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+/// Local Postgres and Redis used for managed databases.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DatabaseSettings {
+    pub postgres_host: String, // Host put in DATABASE_URL
+    pub postgres_port: u16,
+    pub postgres_superuser: String, // OS and database user running psql
+    pub redis_url: String,          // This node's Redis (not the mesh registry's REDIS_URL)
+}
+
+/// Mesh proxy. Static routes come from the node config file and take
+/// precedence over the Redis registry and project domains.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MeshSettings {
+    #[serde(default)]
+    pub routes: HashMap<String, StaticRoute>, // Keyed by host
+    pub jwks_url: Option<String>, // Public keys of service tokens (RS256/EdDSA)
+    pub jwks_path: Option<String>, // Same from a local file; takes precedence
+    pub jwks_refresh_secs: u64,
+    pub audience: String, // Required `aud` of service tokens
+    pub access_log: AccessLogSettings,
+}
+
+/// Mesh access log file. Entries are also streamed over SSE.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AccessLogSettings {
+    pub enabled: bool, // Write the file
+    pub path: String,
+    pub max_bytes: u64,   // Rotate at this size, 0 to never rotate
+    pub keep: usize,      // Rotated files kept (`path.1` is the newest)
+    pub sample_rate: f64, // Share of requests logged; server errors always are
+}
+
+/// Certificates for custom domains, issued by an ACME CA.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AcmeSettings {
+    pub enabled: bool, // Also enables the mesh HTTPS listener
+    pub directory_url: String,
+    pub contact_email: Option<String>,
+    pub challenge: AcmeChallenge,
+    pub ca_bundle_path: Option<String>, // Trust the CA's own root (e.g. Pebble)
+    pub renew_before_days: i64,
+    pub account_path: String, // Account key, encrypted with the master key
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum AcmeChallenge {
+    #[serde(rename = "http-01")]
+    Http01, // Answered by the mesh on `mesh_port`, which must be reachable on port 80
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01, // Answered on `mesh_tls_port`, which must be reachable on port 443
+}
+
+/// Caddy as the ingress for custom domains, driven through its Admin API.
+/// An alternative to ACME in the agent; enable one or the other.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaddySettings {
+    pub enabled: bool,
+    pub admin_api: String,   // e.g. "http://localhost:2019"
+    pub server: String,      // HTTP server in Caddy's config holding our routes
+    pub listen: Vec<String>, // Used when the server has to be created
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StaticRoute {
+    pub upstreams: Vec<String>, // "host:port"
+    #[serde(default)]
+    pub org_id: String, // Tokens must belong to this org
+    #[serde(default)]
+    pub tls: bool, // Connect to the upstreams over TLS
+    #[serde(default)]
+    pub public: bool, // No service token required
+    pub allowed_sources: Option<Vec<String>>, // Source services that may call it; any if unset
+    #[serde(default)]
+    pub lb: LoadBalancing,
+    #[serde(default)]
+    pub resilience: Resilience,
+}
+
+/// How requests are spread over the upstreams of a route.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct LoadBalancing {
+    #[serde(default)]
+    pub strategy: Balance,
+    pub hash_header: Option<String>, // Consistent hash key; the client address if unset
+    pub health_check: Option<HealthCheck>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    LeastConn,
+    ConsistentHash, // Same key, same upstream while it stays healthy
+}
+
+/// Timeouts, retries and circuit breaking towards the upstreams of a route.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Resilience {
+    pub connect_timeout_ms: u64,
+    pub read_timeout_ms: u64, // Longest wait for the next bytes of the response
+    pub total_timeout_ms: u64, // Budget of a request including retries, 0 for none
+    pub retries: usize,       // Extra attempts on other upstreams
+    pub breaker_failures: u32, // Consecutive failures opening an upstream's circuit
+    pub breaker_open_secs: u64,
+}
+
+impl Default for Resilience {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 5_000,
+            read_timeout_ms: 60_000,
+            total_timeout_ms: 0,
+            retries: 2,
+            breaker_failures: 5,
+            breaker_open_secs: 30,
+        }
+    }
+}
+
+/// Active check run against every upstream of a route.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HealthCheck {
+    Tcp,                   // The port accepts connections
+    Http { path: String }, // GET answers 2xx or 3xx
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct CloudSettings {
     pub api_url: String,
@@ -45,6 +255,13 @@ pub struct CloudSettings {
     pub worker_id: Option<String>,
 }
 
+impl CloudSettings {
+    /// Managed by Zexio Cloud, as opposed to standalone.
+    pub fn is_cloud_mode(&self) -> bool {
+        self.token.is_some() && self.worker_id.is_some()
+    }
+}
+
 /// Get OS-specific config directory
 fn get_config_dir() -> PathBuf {
     // Check if running as root or with write permissions
@@ -158,7 +375,40 @@ impl Settings {
             // Start with default values
             .set_default("server.port", port as i64)?
             .set_default("server.mesh_port", mesh_port as i64)?
-            .set_default("server.host", "0.0.0.0")?
+            .set_default("server.mesh_tls_port", 8443)?
+            .set_default("server.host", "127.0.0.1")?
+            .set_default("server.tcp_enabled", true)?
+            .set_default("server.tls_enabled", false)?
+            .set_default(
+                "server.tls_cert_path",
+                config_dir
+                    .join("tls")
+                    .join("cert.pem")
+                    .to_string_lossy()
+                    .to_string(),
+            )?
+            .set_default(
+                "server.tls_key_path",
+                config_dir
+                    .join("tls")
+                    .join("key.pem")
+                    .to_string_lossy()
+                    .to_string(),
+            )?
+            .set_default("server.tls_client_ca_path", None::<String>)?
+            .set_default(
+                "server.socket_path",
+                config_dir.join("agent.sock").to_string_lossy().to_string(),
+            )?
+            .set_default("server.socket_group", None::<String>)?
+            .set_default(
+                "server.cors_origins",
+                vec![
+                    "tauri://localhost",
+                    "http://tauri.localhost",
+                    "https://tauri.localhost",
+                ],
+            )?
             .set_default("server.public_hostname", None::<String>)?
             .set_default("server.public_ip", None::<String>)?
             // Default Storage Paths (OS-specific)
@@ -166,6 +416,88 @@ impl Settings {
                 "storage.projects_dir",
                 data_dir.join("apps").to_string_lossy().to_string(),
             )?
+            .set_default(
+                "storage.volumes_dir",
+                data_dir.join("volumes").to_string_lossy().to_string(),
+            )?
+            .set_default(
+                "storage.snapshots_dir",
+                data_dir.join("snapshots").to_string_lossy().to_string(),
+            )?
+            .set_default(
+                "storage.audit_log_path",
+                data_dir.join("audit.log").to_string_lossy().to_string(),
+            )?
+            .set_default(
+                "storage.services_path",
+                data_dir.join("services.json").to_string_lossy().to_string(),
+            )?
+            .set_default(
+                "storage.certs_dir",
+                data_dir.join("certs").to_string_lossy().to_string(),
+            )?
+            // Snapshot offsite storage (disabled unless endpoint & bucket are set)
+            .set_default("snapshots.s3_endpoint", None::<String>)?
+            .set_default("snapshots.s3_bucket", None::<String>)?
+            .set_default("snapshots.s3_region", "us-east-1")?
+            .set_default("snapshots.s3_access_key", None::<String>)?
+            .set_default("snapshots.s3_secret_key", None::<String>)?
+            // Request signing: v2 signatures must be within this many seconds of our clock
+            .set_default("security.signature_max_skew_secs", 300)?
+            .set_default("security.accept_legacy_signatures", true)?
+            // Remote command execution policy
+            .set_default("exec.mode", "restricted")?
+            .set_default("exec.run_as", None::<String>)?
+            .set_default("exec.sandbox", false)?
+            .set_default("exec.timeout_secs", 600)?
+            .set_default("exec.max_output_bytes", 1024 * 1024)?
+            .set_default(
+                "exec.recipes_dir",
+                config_dir.join("recipes").to_string_lossy().to_string(),
+            )?
+            // Managed databases
+            .set_default("databases.postgres_host", "127.0.0.1")?
+            .set_default("databases.postgres_port", 5432)?
+            .set_default("databases.postgres_superuser", "postgres")?
+            .set_default("databases.redis_url", "redis://127.0.0.1:6379")?
+            // ACME certificates for custom domains (disabled by default)
+            .set_default("acme.enabled", false)?
+            .set_default(
+                "acme.directory_url",
+                "https://acme-v02.api.letsencrypt.org/directory",
+            )?
+            .set_default("acme.contact_email", None::<String>)?
+            .set_default("acme.challenge", "http-01")?
+            .set_default("acme.ca_bundle_path", None::<String>)?
+            .set_default("acme.renew_before_days", 30)?
+            .set_default(
+                "acme.account_path",
+                config_dir
+                    .join("acme_account.json")
+                    .to_string_lossy()
+                    .to_string(),
+            )?
+            // Mesh service tokens: HS256 with MESH_JWT_SECRET unless a JWKS is set
+            .set_default("mesh.jwks_url", None::<String>)?
+            .set_default("mesh.jwks_path", None::<String>)?
+            .set_default("mesh.jwks_refresh_secs", 300)?
+            .set_default("mesh.audience", "zexio-mesh")?
+            .set_default("mesh.access_log.enabled", true)?
+            .set_default(
+                "mesh.access_log.path",
+                data_dir
+                    .join("mesh-access.log")
+                    .to_string_lossy()
+                    .to_string(),
+            )?
+            .set_default("mesh.access_log.max_bytes", 50 * 1024 * 1024)?
+            .set_default("mesh.access_log.keep", 5)?
+            .set_default("mesh.access_log.sample_rate", 1.0)?
+            // Caddy ingress for custom domains (disabled by default)
+            .set_default("caddy.enabled", false)?
+            .set_default("caddy.admin_api", "http://localhost:2019")?
+            .set_default("caddy.server", "zexio")?
+            .set_default("caddy.listen", vec![":443"])?
             // Default Secrets Paths (OS-specific)
             .set_default(
                 "secrets.worker_secret_path",
@@ -178,6 +510,28 @@ impl Settings {
                 "secrets.master_key_path",
                 config_dir.join("master.key").to_string_lossy().to_string(),
             )?
+            .set_default(
+                "secrets.keyring_path",
+                config_dir
+                    .join("keyring.json")
+                    .to_string_lossy()
+                    .to_string(),
+            )?
+            .set_default(
+                "secrets.data_keys_dir",
+                config_dir.join("keys").to_string_lossy().to_string(),
+            )?
+            .set_default(
+                "secrets.node_key_path",
+                config_dir
+                    .join("node_key.json")
+                    .to_string_lossy()
+                    .to_string(),
+            )?
+            .set_default(
+                "secrets.tokens_path",
+                config_dir.join("tokens.json").to_string_lossy().to_string(),
+            )?
             .set_default(
                 "secrets.identity_path",
                 config_dir
@@ -195,7 +549,11 @@ impl Settings {
             // Default Cloud Settings
             .set_default("cloud.api_url", "https://api.zexio.io")?
             .set_default("debug", false)?
-            // Load config file if exists
+            // Load config files if they exist (node-wide, then working directory)
+            .add_source(
+                config::File::with_name(&config_dir.join("config").to_string_lossy())
+                    .required(false),
+            )
             .add_source(config::File::with_name(&format!("config/{}", env)).required(false))
             .add_source(config::Environment::with_prefix("ZEXIO").separator("__"));
 
@@ -1,53 +1,187 @@
+use crate::config::SecretsSettings;
 use aes_gcm::{
+    aead::stream::{DecryptorBE32, EncryptorBE32},
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
 use anyhow::{Context, Result};
 use hmac::{Hmac, Mac};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// Ciphertext layout: MAGIC | key id length (u8) | key id | nonce (12) | AES-256-GCM output.
+/// Ciphertexts without the header predate envelope encryption and are
+/// decrypted with the legacy key imported from `master.key`.
+const MAGIC: &[u8; 4] = b"ZXE1";
+const NONCE_LEN: usize = 12;
+const DATA_KEY_PREFIX: &str = "dk-";
+
+/// Streamed layout (large files): STREAM_MAGIC | key id length (u8) | key id |
+/// nonce prefix (7) | chunks of AES-256-GCM STREAM (BE32). Every chunk but
+/// the last holds STREAM_CHUNK plaintext bytes, so a short chunk ends it.
+const STREAM_MAGIC: &[u8; 4] = b"ZXS1";
+const STREAM_NONCE_LEN: usize = 7;
+const STREAM_CHUNK: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MasterKey {
+    pub key: String, // Hex-encoded 32 bytes
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Versioned master keys. Only `active` encrypts; the others are kept for
+/// decryption until a rotation completes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyring {
+    pub active: String,
+    pub keys: BTreeMap<String, MasterKey>,
+    #[serde(default)]
+    pub legacy: Option<String>, // Key for headerless ciphertexts
+}
+
+/// A per-project data key, stored wrapped by a master key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrappedDataKey {
+    pub id: String,
+    pub wrapped: String, // Hex-encoded master-key ciphertext
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
 
 #[derive(Clone)]
 pub struct Crypto {
-    key: Vec<u8>,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    keyring_path: PathBuf,
+    legacy_key_path: PathBuf,
+    data_keys_dir: PathBuf,
+    keyring: RwLock<(Keyring, Option<SystemTime>)>,
+    data_key_lock: Mutex<()>,
 }
 
 impl Crypto {
-    pub fn new(master_key_path: &str) -> Result<Self> {
-        // Auto-generate master key if not exists
-        let key_hex = if Path::new(master_key_path).exists() {
-            std::fs::read_to_string(master_key_path)
-                .with_context(|| format!("Failed to read master key from {}", master_key_path))?
-                .trim()
-                .to_string()
+    pub fn new(secrets: &SecretsSettings) -> Result<Self> {
+        let keyring_path = PathBuf::from(&secrets.keyring_path);
+        let legacy_key_path = PathBuf::from(&secrets.master_key_path);
+
+        let keyring = if keyring_path.exists() {
+            Self::read_keyring(&keyring_path)?
         } else {
-            // Generate new master key
-            let key = Self::generate_master_key();
-
-            // Create parent directory if not exists
-            if let Some(parent) = Path::new(master_key_path).parent() {
-                std::fs::create_dir_all(parent).with_context(|| {
-                    format!("Failed to create directory for master key: {:?}", parent)
-                })?;
-            }
+            let keyring = Self::bootstrap_keyring(&legacy_key_path)?;
+            Self::write_secret_file(&keyring_path, &serde_json::to_vec_pretty(&keyring)?)?;
+            tracing::info!("Initialized master keyring at {:?}", keyring_path);
+            keyring
+        };
+
+        let data_keys_dir = PathBuf::from(&secrets.data_keys_dir);
+        std::fs::create_dir_all(&data_keys_dir)
+            .with_context(|| format!("Failed to create data keys directory {:?}", data_keys_dir))?;
 
-            // Write key to file
-            std::fs::write(master_key_path, &key)
-                .with_context(|| format!("Failed to write master key to {}", master_key_path))?;
+        let mtime = Self::mtime(&keyring_path);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                keyring_path,
+                legacy_key_path,
+                data_keys_dir,
+                keyring: RwLock::new((keyring, mtime)),
+                data_key_lock: Mutex::new(()),
+            }),
+        })
+    }
 
-            tracing::info!("Generated new master key at {}", master_key_path);
-            key
+    /// First start with envelope encryption: import an existing `master.key`
+    /// as the legacy key, or generate a fresh keyring.
+    fn bootstrap_keyring(legacy_key_path: &Path) -> Result<Keyring> {
+        let (key, legacy) = if legacy_key_path.exists() {
+            let key_hex = std::fs::read_to_string(legacy_key_path)
+                .with_context(|| format!("Failed to read master key from {:?}", legacy_key_path))?
+                .trim()
+                .to_string();
+            Self::check_key(&key_hex)?;
+            (key_hex, Some("mk1".to_string()))
+        } else {
+            (Self::generate_master_key(), None)
         };
 
-        let key_bytes = hex::decode(&key_hex)
-            .with_context(|| "Master key must be 64 hex characters (32 bytes)")?;
+        let mut keys = BTreeMap::new();
+        keys.insert(
+            "mk1".to_string(),
+            MasterKey {
+                key,
+                created_at: chrono::Utc::now(),
+            },
+        );
+
+        Ok(Keyring {
+            active: "mk1".to_string(),
+            keys,
+            legacy,
+        })
+    }
+
+    fn read_keyring(path: &Path) -> Result<Keyring> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read keyring from {:?}", path))?;
+        let keyring: Keyring = serde_json::from_str(&json).context("Failed to parse keyring")?;
+
+        if !keyring.keys.contains_key(&keyring.active) {
+            anyhow::bail!("Keyring active key {} is missing", keyring.active);
+        }
+        for key in keyring.keys.values() {
+            Self::check_key(&key.key)?;
+        }
+        Ok(keyring)
+    }
 
+    fn check_key(key_hex: &str) -> Result<()> {
+        let key_bytes = hex::decode(key_hex)
+            .with_context(|| "Master key must be 64 hex characters (32 bytes)")?;
         if key_bytes.len() != 32 {
             anyhow::bail!("Master key must be exactly 32 bytes");
         }
+        Ok(())
+    }
+
+    fn mtime(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Write a file readable only by the agent user, atomically.
+    pub(crate) fn write_secret_file(path: &Path, contents: &[u8]) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+
+        // Per process, as the daemon and `zexio keys rotate` both write here
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let _ = std::fs::remove_file(&tmp);
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600); // Never readable by others, not even briefly
+        }
+        options
+            .open(&tmp)
+            .and_then(|mut file| {
+                file.write_all(contents)?;
+                file.sync_all()
+            })
+            .with_context(|| format!("Failed to write {:?}", tmp))?;
 
-        Ok(Self { key: key_bytes })
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {:?}", path))?;
+        Ok(())
     }
 
     /// Generate a new random 32-byte master key as hex string
@@ -57,33 +191,83 @@ impl Crypto {
         hex::encode(key)
     }
 
-    #[allow(dead_code)]
-    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
-        let mut nonce_bytes = [0u8; 12];
+    /// Current keyring, reloaded if another process (e.g. `zexio keys rotate`)
+    /// changed it on disk.
+    pub fn keyring(&self) -> Result<Keyring> {
+        let current = Self::mtime(&self.inner.keyring_path);
+        {
+            let guard = self
+                .inner
+                .keyring
+                .read()
+                .map_err(|_| anyhow::anyhow!("Keyring lock poisoned"))?;
+            if guard.1 == current {
+                return Ok(guard.0.clone());
+            }
+        }
+
+        let keyring = Self::read_keyring(&self.inner.keyring_path)?;
+        let mut guard = self
+            .inner
+            .keyring
+            .write()
+            .map_err(|_| anyhow::anyhow!("Keyring lock poisoned"))?;
+        *guard = (keyring.clone(), current);
+        tracing::info!("Reloaded master keyring (active: {})", keyring.active);
+        Ok(keyring)
+    }
+
+    fn save_keyring(&self, keyring: &Keyring) -> Result<()> {
+        Self::write_secret_file(
+            &self.inner.keyring_path,
+            &serde_json::to_vec_pretty(keyring)?,
+        )?;
+        let mut guard = self
+            .inner
+            .keyring
+            .write()
+            .map_err(|_| anyhow::anyhow!("Keyring lock poisoned"))?;
+        *guard = (keyring.clone(), Self::mtime(&self.inner.keyring_path));
+        Ok(())
+    }
+
+    fn master_key(keyring: &Keyring, id: &str) -> Result<Vec<u8>> {
+        let key = keyring
+            .keys
+            .get(id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown master key: {}", id))?;
+        Ok(hex::decode(&key.key)?)
+    }
+
+    fn seal(key_id: &str, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce_bytes = [0u8; NONCE_LEN];
         rand::thread_rng().fill(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
 
-        let cipher = Aes256Gcm::new_from_slice(&self.key)
+        let cipher = Aes256Gcm::new_from_slice(key)
             .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
 
         let ciphertext = cipher
             .encrypt(nonce, plaintext)
             .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
 
-        let mut result = nonce_bytes.to_vec();
+        let mut result = MAGIC.to_vec();
+        result.push(key_id.len() as u8);
+        result.extend_from_slice(key_id.as_bytes());
+        result.extend_from_slice(&nonce_bytes);
         result.extend_from_slice(&ciphertext);
         Ok(result)
     }
 
-    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
-        if data.len() < 12 {
+    fn open(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN {
             anyhow::bail!("Invalid encrypted data: too short");
         }
 
-        let (nonce_bytes, ciphertext) = data.split_at(12);
+        let (nonce_bytes, ciphertext) = data.split_at(NONCE_LEN);
         let nonce = Nonce::from_slice(nonce_bytes);
 
-        let cipher = Aes256Gcm::new_from_slice(&self.key)
+        let cipher = Aes256Gcm::new_from_slice(key)
             .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
 
         cipher
@@ -91,6 +275,328 @@ impl Crypto {
             .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
     }
 
+    /// Split a ciphertext into its key id and nonce+ciphertext body.
+    fn parse_header(data: &[u8]) -> Option<(&str, &[u8])> {
+        let rest = data.strip_prefix(MAGIC.as_slice())?;
+        let (&len, rest) = rest.split_first()?;
+        if rest.len() < len as usize {
+            return None;
+        }
+        let (id, body) = rest.split_at(len as usize);
+        Some((std::str::from_utf8(id).ok()?, body))
+    }
+
+    /// Key id a ciphertext was produced with (`None` for legacy ciphertexts).
+    pub fn key_id(data: &[u8]) -> Option<String> {
+        Self::parse_header(data).map(|(id, _)| id.to_string())
+    }
+
+    /// Whether a ciphertext is encrypted with a project data key.
+    pub fn is_data_key_ciphertext(data: &[u8]) -> bool {
+        Self::key_id(data)
+            .map(|id| id.starts_with(DATA_KEY_PREFIX))
+            .unwrap_or(false)
+    }
+
+    /// Encrypt node-level data with the active master key.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let keyring = self.keyring()?;
+        let key = Self::master_key(&keyring, &keyring.active)?;
+        Self::seal(&keyring.active, &key, plaintext)
+    }
+
+    /// Decrypt data encrypted with a master key (current, retiring or legacy).
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let keyring = self.keyring()?;
+
+        if let Some((id, body)) = Self::parse_header(data) {
+            if let Ok(key) = Self::master_key(&keyring, id) {
+                if let Ok(plaintext) = Self::open(&key, body) {
+                    return Ok(plaintext);
+                }
+            }
+        }
+
+        // Headerless (pre-envelope) ciphertext
+        let legacy = keyring
+            .legacy
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Decryption failed: unknown key"))?;
+        Self::open(&Self::master_key(&keyring, legacy)?, data)
+    }
+
+    fn data_key_path(&self, project_id: &str) -> PathBuf {
+        self.inner
+            .data_keys_dir
+            .join(format!("{}.json", project_id))
+    }
+
+    fn read_data_key(&self, project_id: &str) -> Result<Option<WrappedDataKey>> {
+        let path = self.data_key_path(project_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read data key {:?}", path))?;
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    fn unwrap_data_key(&self, wrapped: &WrappedDataKey) -> Result<Vec<u8>> {
+        self.decrypt(&hex::decode(&wrapped.wrapped)?)
+            .with_context(|| format!("Failed to unwrap data key {}", wrapped.id))
+    }
+
+    /// The project's data key, created on first use.
+    fn data_key(&self, project_id: &str) -> Result<(String, Vec<u8>)> {
+        if let Some(wrapped) = self.read_data_key(project_id)? {
+            return Ok((wrapped.id.clone(), self.unwrap_data_key(&wrapped)?));
+        }
+
+        let _guard = self
+            .inner
+            .data_key_lock
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Data key lock poisoned"))?;
+
+        // Another task may have created it while we waited
+        if let Some(wrapped) = self.read_data_key(project_id)? {
+            return Ok((wrapped.id.clone(), self.unwrap_data_key(&wrapped)?));
+        }
+
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill(&mut key);
+        let mut id_bytes = [0u8; 4];
+        rand::thread_rng().fill(&mut id_bytes);
+
+        let wrapped = WrappedDataKey {
+            id: format!("{}{}", DATA_KEY_PREFIX, hex::encode(id_bytes)),
+            wrapped: hex::encode(self.encrypt(&key)?),
+            created_at: chrono::Utc::now(),
+        };
+        Self::write_secret_file(
+            &self.data_key_path(project_id),
+            &serde_json::to_vec_pretty(&wrapped)?,
+        )?;
+        tracing::info!("Created data key {} for project {}", wrapped.id, project_id);
+
+        Ok((wrapped.id, key.to_vec()))
+    }
+
+    /// Encrypt project data with the project's data key.
+    pub fn encrypt_for(&self, project_id: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let (id, key) = self.data_key(project_id)?;
+        Self::seal(&id, &key, plaintext)
+    }
+
+    /// Decrypt project data. Accepts data-key, master-key and legacy ciphertexts.
+    pub fn decrypt_for(&self, project_id: &str, data: &[u8]) -> Result<Vec<u8>> {
+        match Self::parse_header(data) {
+            Some((id, body)) if id.starts_with(DATA_KEY_PREFIX) => {
+                let wrapped = self
+                    .read_data_key(project_id)?
+                    .ok_or_else(|| anyhow::anyhow!("No data key for project {}", project_id))?;
+                if wrapped.id != id {
+                    anyhow::bail!("Ciphertext key {} does not belong to {}", id, project_id);
+                }
+                Self::open(&self.unwrap_data_key(&wrapped)?, body)
+            }
+            _ => self.decrypt(data),
+        }
+    }
+
+    /// Encrypt project data of any size as it is written to `writer`.
+    /// Call `finish` on the result once everything is written.
+    pub fn encrypt_stream_for<W: Write>(
+        &self,
+        project_id: &str,
+        mut writer: W,
+    ) -> Result<StreamEncryptor<W>> {
+        let (id, key) = self.data_key(project_id)?;
+        let mut nonce = [0u8; STREAM_NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
+
+        writer.write_all(STREAM_MAGIC)?;
+        writer.write_all(&[id.len() as u8])?;
+        writer.write_all(id.as_bytes())?;
+        writer.write_all(&nonce)?;
+
+        Ok(StreamEncryptor {
+            writer,
+            encryptor: Some(EncryptorBE32::from_aead(cipher, nonce.as_slice().into())),
+            buffer: Vec::with_capacity(STREAM_CHUNK),
+        })
+    }
+
+    /// Decrypt data produced by `encrypt_stream_for` as it is read.
+    pub fn decrypt_stream_for<R: Read>(
+        &self,
+        project_id: &str,
+        mut reader: R,
+    ) -> Result<StreamDecryptor<R>> {
+        let mut magic = [0u8; 5];
+        reader.read_exact(&mut magic)?;
+        if magic[..4] != STREAM_MAGIC[..] {
+            anyhow::bail!("Not a streamed ciphertext");
+        }
+        let mut id = vec![0u8; magic[4] as usize];
+        reader.read_exact(&mut id)?;
+        let mut nonce = [0u8; STREAM_NONCE_LEN];
+        reader.read_exact(&mut nonce)?;
+
+        let wrapped = self
+            .read_data_key(project_id)?
+            .ok_or_else(|| anyhow::anyhow!("No data key for project {}", project_id))?;
+        if wrapped.id.as_bytes() != id.as_slice() {
+            anyhow::bail!("Ciphertext key does not belong to {}", project_id);
+        }
+        let cipher = Aes256Gcm::new_from_slice(&self.unwrap_data_key(&wrapped)?)
+            .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
+
+        Ok(StreamDecryptor {
+            reader,
+            decryptor: Some(DecryptorBE32::from_aead(cipher, nonce.as_slice().into())),
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Whether a file starts like a `encrypt_stream_for` ciphertext.
+    pub fn is_stream_ciphertext(prefix: &[u8]) -> bool {
+        prefix.starts_with(STREAM_MAGIC)
+    }
+
+    /// Destroy a project's data key (crypto-shredding its encrypted data).
+    pub fn delete_data_key(&self, project_id: &str) -> Result<()> {
+        let path = self.data_key_path(project_id);
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete data key {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    /// Project ids that have a data key.
+    pub fn data_key_projects(&self) -> Result<Vec<String>> {
+        let mut projects = Vec::new();
+        for entry in std::fs::read_dir(&self.inner.data_keys_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                if let Some(stem) = path.file_stem() {
+                    projects.push(stem.to_string_lossy().to_string());
+                }
+            }
+        }
+        Ok(projects)
+    }
+
+    /// Master key id wrapping a project's data key.
+    pub fn data_key_wrapper(&self, project_id: &str) -> Result<Option<String>> {
+        Ok(self
+            .read_data_key(project_id)?
+            .and_then(|w| hex::decode(&w.wrapped).ok())
+            .and_then(|bytes| Self::key_id(&bytes)))
+    }
+
+    /// Start (or resume) a rotation: add a new active master key while keeping
+    /// the previous ones for decryption. Returns the active key id.
+    pub fn begin_rotation(&self) -> Result<String> {
+        let mut keyring = self.keyring()?;
+
+        if keyring.keys.len() > 1 {
+            tracing::info!(
+                "Resuming unfinished rotation to {} ({} keys in keyring)",
+                keyring.active,
+                keyring.keys.len()
+            );
+            return Ok(keyring.active);
+        }
+
+        let next = keyring
+            .keys
+            .keys()
+            .filter_map(|id| id.strip_prefix("mk").and_then(|n| n.parse::<u32>().ok()))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let id = format!("mk{}", next);
+
+        keyring.keys.insert(
+            id.clone(),
+            MasterKey {
+                key: Self::generate_master_key(),
+                created_at: chrono::Utc::now(),
+            },
+        );
+        keyring.active = id.clone();
+        self.save_keyring(&keyring)?;
+
+        Ok(id)
+    }
+
+    /// Re-wrap every data key not yet wrapped by the active master key.
+    pub fn rewrap_data_keys(&self) -> Result<usize> {
+        let active = self.keyring()?.active;
+        let mut rewrapped = 0;
+
+        for project_id in self.data_key_projects()? {
+            let Some(mut wrapped) = self.read_data_key(&project_id)? else {
+                continue;
+            };
+            if self.data_key_wrapper(&project_id)?.as_deref() == Some(active.as_str()) {
+                continue;
+            }
+
+            let key = self.unwrap_data_key(&wrapped)?;
+            wrapped.wrapped = hex::encode(self.encrypt(&key)?);
+            Self::write_secret_file(
+                &self.data_key_path(&project_id),
+                &serde_json::to_vec_pretty(&wrapped)?,
+            )?;
+            rewrapped += 1;
+        }
+
+        Ok(rewrapped)
+    }
+
+    /// Drop every master key except the active one. Fails if any data key is
+    /// still wrapped by an old key. Returns the retired key ids.
+    pub fn finish_rotation(&self) -> Result<Vec<String>> {
+        // Catch data keys created by the daemon while the rotation was running
+        self.rewrap_data_keys()?;
+
+        let mut keyring = self.keyring()?;
+        for project_id in self.data_key_projects()? {
+            if self.data_key_wrapper(&project_id)?.as_deref() != Some(keyring.active.as_str()) {
+                anyhow::bail!(
+                    "Data key of {} is not wrapped by {}; keeping old keys",
+                    project_id,
+                    keyring.active
+                );
+            }
+        }
+
+        let active = keyring.active.clone();
+        let retired: Vec<String> = keyring
+            .keys
+            .keys()
+            .filter(|id| **id != active)
+            .cloned()
+            .collect();
+        keyring.keys.retain(|id, _| *id == active);
+        let had_legacy = keyring.legacy.take().is_some();
+        self.save_keyring(&keyring)?;
+
+        // The legacy key file now holds a retired key
+        if had_legacy && self.inner.legacy_key_path.exists() {
+            std::fs::remove_file(&self.inner.legacy_key_path)
+                .with_context(|| format!("Failed to remove {:?}", self.inner.legacy_key_path))?;
+        }
+
+        Ok(retired)
+    }
+
     // HMAC-SHA256 verification
     pub fn verify_signature(secret: &str, body: &[u8], signature_hex: &str) -> bool {
         type HmacSha256 = Hmac<Sha256>;
@@ -102,12 +608,107 @@ impl Crypto {
 
         mac.update(body);
 
-        let expected_bytes = mac.finalize().into_bytes();
-        let expected_hex = hex::encode(expected_bytes);
-
         // Remove "sha256=" prefix if present
         let clean_sig = signature_hex.trim_start_matches("sha256=");
+        let Ok(signature) = hex::decode(clean_sig) else {
+            return false;
+        };
+
+        // Constant-time comparison
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// Writer side of a streamed ciphertext (see `Crypto::encrypt_stream_for`).
+pub struct StreamEncryptor<W: Write> {
+    writer: W,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+    /// Seal the last chunk and return the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        let encryptor = self
+            .encryptor
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Stream already finished"))?;
+        let chunk = encryptor
+            .encrypt_last(self.buffer.as_slice())
+            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+        self.writer.write_all(&chunk)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let n = data.len().min(STREAM_CHUNK - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..n]);
+
+        // Seal full chunks right away so the last one is always short
+        if self.buffer.len() == STREAM_CHUNK {
+            let encryptor = self
+                .encryptor
+                .as_mut()
+                .ok_or_else(|| std::io::Error::other("Stream already finished"))?;
+            let chunk = encryptor
+                .encrypt_next(self.buffer.as_slice())
+                .map_err(|e| std::io::Error::other(format!("Encryption failed: {}", e)))?;
+            self.writer.write_all(&chunk)?;
+            self.buffer.clear();
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reader side of a streamed ciphertext (see `Crypto::decrypt_stream_for`).
+/// Fails on tampered, reordered or truncated chunks.
+pub struct StreamDecryptor<R: Read> {
+    reader: R,
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    chunk: Vec<u8>, // Decrypted chunk being read out
+    pos: usize,
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            let Some(mut decryptor) = self.decryptor.take() else {
+                return Ok(0);
+            };
+
+            let mut sealed = vec![0u8; STREAM_CHUNK + TAG_LEN];
+            let mut len = 0;
+            while len < sealed.len() {
+                match self.reader.read(&mut sealed[len..])? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            sealed.truncate(len);
+
+            let plain = if len == STREAM_CHUNK + TAG_LEN {
+                let plain = decryptor.decrypt_next(sealed.as_slice());
+                self.decryptor = Some(decryptor);
+                plain
+            } else {
+                decryptor.decrypt_last(sealed.as_slice())
+            };
+            self.chunk = plain.map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "Decryption failed")
+            })?;
+            self.pos = 0;
+        }
 
-        expected_hex == clean_sig
+        let n = out.len().min(self.chunk.len() - self.pos);
+        out[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
     }
 }
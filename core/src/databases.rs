@@ -0,0 +1,595 @@
+use crate::config::Settings;
+use crate::env_vars;
+use crate::storage::{DatabaseBinding, DatabaseEngine, ProjectConfig};
+use crate::{errors::AppError, state::AppState};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && name
+            .chars()
+            .next()
+            .map(|c| c.is_ascii_lowercase())
+            .unwrap_or(false);
+
+    if !valid {
+        return Err(AppError::BadRequest(
+            "Database name must be 1-32 chars of [a-z0-9_] starting with a letter".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Node-wide name of a project's database/user, e.g. `zx_web_main`. Only
+/// `[a-z0-9_]`, so it is safe to quote into SQL and Redis ACL rules.
+fn object_name(project_id: &str, name: &str) -> String {
+    let project: String = project_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut object = format!("zx_{}_{}", project, name);
+    object.truncate(63); // Postgres identifier limit
+    object
+}
+
+fn generate_password() -> String {
+    use rand::Rng;
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Keys a Redis user may access: `<name>:*`.
+fn key_prefix(binding: &DatabaseBinding) -> String {
+    format!("{}:", binding.name)
+}
+
+fn decrypt_password(
+    state: &AppState,
+    project_id: &str,
+    binding: &DatabaseBinding,
+) -> Result<String> {
+    let bytes = state
+        .crypto
+        .decrypt_for(project_id, &hex::decode(&binding.password)?)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Client of this node's Redis (`databases.redis_url`).
+fn node_redis(settings: &Settings) -> Result<redis::Client> {
+    redis::Client::open(settings.databases.redis_url.as_str())
+        .context("Invalid databases.redis_url")
+}
+
+fn redis_address(settings: &Settings) -> Result<(String, u16)> {
+    Ok(
+        match node_redis(settings)?.get_connection_info().addr.clone() {
+            redis::ConnectionAddr::Tcp(host, port) => (host, port),
+            redis::ConnectionAddr::TcpTls { host, port, .. } => (host, port),
+            _ => ("127.0.0.1".to_string(), 6379),
+        },
+    )
+}
+
+/// Connection variables of the project's bindings, e.g.
+/// `DATABASE_URL=postgres://...` or `REDIS_URL` plus `REDIS_KEY_PREFIX`.
+pub fn env_vars(state: &AppState, config: &ProjectConfig) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for binding in &config.databases {
+        let password = decrypt_password(state, &config.id, binding)?;
+        match binding.engine {
+            DatabaseEngine::Postgres => {
+                let db = &state.settings.databases;
+                vars.push((
+                    binding.env_key.clone(),
+                    format!(
+                        "postgres://{}:{}@{}:{}/{}",
+                        binding.username,
+                        password,
+                        db.postgres_host,
+                        db.postgres_port,
+                        binding.name
+                    ),
+                ));
+            }
+            DatabaseEngine::Redis => {
+                let (host, port) = redis_address(&state.settings)?;
+                vars.push((
+                    binding.env_key.clone(),
+                    format!(
+                        "redis://{}:{}@{}:{}",
+                        binding.username, password, host, port
+                    ),
+                ));
+                let prefix_key = format!("{}_KEY_PREFIX", binding.env_key.trim_end_matches("_URL"));
+                vars.push((prefix_key, key_prefix(binding)));
+            }
+        }
+    }
+    Ok(vars)
+}
+
+/// Run SQL statements as the Postgres superuser, returning the output.
+async fn psql(settings: &Settings, statements: &[String]) -> Result<String> {
+    let superuser = &settings.databases.postgres_superuser;
+    let mut cmd = Command::new("runuser");
+    cmd.args(["-u", superuser, "--", "psql", "-X", "-q", "-t", "-A"])
+        .args(["-v", "ON_ERROR_STOP=1", "-d", "postgres"]);
+    for statement in statements {
+        cmd.arg("-c").arg(statement);
+    }
+
+    let output = cmd.output().await.context("Failed to run psql")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "psql failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Create the role and its database. Only the role can connect to the
+/// database. Existing ones are left alone: no binding owns them (the caller
+/// checked), so they hold data of a deleted project.
+async fn provision_postgres(settings: &Settings, name: &str, password: &str) -> Result<()> {
+    let exists = psql(
+        settings,
+        &[format!(
+            "SELECT (SELECT count(*) FROM pg_roles WHERE rolname = '{0}') \
+             + (SELECT count(*) FROM pg_database WHERE datname = '{0}')",
+            name
+        )],
+    )
+    .await?;
+    if exists != "0" {
+        anyhow::bail!(
+            "Postgres role or database {} already exists without a binding (left by a deleted project?); drop it to reuse the name",
+            name
+        );
+    }
+
+    let statements = [
+        format!("CREATE ROLE \"{}\" LOGIN PASSWORD '{}'", name, password),
+        format!("CREATE DATABASE \"{}\" OWNER \"{}\"", name, name),
+        format!("REVOKE ALL ON DATABASE \"{}\" FROM PUBLIC", name),
+        format!("GRANT ALL ON DATABASE \"{}\" TO \"{}\"", name, name),
+    ];
+    if let Err(e) = psql(settings, &statements).await {
+        // Both were just created by us, so a retry can start over
+        let cleanup = [
+            format!("DROP DATABASE IF EXISTS \"{}\"", name),
+            format!("DROP ROLE IF EXISTS \"{}\"", name),
+        ];
+        if let Err(cleanup_err) = psql(settings, &cleanup).await {
+            warn!("Failed to clean up Postgres {}: {}", name, cleanup_err);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Disable the role and close its sessions. Data is only dropped on `purge`.
+async fn revoke_postgres(settings: &Settings, name: &str, purge: bool) -> Result<()> {
+    let mut statements = vec![
+        format!("ALTER ROLE \"{}\" NOLOGIN", name),
+        format!(
+            "SELECT count(pg_terminate_backend(pid)) FROM pg_stat_activity WHERE usename = '{}'",
+            name
+        ),
+    ];
+    if purge {
+        statements.push(format!("DROP DATABASE IF EXISTS \"{}\"", name));
+        statements.push(format!("DROP ROLE IF EXISTS \"{}\"", name));
+    }
+    psql(settings, &statements).await?;
+    Ok(())
+}
+
+/// Persist ACL changes across Redis restarts (aclfile, or redis.conf).
+async fn persist_redis_acl(con: &mut redis::aio::MultiplexedConnection) {
+    let saved: redis::RedisResult<()> = redis::cmd("ACL").arg("SAVE").query_async(con).await;
+    if saved.is_ok() {
+        return;
+    }
+    let rewritten: redis::RedisResult<()> =
+        redis::cmd("CONFIG").arg("REWRITE").query_async(con).await;
+    if let Err(e) = rewritten {
+        warn!(
+            "Redis ACL changes are not persisted (no aclfile, CONFIG REWRITE failed: {})",
+            e
+        );
+    }
+}
+
+/// Whether any key matches `pattern`.
+async fn redis_has_keys(
+    con: &mut redis::aio::MultiplexedConnection,
+    pattern: &str,
+) -> Result<bool> {
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(500)
+            .query_async(con)
+            .await?;
+        if !keys.is_empty() {
+            return Ok(true);
+        }
+        if next == 0 {
+            return Ok(false);
+        }
+        cursor = next;
+    }
+}
+
+/// Create an ACL user limited to its own key prefix, without admin or
+/// dangerous commands. Like Postgres, an existing user or existing keys
+/// under the prefix are never taken over.
+async fn provision_redis(
+    settings: &Settings,
+    binding: &DatabaseBinding,
+    password: &str,
+) -> Result<()> {
+    let mut con = node_redis(settings)?
+        .get_multiplexed_async_connection()
+        .await
+        .context("Failed to connect to Redis")?;
+    let prefix = key_prefix(binding);
+
+    let user: redis::Value = redis::cmd("ACL")
+        .arg("GETUSER")
+        .arg(&binding.username)
+        .query_async(&mut con)
+        .await
+        .context("ACL GETUSER failed")?;
+    if user != redis::Value::Nil || redis_has_keys(&mut con, &format!("{}*", prefix)).await? {
+        anyhow::bail!(
+            "Redis user {} or keys under {} already exist without a binding (left by a deleted project?); delete them to reuse the name",
+            binding.username,
+            prefix
+        );
+    }
+
+    let _: () = redis::cmd("ACL")
+        .arg("SETUSER")
+        .arg(&binding.username)
+        .arg("reset")
+        .arg("on")
+        .arg(format!(">{}", password))
+        .arg(format!("~{}*", prefix))
+        .arg(format!("&{}*", prefix))
+        .arg("+@all")
+        .arg("-@admin")
+        .arg("-@dangerous")
+        .query_async(&mut con)
+        .await
+        .context("ACL SETUSER failed")?;
+    persist_redis_acl(&mut con).await;
+    Ok(())
+}
+
+/// Delete the ACL user and disconnect it. Keys are only deleted on `purge`.
+async fn revoke_redis(settings: &Settings, binding: &DatabaseBinding, purge: bool) -> Result<()> {
+    let mut con = node_redis(settings)?
+        .get_multiplexed_async_connection()
+        .await
+        .context("Failed to connect to Redis")?;
+    let _: () = redis::cmd("ACL")
+        .arg("DELUSER")
+        .arg(&binding.username)
+        .query_async(&mut con)
+        .await
+        .context("ACL DELUSER failed")?;
+    let killed: redis::RedisResult<()> = redis::cmd("CLIENT")
+        .arg("KILL")
+        .arg("USER")
+        .arg(&binding.username)
+        .query_async(&mut con)
+        .await;
+    if let Err(e) = killed {
+        warn!(
+            "Failed to disconnect Redis user {}: {}",
+            binding.username, e
+        );
+    }
+    persist_redis_acl(&mut con).await;
+
+    if purge {
+        let pattern = format!("{}*", key_prefix(binding));
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(500)
+                .query_async(&mut con)
+                .await?;
+            if !keys.is_empty() {
+                let _: () = redis::cmd("UNLINK")
+                    .arg(&keys)
+                    .query_async(&mut con)
+                    .await?;
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    }
+    Ok(())
+}
+
+async fn revoke(state: &AppState, binding: &DatabaseBinding, purge: bool) -> Result<()> {
+    match binding.engine {
+        DatabaseEngine::Postgres => revoke_postgres(&state.settings, &binding.name, purge).await,
+        DatabaseEngine::Redis => revoke_redis(&state.settings, binding, purge).await,
+    }
+}
+
+/// Revoke all bindings of a project that is being deleted.
+pub async fn revoke_all(state: &AppState, config: &ProjectConfig, purge: bool) {
+    for binding in &config.databases {
+        match revoke(state, binding, purge).await {
+            Ok(()) => info!("Revoked {:?} access {}", binding.engine, binding.name),
+            Err(e) => warn!(
+                "Failed to revoke {:?} access {}: {}",
+                binding.engine, binding.name, e
+            ),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BindingInfo {
+    pub engine: DatabaseEngine,
+    pub name: String,
+    pub username: String,
+    pub project_id: String,
+    pub env_key: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl BindingInfo {
+    fn new(project_id: &str, binding: &DatabaseBinding) -> Self {
+        Self {
+            engine: binding.engine,
+            name: binding.name.clone(),
+            username: binding.username.clone(),
+            project_id: project_id.to_string(),
+            env_key: binding.env_key.clone(),
+            created_at: binding.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateBindingRequest {
+    pub project_id: String,
+    pub name: Option<String>,    // Default "main"
+    pub env_key: Option<String>, // Default DATABASE_URL / REDIS_URL
+}
+
+#[derive(Deserialize)]
+pub struct DeleteBindingQuery {
+    #[serde(default)]
+    pub purge: bool,
+}
+
+async fn list(state: &AppState, engine: DatabaseEngine) -> Result<Vec<BindingInfo>, AppError> {
+    let projects = state
+        .store
+        .list()
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+    Ok(projects
+        .iter()
+        .flat_map(|p| {
+            p.databases
+                .iter()
+                .filter(|b| b.engine == engine)
+                .map(|b| BindingInfo::new(&p.id, b))
+        })
+        .collect())
+}
+
+async fn create(
+    state: &AppState,
+    engine: DatabaseEngine,
+    payload: CreateBindingRequest,
+) -> Result<impl IntoResponse, AppError> {
+    let name = payload.name.unwrap_or_else(|| "main".to_string());
+    validate_name(&name)?;
+    let env_key = payload.env_key.unwrap_or_else(|| match engine {
+        DatabaseEngine::Postgres => "DATABASE_URL".to_string(),
+        DatabaseEngine::Redis => "REDIS_URL".to_string(),
+    });
+    env_vars::validate_key(&env_key)?;
+
+    let _lock = state
+        .store
+        .lock(&payload.project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = state
+        .store
+        .read(&payload.project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    if config.databases.iter().any(|b| b.env_key == env_key) {
+        return Err(AppError::BadRequest(format!(
+            "{} is already bound in this project",
+            env_key
+        )));
+    }
+
+    let object = object_name(&config.id, &name);
+    let taken = state
+        .store
+        .list()
+        .await
+        .map_err(|_| AppError::InternalServerError)?
+        .iter()
+        .flat_map(|p| p.databases.iter())
+        .any(|b| b.engine == engine && b.name == object);
+    if taken {
+        return Err(AppError::BadRequest(format!("{} already exists", object)));
+    }
+
+    let password = generate_password();
+    let binding = DatabaseBinding {
+        engine,
+        name: object.clone(),
+        username: object.clone(),
+        password: hex::encode(state.crypto.encrypt_for(&config.id, password.as_bytes())?),
+        env_key,
+        created_at: chrono::Utc::now(),
+    };
+
+    match engine {
+        DatabaseEngine::Postgres => provision_postgres(&state.settings, &object, &password).await,
+        DatabaseEngine::Redis => provision_redis(&state.settings, &binding, &password).await,
+    }
+    .map_err(|e| AppError::BadRequest(format!("Provisioning failed: {}", e)))?;
+
+    info!(
+        "Provisioned {:?} {} for project {} ({})",
+        engine, object, config.id, binding.env_key
+    );
+
+    let info = BindingInfo::new(&config.id, &binding);
+    config.databases.push(binding);
+    state
+        .store
+        .update(&config)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn delete(
+    state: &AppState,
+    engine: DatabaseEngine,
+    name: &str,
+    purge: bool,
+) -> Result<impl IntoResponse, AppError> {
+    let projects = state
+        .store
+        .list()
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+    let owned = |p: &ProjectConfig| {
+        p.databases
+            .iter()
+            .position(|b| b.engine == engine && b.name == name)
+    };
+    let project_id = projects
+        .into_iter()
+        .find(|p| owned(p).is_some())
+        .map(|p| p.id)
+        .ok_or_else(|| AppError::BadRequest(format!("{} not found", name)))?;
+
+    // Look again under the lock; the binding may have changed meanwhile
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+    let mut config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+    let idx = owned(&config).ok_or_else(|| AppError::BadRequest(format!("{} not found", name)))?;
+
+    revoke(state, &config.databases[idx], purge)
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Revoking access failed: {}", e)))?;
+    let binding = config.databases.remove(idx);
+    state
+        .store
+        .update(&config)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+
+    info!(
+        "Unbound {:?} {} from project {} (purge: {})",
+        engine, name, config.id, purge
+    );
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "name": name,
+            "project_id": config.id,
+            "env_key": binding.env_key,
+            "status": if purge { "purged" } else { "revoked" },
+        })),
+    ))
+}
+
+pub async fn list_postgres_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<BindingInfo>>, AppError> {
+    Ok(Json(list(&state, DatabaseEngine::Postgres).await?))
+}
+
+pub async fn create_postgres_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateBindingRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    create(&state, DatabaseEngine::Postgres, payload).await
+}
+
+pub async fn delete_postgres_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<DeleteBindingQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    delete(&state, DatabaseEngine::Postgres, &name, query.purge).await
+}
+
+pub async fn list_redis_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<BindingInfo>>, AppError> {
+    Ok(Json(list(&state, DatabaseEngine::Redis).await?))
+}
+
+pub async fn create_redis_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateBindingRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    create(&state, DatabaseEngine::Redis, payload).await
+}
+
+pub async fn delete_redis_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<DeleteBindingQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    delete(&state, DatabaseEngine::Redis, &name, query.purge).await
+}
@@ -1,4 +1,4 @@
-use crate::{errors::AppError, state::AppState};
+use crate::{databases, env_vars, errors::AppError, state::AppState, volumes};
 use axum::{
     body::Bytes,
     extract::{Path, State},
@@ -17,6 +17,8 @@ pub struct DeployProjectRequest {
     pub url: Option<String>,
     pub file: Option<String>,
     pub environment: Option<HashMap<String, String>>,
+    /// Values sealed to the node public key (base64), opened only on the node
+    pub sealed_environment: Option<HashMap<String, String>>,
 }
 
 pub async fn project_deploy_handler(
@@ -103,6 +105,39 @@ pub async fn project_deploy_handler(
 
     let artifact_path = format!("{}/{}", project_dir, artifact_name);
 
+    let config = state.store.read(&project_id).await.ok();
+
+    // Refuse to restart an app whose volumes already exceed their quota
+    if let Some(config) = &config {
+        if let Some(v) = volumes::usage(&state.settings, config)
+            .await
+            .into_iter()
+            .find(|v| v.over_quota)
+        {
+            return Err(AppError::BadRequest(format!(
+                "Volume {} is over quota ({} / {} bytes)",
+                v.name,
+                v.used_bytes,
+                v.limit_bytes.unwrap_or(0)
+            )));
+        }
+
+        // Refuse a bundle shipping a volume's mount path before touching the release
+        if artifact_name.ends_with(".zip") {
+            let listing = Command::new("unzip")
+                .arg("-Z1")
+                .arg(&artifact_path)
+                .output()
+                .map_err(|_| AppError::InternalServerError)?;
+            if listing.status.success() {
+                volumes::check_bundle(config, &String::from_utf8_lossy(&listing.stdout))?;
+            }
+        }
+
+        // Detach volumes so extraction cannot overwrite persistent data
+        volumes::unlink_from_release(config, &project_dir).await?;
+    }
+
     // 2. Extract / Setup
     // Use std::process::Command for unzip/chmod as it is blocking but simple.
     // Ideally use tokio::process::Command in async code.
@@ -132,6 +167,11 @@ pub async fn project_deploy_handler(
         let _ = Command::new("chmod").arg("+x").arg(&app_path).output();
     }
 
+    // 2.1 Re-attach persistent volumes
+    if let Some(config) = &config {
+        volumes::link_into_release(&state.settings, config, &project_dir).await?;
+    }
+
     // 3. Setup Environment (.env)
     let env_path = format!("{}/.env", project_dir);
     let mut env_content = String::new();
@@ -142,12 +182,29 @@ pub async fn project_deploy_handler(
             env_content.push_str(&format!("{}={}\n", k, v));
         }
     }
+    if let Some(sealed_map) = req.sealed_environment {
+        for (k, sealed) in sealed_map {
+            let v = state.node_key.open_string(&sealed).map_err(|e| {
+                AppError::BadRequest(format!("Cannot open sealed value of {}: {}", k, e))
+            })?;
+            env_content.push_str(&format!("{}={}\n", k, v));
+        }
+    }
 
     // 3.2 Add/Merge encrypted environment from stored config
-    if let Ok(config) = state.store.read(&project_id).await {
+    if let Some(config) = &config {
+        for (k, v) in volumes::env_vars(&state.settings, config) {
+            env_content.push_str(&format!("{}={}\n", k, v));
+        }
+
+        // Managed databases bound to the project (DATABASE_URL, REDIS_URL, ...)
+        for (k, v) in databases::env_vars(&state, config)? {
+            env_content.push_str(&format!("{}={}\n", k, v));
+        }
+
         if !config.encrypted_env.is_empty() {
             if let Ok(enc_env) = hex::decode(&config.encrypted_env) {
-                if let Ok(env_bytes) = state.crypto.decrypt(&enc_env) {
+                if let Ok(env_bytes) = state.crypto.decrypt_for(&project_id, &enc_env) {
                     if let Ok(stored_env_str) = String::from_utf8(env_bytes) {
                         env_content.push_str("\n# Stored Secret Env\n");
                         env_content.push_str(&stored_env_str);
@@ -155,6 +212,14 @@ pub async fn project_deploy_handler(
                 }
             }
         }
+
+        // 3.3 Per-key variables (kept last so they can be rewritten in place)
+        if !config.env.is_empty() {
+            if !env_content.is_empty() && !env_content.ends_with('\n') {
+                env_content.push('\n');
+            }
+            env_content.push_str(&env_vars::render_managed(&state, config)?);
+        }
     }
 
     if !env_content.is_empty() {
@@ -167,7 +232,16 @@ pub async fn project_deploy_handler(
     // Ensure Systemd knows about changes if we updated unit file (we didn't, but good practice)
     let _ = Command::new("systemctl").arg("daemon-reload").status();
 
-    // Restart service
+    restart_app(&project_id)?;
+
+    Ok((
+        StatusCode::OK,
+        format!("Deployed artifact: {}", artifact_name),
+    ))
+}
+
+/// Restart the project's systemd unit (`app@<id>`).
+pub fn restart_app(project_id: &str) -> Result<(), AppError> {
     let output = Command::new("systemctl")
         .arg("restart")
         .arg(format!("app@{}", project_id))
@@ -180,8 +254,5 @@ pub async fn project_deploy_handler(
         return Err(AppError::InternalServerError);
     }
 
-    Ok((
-        StatusCode::OK,
-        format!("Deployed artifact: {}", artifact_name),
-    ))
+    Ok(())
 }
@@ -0,0 +1,335 @@
+use crate::storage::ProjectConfig;
+use crate::{deploy, errors::AppError, state::AppState};
+use anyhow::Result;
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+
+/// Marks the start of the per-key section in a project's `.env`. Everything
+/// after it is regenerated when variables change; everything before it
+/// (deploy payload, volumes, legacy blob) is left untouched.
+pub const MANAGED_MARKER: &str = "# Managed Env (per-key)";
+
+const MASK: &str = "********";
+
+#[derive(Serialize)]
+pub struct EnvVarEntry {
+    key: String,
+    value: String,
+    masked: bool,
+}
+
+pub fn validate_key(key: &str) -> Result<(), AppError> {
+    let valid = key
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !valid {
+        return Err(AppError::BadRequest(format!(
+            "Invalid variable name: {}",
+            key
+        )));
+    }
+    Ok(())
+}
+
+fn validate_value(key: &str, value: &str) -> Result<(), AppError> {
+    if value.contains('\n') || value.contains('\r') {
+        return Err(AppError::BadRequest(format!(
+            "Value of {} must not contain newlines",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Resolve a plaintext or sealed value (exactly one must be given).
+fn resolve_value(
+    state: &AppState,
+    key: &str,
+    value: Option<&String>,
+    sealed_value: Option<&String>,
+) -> Result<String, AppError> {
+    let value = match (value, sealed_value) {
+        (Some(value), None) => value.clone(),
+        (None, Some(sealed)) => state.node_key.open_string(sealed).map_err(|e| {
+            AppError::BadRequest(format!("Cannot open sealed value of {}: {}", key, e))
+        })?,
+        _ => {
+            return Err(AppError::BadRequest(format!(
+                "Provide exactly one of value or sealed_value for {}",
+                key
+            )))
+        }
+    };
+    validate_value(key, &value)?;
+    Ok(value)
+}
+
+fn encrypt_value(state: &AppState, project_id: &str, value: &str) -> Result<String> {
+    Ok(hex::encode(
+        state.crypto.encrypt_for(project_id, value.as_bytes())?,
+    ))
+}
+
+fn decrypt_value(state: &AppState, project_id: &str, encrypted: &str) -> Result<String> {
+    let bytes = state
+        .crypto
+        .decrypt_for(project_id, &hex::decode(encrypted)?)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Render the decrypted per-key variables as `.env` lines (marker included).
+pub fn render_managed(state: &AppState, config: &ProjectConfig) -> Result<String> {
+    let mut content = format!("{}\n", MANAGED_MARKER);
+    for (key, encrypted) in &config.env {
+        content.push_str(&format!(
+            "{}={}\n",
+            key,
+            decrypt_value(state, &config.id, encrypted)?
+        ));
+    }
+    Ok(content)
+}
+
+/// Rewrite the managed section of the project's `.env`, optionally restarting
+/// the app so it picks up the change.
+pub async fn apply(state: &AppState, config: &ProjectConfig, restart: bool) -> Result<()> {
+    let env_path = format!(
+        "{}/{}/bundle/.env",
+        state.settings.storage.projects_dir, config.id
+    );
+
+    let existing = tokio::fs::read_to_string(&env_path)
+        .await
+        .unwrap_or_default();
+    let head = match existing.find(MANAGED_MARKER) {
+        Some(idx) => &existing[..idx],
+        None => existing.as_str(),
+    };
+
+    let mut content = head.to_string();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&render_managed(state, config)?);
+
+    if let Some(parent) = std::path::Path::new(&env_path).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&env_path, content).await?;
+
+    if restart {
+        deploy::restart_app(&config.id)?;
+        info!("Restarted app@{} to apply env changes", config.id);
+    }
+    Ok(())
+}
+
+async fn read_project(state: &AppState, project_id: &str) -> Result<ProjectConfig, AppError> {
+    state
+        .store
+        .read(project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))
+}
+
+async fn save_and_apply(
+    state: &AppState,
+    config: &ProjectConfig,
+    restart: bool,
+) -> Result<(), AppError> {
+    state
+        .store
+        .update(config)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+    apply(state, config, restart).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct RevealQuery {
+    #[serde(default)]
+    pub reveal: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RestartQuery {
+    #[serde(default)]
+    pub restart: bool,
+}
+
+pub async fn list_env_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+) -> Result<Json<Vec<EnvVarEntry>>, AppError> {
+    let config = read_project(&state, &project_id).await?;
+
+    let entries = config
+        .env
+        .keys()
+        .map(|key| EnvVarEntry {
+            key: key.clone(),
+            value: MASK.to_string(),
+            masked: true,
+        })
+        .collect();
+
+    Ok(Json(entries))
+}
+
+pub async fn get_env_handler(
+    State(state): State<AppState>,
+    Path((project_id, key)): Path<(String, String)>,
+    Query(query): Query<RevealQuery>,
+) -> Result<Json<EnvVarEntry>, AppError> {
+    let config = read_project(&state, &project_id).await?;
+    let encrypted = config
+        .env
+        .get(&key)
+        .ok_or_else(|| AppError::BadRequest(format!("Variable {} not found", key)))?;
+
+    let value = if query.reveal {
+        decrypt_value(&state, &project_id, encrypted)?
+    } else {
+        MASK.to_string()
+    };
+
+    Ok(Json(EnvVarEntry {
+        key,
+        value,
+        masked: !query.reveal,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct PutEnvRequest {
+    pub value: Option<String>,
+    /// Base64 sealed box addressed to the node public key
+    pub sealed_value: Option<String>,
+    #[serde(default)]
+    pub restart: bool,
+}
+
+pub async fn put_env_handler(
+    State(state): State<AppState>,
+    Path((project_id, key)): Path<(String, String)>,
+    Json(payload): Json<PutEnvRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    validate_key(&key)?;
+    let value = resolve_value(
+        &state,
+        &key,
+        payload.value.as_ref(),
+        payload.sealed_value.as_ref(),
+    )?;
+
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = read_project(&state, &project_id).await?;
+    config
+        .env
+        .insert(key.clone(), encrypt_value(&state, &project_id, &value)?);
+    save_and_apply(&state, &config, payload.restart).await?;
+
+    info!("Set env {} for project {}", key, project_id);
+    Ok((StatusCode::OK, "Variable set"))
+}
+
+pub async fn delete_env_handler(
+    State(state): State<AppState>,
+    Path((project_id, key)): Path<(String, String)>,
+    Query(query): Query<RestartQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = read_project(&state, &project_id).await?;
+    if config.env.remove(&key).is_none() {
+        return Err(AppError::BadRequest(format!("Variable {} not found", key)));
+    }
+    save_and_apply(&state, &config, query.restart).await?;
+
+    info!("Removed env {} from project {}", key, project_id);
+    Ok((StatusCode::OK, "Variable removed"))
+}
+
+#[derive(Deserialize)]
+pub struct PatchEnvRequest {
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+    /// Like `set`, with values sealed to the node public key
+    #[serde(default)]
+    pub sealed_set: HashMap<String, String>,
+    #[serde(default)]
+    pub unset: Vec<String>,
+    #[serde(default)]
+    pub restart: bool,
+}
+
+pub async fn patch_env_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    Json(payload): Json<PatchEnvRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut values = Vec::new();
+    for (key, value) in &payload.set {
+        validate_key(key)?;
+        values.push((key, resolve_value(&state, key, Some(value), None)?));
+    }
+    for (key, sealed) in &payload.sealed_set {
+        validate_key(key)?;
+        if payload.set.contains_key(key) {
+            return Err(AppError::BadRequest(format!(
+                "{} is in both set and sealed_set",
+                key
+            )));
+        }
+        values.push((key, resolve_value(&state, key, None, Some(sealed))?));
+    }
+
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = read_project(&state, &project_id).await?;
+    for key in &payload.unset {
+        config.env.remove(key);
+    }
+    for (key, value) in &values {
+        config
+            .env
+            .insert((*key).clone(), encrypt_value(&state, &project_id, value)?);
+    }
+    save_and_apply(&state, &config, payload.restart).await?;
+
+    info!(
+        "Patched env for project {} ({} set, {} unset)",
+        project_id,
+        values.len(),
+        payload.unset.len()
+    );
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "set": values.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            "unset": payload.unset,
+            "restarted": payload.restart
+        })),
+    ))
+}
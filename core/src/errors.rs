@@ -18,6 +18,8 @@ pub enum AppError {
     InternalServerError,
     #[error("Bad Request: {0}")]
     BadRequest(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
 }
 
 impl IntoResponse for AppError {
@@ -42,6 +44,10 @@ impl IntoResponse for AppError {
                 StatusCode::BAD_REQUEST,
                 Box::leak(msg.into_boxed_str()) as &str,
             ),
+            AppError::Forbidden(msg) => (
+                StatusCode::FORBIDDEN,
+                Box::leak(msg.into_boxed_str()) as &str,
+            ),
         };
 
         let body = Json(json!({
@@ -0,0 +1,495 @@
+use crate::audit::Actor;
+use crate::config::{ExecMode, ExecSettings};
+use crate::recipes::{self, ResolvedRecipe};
+use crate::state::AppState;
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::{mpsc, Notify};
+
+/// Characters a `{placeholder}` in an allowlist template may match. No
+/// whitespace or shell metacharacters, so a match can't smuggle in extra
+/// arguments or commands.
+fn is_placeholder_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | '@')
+}
+
+/// Characters a template may opt into per placeholder, e.g. `{path:/}`.
+/// Off by default: they turn values into paths and `key=value` options.
+const OPT_IN_CHARS: &[char] = &[':', '=', '/'];
+
+/// Whether `value` is a safe match for the placeholder `spec` (the text
+/// between the braces). Values starting with `-` are options, not arguments,
+/// and are never matched.
+fn matches_placeholder(spec: &str, value: &str) -> bool {
+    let extra = spec.split_once(':').map_or("", |(_, extra)| extra);
+    if extra.chars().any(|c| !OPT_IN_CHARS.contains(&c)) {
+        return false; // Misconfigured template: match nothing
+    }
+    !value.is_empty()
+        && !value.starts_with('-')
+        && value
+            .chars()
+            .all(|c| is_placeholder_char(c) || extra.contains(c))
+}
+
+/// Match `cmd` against a template such as `apt-get install -y {package}`,
+/// token by token. Each `{name}` matches exactly one safe token.
+fn matches_template(template: &str, cmd: &str) -> bool {
+    let expected: Vec<&str> = template.split_whitespace().collect();
+    let actual: Vec<&str> = cmd.split_whitespace().collect();
+
+    expected.len() == actual.len()
+        && expected.iter().zip(&actual).all(|(t, a)| {
+            match t.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                Some(spec) if !spec.is_empty() => matches_placeholder(spec, a),
+                _ => t == a,
+            }
+        })
+}
+
+/// How an approved command is executed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Execution {
+    /// Admin-defined script (recipe, or any command in open mode), run by the shell
+    Shell(String),
+    /// Allowlisted command, run directly without a shell
+    Argv(Vec<String>),
+}
+
+/// Decide whether a remote caller may run `command` (or the service's recipe
+/// when no command is given). Trusted local callers (admin socket, CLI) are not
+/// restricted by the mode, since they already have a shell on the node.
+pub fn authorize(
+    settings: &ExecSettings,
+    service: &str,
+    uninstall: bool,
+    command: Option<&str>,
+    recipe: Option<&ResolvedRecipe>,
+    local: bool,
+) -> Result<Execution, String> {
+    if settings.mode == ExecMode::Disabled && !local {
+        return Err("Remote command execution is disabled on this node".into());
+    }
+
+    let Some(command) = command else {
+        let recipe =
+            recipe.ok_or_else(|| format!("No command given and no recipe named '{}'", service))?;
+        let (step, script) = if uninstall {
+            ("uninstall", &recipe.uninstall)
+        } else {
+            ("install", &recipe.install)
+        };
+        let script = script
+            .clone()
+            .ok_or_else(|| format!("Recipe '{}' has no {} step for this system", service, step))?;
+        return Ok(Execution::Shell(script));
+    };
+
+    if local || settings.mode == ExecMode::Open {
+        return Ok(Execution::Shell(command.to_string()));
+    }
+
+    if settings
+        .allowlist
+        .iter()
+        .any(|template| matches_template(template, command))
+    {
+        return Ok(Execution::Argv(
+            command.split_whitespace().map(str::to_string).collect(),
+        ));
+    }
+
+    Err(format!(
+        "Command is not allowed by the execution policy (mode: {:?}). Use a recipe or an allowlisted command.",
+        settings.mode
+    ))
+}
+
+#[derive(Debug)]
+pub struct ExecOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+    pub timed_out: bool,
+    pub cancelled: bool,
+    pub duration: Duration,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        !self.timed_out && !self.cancelled && self.exit_code == Some(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Receives output lines while a command runs.
+pub type LineSink = mpsc::UnboundedSender<(OutputStream, String)>;
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn lookup_user(name: &str) -> Result<(u32, u32), String> {
+    let c_name = std::ffi::CString::new(name).map_err(|e| e.to_string())?;
+    let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if pw.is_null() {
+        return Err(format!("Unknown exec.run_as user '{}'", name));
+    }
+    Ok(unsafe { ((*pw).pw_uid, (*pw).pw_gid) })
+}
+
+/// Kill the command and everything it started (it leads its own process group).
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn kill_process_group(pid: Option<u32>) {
+    if let Some(pid) = pid {
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_pid: Option<u32>) {}
+
+/// Stop a sandboxed command. Killing `systemd-run` only detaches from the
+/// transient unit, which keeps running.
+async fn stop_unit(unit: &str) {
+    let stopped = Command::new("systemctl")
+        .args(["stop", unit])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    if !stopped.is_ok_and(|s| s.success()) {
+        tracing::warn!("Failed to stop sandbox unit {}", unit);
+    }
+}
+
+/// Build the process for an approved execution, applying `run_as` and the
+/// optional systemd sandbox. Also returns the name of the sandbox unit.
+fn build_command(
+    settings: &ExecSettings,
+    execution: &Execution,
+) -> Result<(Command, Option<String>), String> {
+    let argv: Vec<String> = match execution {
+        Execution::Shell(script) if cfg!(target_os = "windows") => {
+            vec!["powershell".into(), "-Command".into(), script.clone()]
+        }
+        Execution::Shell(script) => vec!["bash".into(), "-c".into(), script.clone()],
+        Execution::Argv(argv) => argv.clone(),
+    };
+
+    let mut unit = None;
+    let mut cmd = if settings.sandbox {
+        // Transient unit: no privilege escalation, private /tmp, read-only /home.
+        // Named so it can be stopped; systemd also stops it past the timeout
+        // in case the agent can't.
+        let name = format!("zexio-exec-{}.service", uuid::Uuid::new_v4().simple());
+        let mut cmd = Command::new("systemd-run");
+        cmd.arg(format!("--unit={}", name)).arg(format!(
+            "--property=RuntimeMaxSec={}",
+            settings.timeout_secs.saturating_add(5)
+        ));
+        unit = Some(name);
+        cmd.args([
+            "--quiet",
+            "--wait",
+            "--pipe",
+            "--collect",
+            "--property=NoNewPrivileges=yes",
+            "--property=PrivateTmp=yes",
+            "--property=ProtectHome=read-only",
+        ]);
+        if let Some(user) = &settings.run_as {
+            cmd.arg(format!("--uid={}", user));
+        }
+        cmd.arg("--").args(&argv);
+        cmd
+    } else {
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..]);
+
+        #[cfg(unix)]
+        if let Some(user) = &settings.run_as {
+            let (uid, gid) = lookup_user(user)?;
+            cmd.uid(uid).gid(gid);
+        }
+        cmd
+    };
+
+    // Own process group, so a timeout or cancel also stops child processes
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    Ok((cmd, unit))
+}
+
+/// Read size for command output
+const READ_CHUNK: usize = 8 * 1024;
+/// Longer lines are split before they reach the sink
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+fn send_line(sink: &Option<LineSink>, stream: OutputStream, line: &mut Vec<u8>) {
+    if let Some(sink) = sink {
+        let text = String::from_utf8_lossy(line).trim_end().to_string();
+        let _ = sink.send((stream, text));
+    }
+    line.clear();
+}
+
+/// Read output in fixed-size chunks, keeping up to `limit` bytes and
+/// forwarding every line to `sink`. Keeps draining so the child never blocks.
+async fn read_lines<R: AsyncRead + Unpin>(
+    mut reader: R,
+    limit: usize,
+    stream: OutputStream,
+    sink: Option<LineSink>,
+) -> (Vec<u8>, bool) {
+    let mut chunk = vec![0u8; READ_CHUNK];
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut line = Vec::new();
+    let mut after_cr = false;
+    loop {
+        let n = match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let room = limit.saturating_sub(kept.len());
+        kept.extend_from_slice(&chunk[..n.min(room)]);
+        truncated |= n > room;
+        if sink.is_none() {
+            continue;
+        }
+        for &byte in &chunk[..n] {
+            match byte {
+                b'\n' if after_cr => {} // Second half of a CRLF
+                b'\n' | b'\r' => send_line(&sink, stream, &mut line),
+                _ => {
+                    line.push(byte);
+                    if line.len() >= MAX_LINE_BYTES {
+                        send_line(&sink, stream, &mut line);
+                    }
+                }
+            }
+            after_cr = byte == b'\r';
+        }
+    }
+    if !line.is_empty() {
+        send_line(&sink, stream, &mut line);
+    }
+    (kept, truncated)
+}
+
+/// Run an approved execution with the configured time and output limits.
+pub async fn run(settings: &ExecSettings, execution: &Execution) -> Result<ExecOutput, String> {
+    run_with(settings, execution, None, None).await
+}
+
+/// Like [`run`], streaming output lines to `sink` and stopping early when
+/// `cancel` is notified.
+pub async fn run_with(
+    settings: &ExecSettings,
+    execution: &Execution,
+    sink: Option<LineSink>,
+    cancel: Option<&Notify>,
+) -> Result<ExecOutput, String> {
+    let started = Instant::now();
+    let (mut cmd, unit) = build_command(settings, execution)?;
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to start command: {}", e))?;
+    let pid = child.id();
+
+    let limit = settings.max_output_bytes;
+    let stdout = child
+        .stdout
+        .take()
+        .map(|s| read_lines(s, limit, OutputStream::Stdout, sink.clone()));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|s| read_lines(s, limit, OutputStream::Stderr, sink));
+
+    let wait = async {
+        let (out, err) = tokio::join!(
+            async {
+                match stdout {
+                    Some(f) => f.await,
+                    None => (Vec::new(), false),
+                }
+            },
+            async {
+                match stderr {
+                    Some(f) => f.await,
+                    None => (Vec::new(), false),
+                }
+            }
+        );
+        (child.wait().await, out, err)
+    };
+    let cancelled = async {
+        match cancel {
+            Some(cancel) => cancel.notified().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    let timeout = Duration::from_secs(settings.timeout_secs);
+    let (stderr, timed_out) = tokio::select! {
+        result = tokio::time::timeout(timeout, wait) => match result {
+            Ok((status, (out, out_trunc), (err, err_trunc))) => {
+                return Ok(ExecOutput {
+                    exit_code: status.map_err(|e| e.to_string())?.code(),
+                    stdout: String::from_utf8_lossy(&out).to_string(),
+                    stderr: String::from_utf8_lossy(&err).to_string(),
+                    truncated: out_trunc || err_trunc,
+                    timed_out: false,
+                    cancelled: false,
+                    duration: started.elapsed(),
+                });
+            }
+            Err(_) => (format!("Timed out after {}s", settings.timeout_secs), true),
+        },
+        _ = cancelled => ("Cancelled".to_string(), false),
+    };
+
+    if let Some(unit) = &unit {
+        stop_unit(unit).await;
+    }
+    kill_process_group(pid);
+    Ok(ExecOutput {
+        exit_code: None,
+        stdout: String::new(),
+        stderr,
+        truncated: false,
+        timed_out,
+        cancelled: !timed_out,
+        duration: started.elapsed(),
+    })
+}
+
+fn service_action(uninstall: bool) -> &'static str {
+    if uninstall {
+        "exec uninstall"
+    } else {
+        "exec install"
+    }
+}
+
+/// Authorize a service install/uninstall command, auditing denials.
+pub async fn authorize_service(
+    state: &AppState,
+    actor: &Actor,
+    service: &str,
+    uninstall: bool,
+    command: Option<&str>,
+) -> Result<Execution, ExecError> {
+    let recipe = match command {
+        Some(_) => None,
+        None => recipes::Catalog::load(&state.settings)
+            .map_err(|e| ExecError::Failed(e.to_string()))?
+            .resolve(service),
+    };
+
+    let local = actor.kind == "socket";
+    let action = service_action(uninstall);
+    match authorize(
+        &state.settings.exec,
+        service,
+        uninstall,
+        command,
+        recipe.as_ref(),
+        local,
+    ) {
+        Ok(execution) => {
+            tracing::info!("Running {} for {}: {:?}", action, service, execution);
+            Ok(execution)
+        }
+        Err(reason) => {
+            tracing::warn!("🚫 Denied command for {}: {}", service, reason);
+            record(
+                state,
+                actor.clone(),
+                action,
+                service,
+                command,
+                &reason,
+                None,
+            )
+            .await;
+            Err(ExecError::Denied(reason))
+        }
+    }
+}
+
+/// Audit the result of an approved service install/uninstall command.
+pub async fn record_service_result(
+    state: &AppState,
+    actor: Actor,
+    service: &str,
+    uninstall: bool,
+    command: Option<&str>,
+    result: &Result<ExecOutput, String>,
+) {
+    let (outcome, output) = match result {
+        Ok(output) if output.success() => ("allowed", Some(output)),
+        Ok(output) if output.cancelled => ("cancelled", Some(output)),
+        Ok(output) => ("failed", Some(output)),
+        Err(_) => ("failed", None),
+    };
+    let action = service_action(uninstall);
+    record(state, actor, action, service, command, outcome, output).await;
+}
+
+async fn record(
+    state: &AppState,
+    actor: Actor,
+    action: &str,
+    service: &str,
+    command: Option<&str>,
+    decision: &str,
+    output: Option<&ExecOutput>,
+) {
+    let details = serde_json::json!({
+        "service": service,
+        "command": command,
+        "decision": decision,
+        "exit_code": output.and_then(|o| o.exit_code),
+        "timed_out": output.map(|o| o.timed_out),
+        "cancelled": output.map(|o| o.cancelled),
+        "truncated": output.map(|o| o.truncated),
+        "duration_ms": output.map(|o| o.duration.as_millis() as u64),
+        "run_as": state.settings.exec.run_as,
+        "sandbox": state.settings.exec.sandbox,
+    });
+    let success = output.map(|o| o.success()).unwrap_or(false);
+
+    if let Err(e) = state
+        .audit
+        .append(actor, action.to_string(), None, details, None, success)
+        .await
+    {
+        tracing::error!("Failed to write audit log: {}", e);
+    }
+}
+
+#[derive(Debug)]
+pub enum ExecError {
+    Denied(String),
+    Failed(String),
+}
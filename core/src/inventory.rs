@@ -0,0 +1,269 @@
+use crate::config::Settings;
+use crate::recipes::{self, Catalog};
+use crate::{errors::AppError, state::AppState};
+use anyhow::{Context, Result};
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{error, info, warn};
+
+/// How often installed services are checked.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    #[default]
+    Unknown, // Nothing to check (no unit, status step or ports)
+    Healthy,
+    Unhealthy,
+}
+
+impl Health {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Healthy => "healthy",
+            Self::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledService {
+    pub name: String,
+    pub version: Option<String>,
+    pub installed_at: DateTime<Utc>,
+    pub recipe: Option<String>, // None when installed with a custom command
+    pub unit: Option<String>,
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub health: Health,
+    pub health_message: Option<String>,
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+/// Services installed through the agent, persisted as JSON in
+/// `storage.services_path`. The daemon and CLI commands both update it.
+#[derive(Clone)]
+pub struct ServiceInventory {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>, // Serializes read-modify-write cycles in this process
+}
+
+impl ServiceInventory {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<InstalledService>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let json = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {:?}", self.path))?;
+        serde_json::from_str(&json).context("Failed to parse services inventory")
+    }
+
+    /// Exclusive access for a read-modify-write cycle: the in-process lock
+    /// plus a file lock (`services.json.lock`) other processes honor.
+    async fn exclusive(&self) -> Result<(MutexGuard<'_, ()>, std::fs::File)> {
+        let guard = self.lock.lock().await;
+        let path = self.path.with_extension("json.lock");
+        let file = tokio::task::spawn_blocking(move || -> Result<std::fs::File> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {:?}", path))?;
+            file.lock().context("Failed to lock services inventory")?;
+            Ok(file)
+        })
+        .await??;
+        Ok((guard, file))
+    }
+
+    fn save(&self, services: &[InstalledService]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_string_pretty(services)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Record a successful install. Unit, ports and version come from the
+    /// catalog recipe of the same name, if there is one.
+    pub async fn record_install(
+        &self,
+        settings: &Settings,
+        name: &str,
+        from_recipe: bool,
+    ) -> Result<InstalledService> {
+        let recipe = Catalog::load(settings)?.resolve(name);
+        let version = match recipe.as_ref().and_then(|r| r.version.clone()) {
+            Some(script) => recipes::query(settings, &script).await,
+            None => None,
+        };
+
+        let service = InstalledService {
+            name: name.to_string(),
+            version,
+            installed_at: Utc::now(),
+            recipe: recipe
+                .as_ref()
+                .filter(|_| from_recipe)
+                .map(|_| name.to_string()),
+            unit: recipe.as_ref().and_then(|r| r.unit.clone()),
+            ports: recipe.map(|r| r.ports).unwrap_or_default(),
+            health: Health::Unknown,
+            health_message: None,
+            checked_at: None,
+        };
+
+        let _lock = self.exclusive().await?;
+        let mut services = self.list()?;
+        services.retain(|s| s.name != name);
+        services.push(service.clone());
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        self.save(&services)?;
+        Ok(service)
+    }
+
+    pub async fn record_uninstall(&self, name: &str) -> Result<()> {
+        let _lock = self.exclusive().await?;
+        let mut services = self.list()?;
+        services.retain(|s| s.name != name);
+        self.save(&services)
+    }
+
+    /// Check every service and store the results.
+    pub async fn check_health(&self, settings: &Settings) -> Result<()> {
+        let services = self.list()?;
+        if services.is_empty() {
+            return Ok(());
+        }
+        let catalog = Catalog::load(settings)?;
+
+        // Checks can be slow, so run them before taking the lock
+        let mut results = Vec::new();
+        for service in &services {
+            let status = catalog.resolve(&service.name).and_then(|r| r.status);
+            results.push((
+                service.name.clone(),
+                service.health,
+                probe(settings, service, status).await,
+            ));
+        }
+
+        let _lock = self.exclusive().await?;
+        let mut current = self.list()?;
+        for (name, previous, (health, message)) in results {
+            let Some(service) = current.iter_mut().find(|s| s.name == name) else {
+                continue; // Uninstalled meanwhile
+            };
+            if health != previous {
+                match health {
+                    Health::Unhealthy => warn!(
+                        "⚠️  Service {} is unhealthy: {}",
+                        name,
+                        message.as_deref().unwrap_or_default()
+                    ),
+                    _ => info!("Service {} is {:?}", name, health),
+                }
+            }
+            service.health = health;
+            service.health_message = message;
+            service.checked_at = Some(Utc::now());
+        }
+        self.save(&current)
+    }
+}
+
+/// Health of one service: its unit is active, its status step passes and its
+/// ports accept connections.
+async fn probe(
+    settings: &Settings,
+    service: &InstalledService,
+    status: Option<String>,
+) -> (Health, Option<String>) {
+    let mut checked = false;
+
+    if let Some(unit) = &service.unit {
+        if Path::new("/run/systemd/system").exists() {
+            checked = true;
+            let active = Command::new("systemctl")
+                .args(["is-active", "--quiet", unit])
+                .status()
+                .await
+                .map(|s| s.success())
+                .unwrap_or(false);
+            if !active {
+                return (
+                    Health::Unhealthy,
+                    Some(format!("Unit {} is not active", unit)),
+                );
+            }
+        }
+    }
+
+    if let Some(script) = status {
+        checked = true;
+        if recipes::query(settings, &script).await.is_none() {
+            return (Health::Unhealthy, Some("Status check failed".into()));
+        }
+    }
+
+    for port in &service.ports {
+        checked = true;
+        let connect = TcpStream::connect(("127.0.0.1", *port));
+        if !matches!(
+            tokio::time::timeout(Duration::from_secs(2), connect).await,
+            Ok(Ok(_))
+        ) {
+            return (
+                Health::Unhealthy,
+                Some(format!("Port {} is not listening", port)),
+            );
+        }
+    }
+
+    if checked {
+        (Health::Healthy, None)
+    } else {
+        (Health::Unknown, None)
+    }
+}
+
+/// Background task: periodically check installed services.
+pub async fn health_monitor(state: AppState) {
+    loop {
+        if let Err(e) = state.inventory.check_health(&state.settings).await {
+            error!("Service health check failed: {}", e);
+        }
+        tokio::time::sleep(HEALTH_INTERVAL).await;
+    }
+}
+
+/// `GET /services`
+pub async fn list_services_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<InstalledService>>, AppError> {
+    Ok(Json(state.inventory.list()?))
+}
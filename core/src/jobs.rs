@@ -0,0 +1,404 @@
+use crate::admin_socket::DaemonClient;
+use crate::audit::Actor;
+use crate::exec::{self, ExecError, ExecOutput, Execution, OutputStream};
+use crate::{errors::AppError, state::AppState};
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::stream::Stream;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc, Notify};
+
+/// Finished jobs kept for `GET /jobs`; older ones are dropped.
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: String, // "install" or "uninstall"
+    pub service: String,
+    pub command: Option<String>, // None when the recipe was used
+    pub actor: Actor,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<u64>,
+    pub truncated: bool,
+    pub version: Option<String>, // Reported by the recipe after an install
+}
+
+#[derive(Debug, Clone)]
+enum JobEvent {
+    Output(OutputLine),
+    Finished(Box<JobInfo>),
+}
+
+struct Job {
+    info: JobInfo,
+    output: Vec<OutputLine>,
+    output_bytes: usize,
+    events: broadcast::Sender<JobEvent>,
+    cancel: Arc<Notify>,
+}
+
+/// Service install/uninstall jobs running in the background. Output is
+/// buffered (up to `exec.max_output_bytes`) so late subscribers can replay it.
+#[derive(Clone, Default)]
+pub struct JobManager {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl JobManager {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Job>> {
+        // A panic while holding the lock can't leave a job half-updated
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Authorize and start an install/uninstall. Denials are returned
+    /// immediately; everything else is reported through the job.
+    pub async fn start(
+        &self,
+        state: AppState,
+        actor: Actor,
+        service: String,
+        uninstall: bool,
+        command: Option<String>,
+    ) -> Result<JobInfo, ExecError> {
+        let execution =
+            exec::authorize_service(&state, &actor, &service, uninstall, command.as_deref())
+                .await?;
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let info = JobInfo {
+            id: id.clone(),
+            kind: if uninstall { "uninstall" } else { "install" }.to_string(),
+            service,
+            command,
+            actor,
+            status: JobStatus::Running,
+            exit_code: None,
+            error: None,
+            created_at: Utc::now(),
+            finished_at: None,
+            duration_ms: None,
+            truncated: false,
+            version: None,
+        };
+        let cancel = Arc::new(Notify::new());
+        {
+            let mut jobs = self.lock();
+            Self::prune(&mut jobs);
+            jobs.insert(
+                id.clone(),
+                Job {
+                    info: info.clone(),
+                    output: Vec::new(),
+                    output_bytes: 0,
+                    events: broadcast::channel(1024).0,
+                    cancel: cancel.clone(),
+                },
+            );
+        }
+
+        let manager = self.clone();
+        let job = info.clone();
+        tokio::spawn(async move {
+            manager.run(state, job, execution, cancel).await;
+        });
+
+        Ok(info)
+    }
+
+    async fn run(&self, state: AppState, job: JobInfo, execution: Execution, cancel: Arc<Notify>) {
+        let limit = state.settings.exec.max_output_bytes;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let run = exec::run_with(&state.settings.exec, &execution, Some(tx), Some(&cancel));
+        let forward = async {
+            while let Some((stream, line)) = rx.recv().await {
+                self.push_line(&job.id, limit, OutputLine { stream, line });
+            }
+        };
+        let (result, ()) = tokio::join!(run, forward);
+
+        let uninstall = job.kind == "uninstall";
+        exec::record_service_result(
+            &state,
+            job.actor.clone(),
+            &job.service,
+            uninstall,
+            job.command.as_deref(),
+            &result,
+        )
+        .await;
+
+        let mut version = None;
+        if matches!(&result, Ok(output) if output.success()) {
+            let inventory = &state.inventory;
+            let recorded = if uninstall {
+                inventory.record_uninstall(&job.service).await
+            } else {
+                inventory
+                    .record_install(&state.settings, &job.service, job.command.is_none())
+                    .await
+                    .map(|service| version = service.version)
+            };
+            if let Err(e) = recorded {
+                tracing::error!("Failed to update services inventory: {}", e);
+            }
+        }
+        self.finish(&job.id, result, version);
+    }
+
+    fn push_line(&self, id: &str, limit: usize, line: OutputLine) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        if job.output_bytes + line.line.len() <= limit {
+            job.output_bytes += line.line.len();
+            job.output.push(line.clone());
+        } else {
+            job.info.truncated = true;
+        }
+        let _ = job.events.send(JobEvent::Output(line));
+    }
+
+    fn finish(&self, id: &str, result: Result<ExecOutput, String>, version: Option<String>) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        let info = &mut job.info;
+        match result {
+            Ok(output) => {
+                info.status = if output.success() {
+                    JobStatus::Succeeded
+                } else if output.timed_out {
+                    JobStatus::TimedOut
+                } else if output.cancelled {
+                    JobStatus::Cancelled
+                } else {
+                    JobStatus::Failed
+                };
+                info.exit_code = output.exit_code;
+                info.truncated |= output.truncated;
+                info.duration_ms = Some(output.duration.as_millis() as u64);
+                if !output.success() {
+                    info.error = Some(output.stderr.trim().to_string());
+                }
+            }
+            Err(e) => {
+                info.status = JobStatus::Failed;
+                info.error = Some(e);
+            }
+        }
+        info.version = version;
+        info.finished_at = Some(Utc::now());
+
+        tracing::info!(
+            "Job {} ({} {}) finished: {:?}",
+            info.id,
+            info.kind,
+            info.service,
+            info.status
+        );
+        let _ = job
+            .events
+            .send(JobEvent::Finished(Box::new(job.info.clone())));
+    }
+
+    /// Drop the oldest finished jobs beyond `MAX_FINISHED_JOBS`.
+    fn prune(jobs: &mut HashMap<String, Job>) {
+        let mut finished: Vec<(DateTime<Utc>, String)> = jobs
+            .values()
+            .filter(|j| j.info.status != JobStatus::Running)
+            .map(|j| (j.info.created_at, j.info.id.clone()))
+            .collect();
+        if finished.len() < MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in &finished[..=finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(id);
+        }
+    }
+
+    /// All known jobs, newest first.
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self.lock().values().map(|j| j.info.clone()).collect();
+        jobs.sort_by_key(|j| std::cmp::Reverse(j.created_at));
+        jobs
+    }
+
+    pub fn get(&self, id: &str) -> Option<(JobInfo, Vec<OutputLine>)> {
+        self.lock()
+            .get(id)
+            .map(|j| (j.info.clone(), j.output.clone()))
+    }
+
+    /// Ask a running job to stop. Returns `None` for unknown jobs.
+    pub fn cancel(&self, id: &str) -> Option<JobInfo> {
+        let jobs = self.lock();
+        let job = jobs.get(id)?;
+        if job.info.status == JobStatus::Running {
+            job.cancel.notify_one();
+        }
+        Some(job.info.clone())
+    }
+
+    /// Buffered output plus a receiver for what follows. Taken under the same
+    /// lock that publishes events, so nothing is missed or sent twice.
+    fn subscribe(
+        &self,
+        id: &str,
+    ) -> Option<(
+        Vec<OutputLine>,
+        Option<JobInfo>,
+        broadcast::Receiver<JobEvent>,
+    )> {
+        let jobs = self.lock();
+        let job = jobs.get(id)?;
+        let finished = (job.info.status != JobStatus::Running).then(|| job.info.clone());
+        Some((job.output.clone(), finished, job.events.subscribe()))
+    }
+}
+
+fn output_event(line: &OutputLine) -> Event {
+    Event::default()
+        .event("output")
+        .json_data(line)
+        .unwrap_or_default()
+}
+
+fn status_event(info: &JobInfo) -> Event {
+    Event::default()
+        .event("status")
+        .json_data(info)
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+pub struct JobDetails {
+    #[serde(flatten)]
+    pub info: JobInfo,
+    pub output: Vec<OutputLine>,
+}
+
+pub async fn list_jobs_handler(State(state): State<AppState>) -> Json<Vec<JobInfo>> {
+    Json(state.jobs.list())
+}
+
+pub async fn get_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobDetails>, AppError> {
+    let (info, output) = state
+        .jobs
+        .get(&id)
+        .ok_or_else(|| AppError::BadRequest("Job not found".into()))?;
+    Ok(Json(JobDetails { info, output }))
+}
+
+pub async fn cancel_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobInfo>, AppError> {
+    let info = state
+        .jobs
+        .cancel(&id)
+        .ok_or_else(|| AppError::BadRequest("Job not found".into()))?;
+    Ok(Json(info))
+}
+
+/// Replays the job's output so far, then streams new lines as `output`
+/// events and ends with a `status` event once the job is finished.
+pub async fn job_stream_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let (buffered, finished, mut rx) = state
+        .jobs
+        .subscribe(&id)
+        .ok_or_else(|| AppError::BadRequest("Job not found".into()))?;
+
+    let stream = async_stream::stream! {
+        for line in &buffered {
+            yield Ok(output_event(line));
+        }
+        if let Some(info) = finished {
+            yield Ok(status_event(&info));
+            return;
+        }
+
+        loop {
+            match rx.recv().await {
+                Ok(JobEvent::Output(line)) => yield Ok(output_event(&line)),
+                Ok(JobEvent::Finished(info)) => {
+                    yield Ok(status_event(&info));
+                    break;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    yield Ok(Event::default().event("lagged").data(skipped.to_string()));
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Print a daemon job's output live until it finishes (CLI).
+pub async fn follow(client: &DaemonClient, id: &str) -> Result<Option<serde_json::Value>> {
+    let mut result = None;
+    client
+        .stream_events(&format!("/jobs/{}/stream", id), |event, data| {
+            match event {
+                "output" => {
+                    if let Ok(line) = serde_json::from_str::<serde_json::Value>(data) {
+                        let text = line["line"].as_str().unwrap_or_default();
+                        if line["stream"] == "stderr" {
+                            eprintln!("{}", text);
+                        } else {
+                            println!("{}", text);
+                        }
+                    }
+                }
+                "status" => {
+                    result = Some(data.to_string());
+                    return false;
+                }
+                _ => {}
+            }
+            true
+        })
+        .await?;
+
+    Ok(result.and_then(|data| serde_json::from_str(&data).ok()))
+}
@@ -0,0 +1,131 @@
+use crate::config::Settings;
+use crate::crypto::Crypto;
+use crate::mesh::acme;
+use crate::storage::ProjectConfig;
+use crate::{snapshots, state::AppState};
+use anyhow::Result;
+use tracing::{info, warn};
+
+/// Rotate the master key without downtime:
+/// 1. add a new active master key (old keys stay for decryption),
+/// 2. re-wrap every project data key,
+/// 3. move data still encrypted directly under a master key to data keys,
+///    and re-encrypt what stays under it (the ACME account key),
+/// 4. drop the old master keys.
+///
+/// The running daemon picks up the new keyring on its next crypto operation.
+/// An interrupted rotation is resumed by running the command again.
+pub async fn rotate(settings: Settings) -> Result<()> {
+    let state = AppState::new(settings)?;
+
+    let active = state.crypto.begin_rotation()?;
+    info!("🔑 Active master key: {}", active);
+
+    let rewrapped = state.crypto.rewrap_data_keys()?;
+    info!("   Re-wrapped {} project data key(s)", rewrapped);
+
+    for project in state.store.list().await? {
+        // The daemon may be changing the same config; re-read it under the lock
+        let lock = state.store.lock(&project.id).await?;
+        let mut project = state.store.read(&project.id).await?;
+        if migrate_env(&state, &mut project)? {
+            state.store.update(&project).await?;
+            info!("   Migrated environment of {} to its data key", project.id);
+        }
+        drop(lock);
+
+        let rewritten = snapshots::reencrypt_legacy(&state, &project.id).await?;
+        if rewritten > 0 {
+            info!(
+                "   Re-encrypted {} snapshot(s) of {} with its data key",
+                rewritten, project.id
+            );
+        }
+    }
+
+    match acme::reencrypt_account(&state.settings.acme, &state.crypto) {
+        Ok(true) => info!("   Re-encrypted the ACME account key"),
+        Ok(false) => {}
+        // The agent registers a new account when it can't read the key
+        Err(e) => warn!("ACME account key not re-encrypted: {}", e),
+    }
+
+    let retired = state.crypto.finish_rotation()?;
+    if retired.is_empty() {
+        info!("✅ Rotation complete. No old keys to retire.");
+    } else {
+        info!("✅ Rotation complete. Retired keys: {}", retired.join(", "));
+    }
+    Ok(())
+}
+
+/// Re-encrypt env values (and the legacy blob) that are not yet under the
+/// project's data key. Returns whether anything changed.
+fn migrate_env(state: &AppState, project: &mut ProjectConfig) -> Result<bool> {
+    let mut changed = false;
+
+    for value in project.env.values_mut() {
+        let encrypted = hex::decode(&*value)?;
+        if Crypto::is_data_key_ciphertext(&encrypted) {
+            continue;
+        }
+        let plaintext = state.crypto.decrypt_for(&project.id, &encrypted)?;
+        *value = hex::encode(state.crypto.encrypt_for(&project.id, &plaintext)?);
+        changed = true;
+    }
+
+    if !project.encrypted_env.is_empty() {
+        let encrypted = hex::decode(&project.encrypted_env)?;
+        if !Crypto::is_data_key_ciphertext(&encrypted) {
+            match state.crypto.decrypt_for(&project.id, &encrypted) {
+                Ok(plaintext) => {
+                    project.encrypted_env =
+                        hex::encode(state.crypto.encrypt_for(&project.id, &plaintext)?);
+                    changed = true;
+                }
+                Err(e) => warn!(
+                    "Legacy env blob of {} cannot be decrypted ({}); leaving it as is",
+                    project.id, e
+                ),
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Print the keyring and which master key wraps each project data key.
+pub fn status(settings: &Settings) -> Result<()> {
+    let crypto = Crypto::new(&settings.secrets)?;
+    let keyring = crypto.keyring()?;
+
+    println!("Master keys ({}):", settings.secrets.keyring_path);
+    for (id, key) in &keyring.keys {
+        let mut flags = Vec::new();
+        if *id == keyring.active {
+            flags.push("active");
+        }
+        if keyring.legacy.as_deref() == Some(id.as_str()) {
+            flags.push("legacy");
+        }
+        println!(
+            "  {:<6} created {}  {}",
+            id,
+            key.created_at.to_rfc3339(),
+            flags.join(", ")
+        );
+    }
+    if keyring.keys.len() > 1 {
+        println!("  ⚠️  Rotation in progress. Run `zexio keys rotate` to finish it.");
+    }
+
+    println!("\nProject data keys ({}):", settings.secrets.data_keys_dir);
+    for project_id in crypto.data_key_projects()? {
+        let wrapper = crypto
+            .data_key_wrapper(&project_id)?
+            .unwrap_or_else(|| "?".to_string());
+        println!("  {:<36} wrapped by {}", project_id, wrapper);
+    }
+
+    Ok(())
+}
@@ -1,18 +1,35 @@
+mod admin_socket;
+mod audit;
+mod caddy;
 mod config;
 mod crypto;
 mod daemon;
+mod databases;
 mod deploy;
+mod env_vars;
 mod errors;
+mod exec;
+mod inventory;
+mod jobs;
+mod keys;
 mod mesh;
 mod middleware;
 mod monitor;
 mod project;
+mod recipes;
 mod registration;
+mod s3;
+mod sealed;
 mod server;
 mod services;
+mod signing;
+mod snapshots;
 mod state;
 mod storage;
 mod streams;
+mod tls;
+mod tokens;
+mod volumes;
 
 use clap::{Parser, Subcommand};
 use tracing::{error, info};
@@ -47,20 +64,39 @@ enum Commands {
     Unregister,
     /// Install a software package (e.g., docker, redis, postgres)
     Install {
-        /// Name of the package to install
-        package: String,
-        /// Optional: Exact shell command/script to execute
+        /// Name of the package to install (a recipe from `--list`)
+        #[arg(required_unless_present = "list")]
+        package: Option<String>,
+        /// Optional: Exact shell command/script to execute instead of the recipe
         #[arg(long)]
         command: Option<String>,
+        /// List the available recipes
+        #[arg(long)]
+        list: bool,
     },
     /// Uninstall a software package
     Uninstall {
         /// Name of the package to uninstall
         package: String,
-        /// Optional: Exact shell command/script to execute
+        /// Optional: Exact shell command/script to execute instead of the recipe
         #[arg(long)]
         command: Option<String>,
     },
+    /// Manage scoped API tokens for the Management API
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+    /// Inspect the audit log
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+    /// Manage encryption keys
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
     /// Manage Zexio Agent as a system service (daemon)
     Service {
         #[command(subcommand)]
@@ -68,6 +104,40 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum TokenAction {
+    /// Create a token (printed once)
+    Create {
+        /// Token name, e.g. "ci" or "gui"
+        #[arg(long)]
+        name: String,
+        /// Comma-separated scopes, e.g. "projects:read,deploy" ("*" for all)
+        #[arg(long)]
+        scope: String,
+        /// Expiry, e.g. 30d, 12h or 45m (default: never)
+        #[arg(long)]
+        expires_in: Option<String>,
+    },
+    /// List tokens
+    List,
+    /// Revoke a token by id or name
+    Revoke { token: String },
+}
+
+#[derive(Subcommand)]
+enum AuditAction {
+    /// Check the hash chain of the audit log
+    Verify,
+}
+
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Generate a new master key and re-wrap all project data keys
+    Rotate,
+    /// Show master keys and which key wraps each project data key
+    Status,
+}
+
 #[derive(Subcommand)]
 enum ServiceAction {
     /// Install as a system service
@@ -145,42 +215,164 @@ async fn main() -> anyhow::Result<()> {
             daemon::handle_service(daemon_action).await?;
             return Ok(());
         }
-        Some(Commands::Install { package, command }) => {
-            if let Some(cmd) = command {
-                info!(
-                    "🛠️  Zexio is executing specialized command for {}: {}...",
-                    package, cmd
-                );
-                match services::run_generic_command(&cmd).await {
-                    Ok(stdout) => {
-                        info!("✅ Successfully executed for {}:", package);
-                        println!("{}", stdout);
-                    }
-                    Err(e) => error!("❌ Execution failed: {}", e),
+        Some(Commands::Token { action }) => {
+            match action {
+                TokenAction::Create {
+                    name,
+                    scope,
+                    expires_in,
+                } => {
+                    let result = tokens::create(&settings, &name, &scope, expires_in.as_deref());
+                    audit::record_cli(
+                        &settings,
+                        "token create",
+                        None,
+                        serde_json::json!({ "name": name, "scope": scope, "expires_in": expires_in }),
+                        result.is_ok(),
+                    )
+                    .await;
+                    result?
                 }
-            } else {
+                TokenAction::List => tokens::list(&settings)?,
+                TokenAction::Revoke { token } => {
+                    let result = tokens::revoke(&settings, &token);
+                    audit::record_cli(
+                        &settings,
+                        "token revoke",
+                        None,
+                        serde_json::json!({ "id": token }),
+                        result.is_ok(),
+                    )
+                    .await;
+                    result?
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Audit { action }) => {
+            match action {
+                AuditAction::Verify => audit::verify(&settings).await?,
+            }
+            return Ok(());
+        }
+        Some(Commands::Keys { action }) => {
+            match action {
+                KeysAction::Rotate => {
+                    info!("🔑 Rotating master key...");
+                    let result = keys::rotate(settings.clone()).await;
+                    audit::record_cli(
+                        &settings,
+                        "keys rotate",
+                        None,
+                        serde_json::Value::Null,
+                        result.is_ok(),
+                    )
+                    .await;
+                    result?
+                }
+                KeysAction::Status => keys::status(&settings)?,
+            }
+            return Ok(());
+        }
+        Some(Commands::Install {
+            package,
+            command,
+            list,
+        }) => {
+            let Some(package) = package.filter(|_| !list) else {
+                recipes::list(&settings).await?;
+                return Ok(());
+            };
+            if let Some(client) = admin_socket::DaemonClient::connect(&settings).await {
+                info!("🔌 Sending install of {} to the running agent...", package);
+                let body = serde_json::json!({ "service": package, "command": command });
+                run_daemon_job(&client, "/services/install", body).await?;
+                return Ok(());
+            }
+            let recipe = recipes::Catalog::load(&settings)?.resolve(&package);
+            let Some(cmd) = command.clone().or_else(|| recipe.and_then(|r| r.install)) else {
                 error!(
-                    "❌ Error: No command provided. Use --command '...' to specify what to run."
+                    "❌ Error: No recipe for {} on this system. Use --command '...' to specify what to run.",
+                    package
                 );
-                info!("   Example: zexio install docker --command 'curl -fsSL https://get.docker.com | sudo sh'");
+                info!("   Available recipes: zexio install --list");
+                return Ok(());
+            };
+            info!(
+                "🛠️  Zexio is executing install command for {}: {}...",
+                package, cmd
+            );
+            let result = services::run_generic_command(&settings, &cmd).await;
+            audit::record_cli(
+                &settings,
+                "install",
+                None,
+                serde_json::json!({ "service": package, "command": cmd, "recipe": command.is_none() }),
+                result.is_ok(),
+            )
+            .await;
+            match result {
+                Ok(()) => {
+                    info!("✅ Successfully executed for {}", package);
+                    let inventory =
+                        inventory::ServiceInventory::new(&settings.storage.services_path);
+                    match inventory
+                        .record_install(&settings, &package, command.is_none())
+                        .await
+                    {
+                        Ok(service) => {
+                            if let Some(version) = service.version {
+                                info!("📦 Installed: {}", version);
+                            }
+                        }
+                        Err(e) => error!("Failed to update services inventory: {}", e),
+                    }
+                }
+                Err(e) => error!("❌ Execution failed: {}", e),
             }
             return Ok(());
         }
         Some(Commands::Uninstall { package, command }) => {
-            if let Some(cmd) = command {
+            if let Some(client) = admin_socket::DaemonClient::connect(&settings).await {
                 info!(
-                    "🗑️  Zexio is executing uninstall command for {}: {}...",
-                    package, cmd
+                    "🔌 Sending uninstall of {} to the running agent...",
+                    package
+                );
+                let body = serde_json::json!({ "service": package, "command": command });
+                run_daemon_job(&client, "/services/uninstall", body).await?;
+                return Ok(());
+            }
+            let recipe = recipes::Catalog::load(&settings)?.resolve(&package);
+            let Some(cmd) = command.clone().or_else(|| recipe.and_then(|r| r.uninstall)) else {
+                error!(
+                    "❌ Error: No uninstall recipe for {} on this system. Use --command '...'",
+                    package
                 );
-                match services::run_generic_command(&cmd).await {
-                    Ok(stdout) => {
-                        info!("✅ Successfully executed uninstall for {}:", package);
-                        println!("{}", stdout);
+                return Ok(());
+            };
+            info!(
+                "🗑️  Zexio is executing uninstall command for {}: {}...",
+                package, cmd
+            );
+            let result = services::run_generic_command(&settings, &cmd).await;
+            audit::record_cli(
+                &settings,
+                "uninstall",
+                None,
+                serde_json::json!({ "service": package, "command": cmd, "recipe": command.is_none() }),
+                result.is_ok(),
+            )
+            .await;
+            match result {
+                Ok(()) => {
+                    info!("✅ Successfully executed uninstall for {}", package);
+                    let inventory =
+                        inventory::ServiceInventory::new(&settings.storage.services_path);
+                    if let Err(e) = inventory.record_uninstall(&package).await {
+                        error!("Failed to update services inventory: {}", e);
                     }
-                    Err(e) => error!("❌ Uninstallation failed: {}", e),
                 }
-            } else {
-                error!("❌ Error: No command provided for uninstallation. Use --command '...'");
+                Err(e) => error!("❌ Uninstallation failed: {}", e),
             }
             return Ok(());
         }
@@ -216,6 +408,54 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Start an install/uninstall job on the running agent and show its output live.
+async fn run_daemon_job(
+    client: &admin_socket::DaemonClient,
+    path: &str,
+    body: serde_json::Value,
+) -> anyhow::Result<()> {
+    let (status, body) = client
+        .request(axum::http::Method::POST, path, Some(body))
+        .await?;
+    if status != axum::http::StatusCode::ACCEPTED {
+        print_daemon_response(status, &body);
+        return Ok(());
+    }
+
+    let job: serde_json::Value = serde_json::from_str(&body)?;
+    let id = job["id"].as_str().unwrap_or_default();
+    info!("📋 Job {} started, streaming output...", id);
+
+    match jobs::follow(client, id).await? {
+        Some(job) if job["status"] == "succeeded" => {
+            info!("✅ Job {} succeeded", id);
+            if let Some(version) = job["version"].as_str() {
+                info!("📦 Installed: {}", version);
+            }
+        }
+        Some(job) => error!(
+            "❌ Job {} {}: {}",
+            id,
+            job["status"].as_str().unwrap_or("failed"),
+            job["error"].as_str().unwrap_or_default()
+        ),
+        None => error!("❌ Lost connection to job {}", id),
+    }
+    Ok(())
+}
+
+/// Print the result of a request forwarded to the running daemon.
+fn print_daemon_response(status: axum::http::StatusCode, body: &str) {
+    if status.is_success() {
+        info!("✅ Agent responded {}", status);
+    } else {
+        error!("❌ Agent responded {}", status);
+    }
+    if !body.is_empty() {
+        println!("{}", body);
+    }
+}
+
 fn print_banner() {
     println!("\n{}", "=".repeat(60));
     println!("  ____           _         ");
@@ -0,0 +1,192 @@
+use crate::config::AccessLogSettings;
+use crate::state::AppState;
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::{DateTime, Utc};
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, warn};
+
+/// Entries waiting for the file writer; more are dropped (and counted).
+const QUEUE: usize = 10_000;
+
+/// One proxied (or rejected) mesh request.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub host: String,
+    pub method: String,
+    pub path: String, // Without the query string, which may carry secrets
+    pub status: u16,  // 0 if no response was sent
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub upstream: Option<String>,
+    pub upstream_latency_ms: Option<u64>, // Until the upstream's response headers
+    pub total_latency_ms: u64,
+    pub org_id: Option<String>,
+    pub source_service: Option<String>,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Access log of the mesh: sampled entries go to a rotating file and to
+/// live subscribers.
+#[derive(Clone)]
+pub struct AccessLog {
+    sample_rate: f64,
+    file: Option<mpsc::Sender<Arc<AccessLogEntry>>>,
+    live: broadcast::Sender<Arc<AccessLogEntry>>,
+    dropped: Arc<AtomicU64>,
+    queue: Arc<Mutex<Option<mpsc::Receiver<Arc<AccessLogEntry>>>>>, // Taken by `writer`
+}
+
+impl AccessLog {
+    pub fn new(settings: &AccessLogSettings) -> Self {
+        let (file, queue) = if settings.enabled {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        Self {
+            sample_rate: settings.sample_rate.clamp(0.0, 1.0),
+            file,
+            live: broadcast::channel(1024).0,
+            dropped: Arc::new(AtomicU64::new(0)),
+            queue: Arc::new(Mutex::new(queue)),
+        }
+    }
+
+    /// Record `entry` if sampled. Server errors are always kept.
+    pub fn record(&self, entry: AccessLogEntry) {
+        if entry.status < 500 && !sampled(self.sample_rate) {
+            return;
+        }
+        let entry = Arc::new(entry);
+        let _ = self.live.send(entry.clone());
+        if let Some(file) = &self.file {
+            if file.try_send(entry).is_err() {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    warn!("Mesh access log is behind, {} entries dropped", dropped);
+                }
+            }
+        }
+    }
+}
+
+fn sampled(rate: f64) -> bool {
+    rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate)
+}
+
+/// Append entries to `path` as JSON lines. At `max_bytes` the file is
+/// rotated to `path.1`, `path.2`... keeping `keep` old files.
+pub async fn writer(log: AccessLog, settings: AccessLogSettings) {
+    let queue = log.queue.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let Some(mut rx) = queue else {
+        return; // File logging disabled, or already running
+    };
+    let mut file = match open(&settings.path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Mesh access log {} disabled: {}", settings.path, e);
+            return;
+        }
+    };
+
+    while let Some(entry) = rx.recv().await {
+        let Ok(mut line) = serde_json::to_vec(&*entry) else {
+            continue;
+        };
+        line.push(b'\n');
+        if let Err(e) = file.write_all(&line) {
+            error!("Failed to write mesh access log: {}", e);
+            continue;
+        }
+
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        if settings.max_bytes > 0 && size >= settings.max_bytes {
+            match rotate(&settings.path, settings.keep).and_then(|_| open(&settings.path)) {
+                Ok(reopened) => file = reopened,
+                Err(e) => error!("Failed to rotate mesh access log: {}", e),
+            }
+        }
+    }
+}
+
+fn open(path: &str) -> Result<File> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn rotate(path: &str, keep: usize) -> Result<()> {
+    if keep == 0 {
+        fs::remove_file(path)?;
+        return Ok(());
+    }
+    let _ = fs::remove_file(format!("{}.{}", path, keep));
+    for n in (1..keep).rev() {
+        let from = format!("{}.{}", path, n);
+        if Path::new(&from).exists() {
+            fs::rename(&from, format!("{}.{}", path, n + 1))?;
+        }
+    }
+    fs::rename(path, format!("{}.1", path))?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    host: Option<String>,
+    org_id: Option<String>,
+    sample: Option<f64>, // Further sampling of this stream, 0.0 to 1.0
+}
+
+/// Live access log as SSE: one `access` event (JSON entry) per request,
+/// optionally filtered by host or org. `lagged` reports skipped entries.
+pub async fn access_log_stream_handler(
+    State(state): State<AppState>,
+    Query(query): Query<StreamQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let mut rx = state.access_log.live.subscribe();
+    let host = query.host.map(|h| super::resolver::normalize_host(&h));
+    let sample = query.sample.unwrap_or(1.0);
+
+    let stream = async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(entry) => {
+                    if host.as_ref().is_some_and(|h| *h != entry.host)
+                        || query.org_id.as_ref().is_some_and(|o| Some(o) != entry.org_id.as_ref())
+                        || !sampled(sample)
+                    {
+                        continue;
+                    }
+                    yield Ok(Event::default()
+                        .event("access")
+                        .json_data(&*entry)
+                        .unwrap_or_default());
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    yield Ok(Event::default().event("lagged").data(skipped.to_string()));
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,488 @@
+use super::certs::{generate_key, CertManager, CertState};
+use super::resolver::normalize_host;
+use crate::config::{AcmeChallenge, AcmeSettings};
+use crate::crypto::Crypto;
+use crate::state::AppState;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// How often certificates are checked for renewal.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+/// Polling of authorizations and orders.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+/// `acme.account_path`
+#[derive(Serialize, Deserialize)]
+struct StoredAccount {
+    directory_url: String,
+    url: String, // Account URL, used as the JWS key id
+    key: String, // Hex-encoded PKCS#8 PEM, encrypted with the master key
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+    error: Option<Value>,
+}
+
+/// Minimal RFC 8555 client: ES256 account key, one DNS identifier per order.
+pub struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    key: KeyPair,
+    signer: EcdsaKeyPair, // `key`, for signing requests
+    account_url: Option<String>,
+    nonce: Option<String>,
+}
+
+fn b64(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+impl AcmeClient {
+    /// Fetch the directory and load, or register, the node's account.
+    pub async fn connect(settings: &AcmeSettings, crypto: &Crypto) -> Result<Self> {
+        let mut http = reqwest::Client::builder().user_agent("zexio-agent");
+        if let Some(path) = &settings.ca_bundle_path {
+            let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+            http = http.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        let http = http.build()?;
+
+        let directory: Directory = http
+            .get(&settings.directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid ACME directory")?;
+
+        let stored: Option<StoredAccount> = match std::fs::read_to_string(&settings.account_path) {
+            Ok(json) => Some(serde_json::from_str(&json)?),
+            Err(_) => None,
+        };
+        let (key, account_url) = match stored {
+            Some(account) if account.directory_url == settings.directory_url => {
+                match account_key(&account, crypto) {
+                    Ok(key) => (key, Some(account.url)),
+                    Err(e) => {
+                        // E.g. encrypted with a master key retired since:
+                        // register a new account rather than never renew again
+                        warn!(
+                            "ACME account key {} is unreadable ({}), registering a new account",
+                            settings.account_path, e
+                        );
+                        (generate_key()?, None)
+                    }
+                }
+            }
+            _ => (generate_key()?, None),
+        };
+
+        let mut client = Self {
+            http,
+            directory,
+            signer: signer(&key)?,
+            key,
+            account_url,
+            nonce: None,
+        };
+        if client.account_url.is_none() {
+            client.register(settings, crypto).await?;
+        }
+        Ok(client)
+    }
+
+    async fn register(&mut self, settings: &AcmeSettings, crypto: &Crypto) -> Result<()> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = &settings.contact_email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(payload)).await?;
+        let account_url = location(&response)?;
+
+        let stored = StoredAccount {
+            directory_url: settings.directory_url.clone(),
+            url: account_url.clone(),
+            key: hex::encode(crypto.encrypt(self.key.serialize_pem().as_bytes())?),
+        };
+        std::fs::write(
+            &settings.account_path,
+            serde_json::to_string_pretty(&stored)?,
+        )?;
+        info!("Registered ACME account {}", account_url);
+        self.account_url = Some(account_url);
+        Ok(())
+    }
+
+    fn jwk(&self) -> Result<Value> {
+        // Uncompressed point: 0x04 || x || y
+        let point = self.key.public_key_raw();
+        if point.len() != 65 || point[0] != 0x04 {
+            bail!("ACME account key is not a P-256 key");
+        }
+        Ok(json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(&point[1..33]),
+            "y": b64(&point[33..]),
+        }))
+    }
+
+    /// RFC 7638 thumbprint of the account key (members in lexicographic order).
+    fn thumbprint(&self) -> Result<String> {
+        let jwk = self.jwk()?;
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap_or_default(),
+            jwk["y"].as_str().unwrap_or_default()
+        );
+        Ok(b64(ring::digest::digest(
+            &ring::digest::SHA256,
+            canonical.as_bytes(),
+        )
+        .as_ref()))
+    }
+
+    async fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = self.http.head(&self.directory.new_nonce).send().await?;
+        response
+            .headers()
+            .get("replay-nonce")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("ACME server sent no nonce"))
+    }
+
+    /// Flattened JWS signed with ES256. `payload: None` is a POST-as-GET.
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Value> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.account_url {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk()?,
+        }
+        let protected = b64(serde_json::to_string(&protected)?.as_bytes());
+        let payload = match payload {
+            Some(value) => b64(serde_json::to_string(value)?.as_bytes()),
+            None => String::new(),
+        };
+
+        // Fixed-size r || s, as JWS wants it
+        let signature = self
+            .signer
+            .sign(
+                &SystemRandom::new(),
+                format!("{}.{}", protected, payload).as_bytes(),
+            )
+            .map_err(|_| anyhow!("Failed to sign ACME request"))?;
+
+        Ok(
+            json!({ "protected": protected, "payload": payload, "signature": b64(signature.as_ref()) }),
+        )
+    }
+
+    async fn post(&mut self, url: &str, payload: Option<Value>) -> Result<reqwest::Response> {
+        let mut retried = false;
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self.sign(url, &nonce, payload.as_ref())?;
+            let response = self
+                .http
+                .post(url)
+                .header("Content-Type", "application/jose+json")
+                .json(&body)
+                .send()
+                .await?;
+            self.nonce = response
+                .headers()
+                .get("replay-nonce")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let problem: Value = response.json().await.unwrap_or_default();
+            // A stale nonce is expected now and then; retry once with a fresh one
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            bail!(
+                "ACME request to {} failed: {}",
+                url,
+                problem["detail"].as_str().unwrap_or("unknown error")
+            );
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&mut self, url: &str) -> Result<T> {
+        Ok(self.post(url, None).await?.json().await?)
+    }
+
+    /// Order and download a certificate for `domain`. Returns the PEM chain
+    /// and its private key.
+    pub async fn issue(
+        &mut self,
+        certs: &CertManager,
+        domain: &str,
+        challenge: AcmeChallenge,
+    ) -> Result<(String, KeyPair)> {
+        let url = self.directory.new_order.clone();
+        let response = self
+            .post(
+                &url,
+                Some(json!({ "identifiers": [{ "type": "dns", "value": domain }] })),
+            )
+            .await?;
+        let order_url = location(&response)?;
+        let order: Order = response.json().await?;
+
+        for authz_url in &order.authorizations {
+            let authz: Authorization = self.get(authz_url).await?;
+            if authz.status == "valid" {
+                continue;
+            }
+            self.authorize(certs, domain, challenge, authz_url, authz)
+                .await?;
+        }
+
+        // Finalize with a CSR for a fresh key
+        let (csr, key) = csr(domain)?;
+        self.post(&order.finalize, Some(json!({ "csr": b64(&csr) })))
+            .await?;
+
+        let mut order = order;
+        for _ in 0..POLL_ATTEMPTS {
+            match order.status.as_str() {
+                "valid" => break,
+                "invalid" => bail!("ACME order for {} is invalid", domain),
+                _ => {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    order = self.get(&order_url).await?;
+                }
+            }
+        }
+        let certificate_url = order
+            .certificate
+            .ok_or_else(|| anyhow!("ACME order for {} did not complete", domain))?;
+        let chain = self.post(&certificate_url, None).await?.text().await?;
+        Ok((chain, key))
+    }
+
+    async fn authorize(
+        &mut self,
+        certs: &CertManager,
+        domain: &str,
+        kind: AcmeChallenge,
+        authz_url: &str,
+        authz: Authorization,
+    ) -> Result<()> {
+        let wanted = match kind {
+            AcmeChallenge::Http01 => "http-01",
+            AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+        };
+        let challenge = authz
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == wanted)
+            .ok_or_else(|| anyhow!("ACME server offered no {} challenge", wanted))?;
+        let key_authorization = format!("{}.{}", challenge.token, self.thumbprint()?);
+
+        match kind {
+            AcmeChallenge::Http01 => {
+                certs.set_http_challenge(&challenge.token, Some(key_authorization))
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                certs.set_alpn_challenge(domain, Some(&key_authorization))?
+            }
+        }
+        let result = self.validate(&challenge, authz_url).await;
+        match kind {
+            AcmeChallenge::Http01 => certs.set_http_challenge(&challenge.token, None),
+            AcmeChallenge::TlsAlpn01 => certs.set_alpn_challenge(domain, None)?,
+        }
+        result
+    }
+
+    async fn validate(&mut self, challenge: &Challenge, authz_url: &str) -> Result<()> {
+        self.post(&challenge.url, Some(json!({}))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let authz: Authorization = self.get(authz_url).await?;
+            match authz.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" | "processing" => continue,
+                status => {
+                    let detail = authz
+                        .challenges
+                        .iter()
+                        .find_map(|c| c.error.as_ref())
+                        .and_then(|e| e["detail"].as_str())
+                        .unwrap_or(status)
+                        .to_string();
+                    bail!("Challenge failed: {}", detail);
+                }
+            }
+        }
+        bail!("Challenge was not validated in time")
+    }
+}
+
+fn account_key(account: &StoredAccount, crypto: &Crypto) -> Result<KeyPair> {
+    let pem = crypto.decrypt(&hex::decode(&account.key)?)?;
+    Ok(KeyPair::from_pem(std::str::from_utf8(&pem)?)?)
+}
+
+fn signer(key: &KeyPair) -> Result<EcdsaKeyPair> {
+    EcdsaKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        &key.serialize_der(),
+        &SystemRandom::new(),
+    )
+    .map_err(|e| anyhow!("Unusable ACME account key: {}", e))
+}
+
+/// Re-encrypt the stored account key with the active master key, so it
+/// survives a master key rotation. Returns whether there was a key.
+pub fn reencrypt_account(settings: &AcmeSettings, crypto: &Crypto) -> Result<bool> {
+    let json = match std::fs::read_to_string(&settings.account_path) {
+        Ok(json) => json,
+        Err(_) => return Ok(false),
+    };
+    let mut account: StoredAccount = serde_json::from_str(&json)?;
+    let pem = crypto.decrypt(&hex::decode(&account.key)?)?;
+    account.key = hex::encode(crypto.encrypt(&pem)?);
+    std::fs::write(
+        &settings.account_path,
+        serde_json::to_string_pretty(&account)?,
+    )?;
+    Ok(true)
+}
+
+fn location(response: &reqwest::Response) -> Result<String> {
+    response
+        .headers()
+        .get("location")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("ACME server sent no Location header"))
+}
+
+/// DER CSR for `domain` and its new key.
+fn csr(domain: &str) -> Result<(Vec<u8>, KeyPair)> {
+    let mut params = CertificateParams::new(vec![domain.to_string()]);
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, domain);
+    params.key_pair = Some(generate_key()?);
+    let request = rcgen::Certificate::from_params(params)?;
+    let csr = request.serialize_request_der()?;
+    let key = KeyPair::from_der(&request.serialize_private_key_der())?;
+    Ok((csr, key))
+}
+
+/// Issue missing certificates, renew expiring ones and drop those of
+/// removed domains.
+async fn check_domains(state: &AppState) -> Result<()> {
+    let settings = &state.settings.acme;
+    let renew_before = chrono::Duration::days(settings.renew_before_days);
+    let mut client: Option<AcmeClient> = None;
+    let mut attached = HashSet::new();
+
+    for project in state.store.list().await? {
+        for domain in &project.domains {
+            let domain = normalize_host(domain);
+            attached.insert(domain.clone());
+            if !state.certs.needs_issuance(&domain, renew_before) {
+                continue;
+            }
+
+            let client = match &mut client {
+                Some(client) => client,
+                None => client.insert(AcmeClient::connect(settings, &state.crypto).await?),
+            };
+            info!("Requesting certificate for {}", domain);
+            state.certs.set_state(&domain, CertState::Pending, None);
+            let result = match client
+                .issue(&state.certs, &domain, settings.challenge)
+                .await
+            {
+                Ok((chain, key)) => {
+                    state
+                        .certs
+                        .store(&state.crypto, &domain, &project.id, &chain, &key)
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => info!("🔒 Certificate issued for {}", domain),
+                Err(e) => {
+                    warn!("Certificate for {} failed: {}", domain, e);
+                    state
+                        .certs
+                        .set_state(&domain, CertState::Failed, Some(e.to_string()));
+                }
+            }
+        }
+    }
+
+    for domain in state.certs.domains() {
+        if !attached.contains(&domain) {
+            state.certs.remove(&domain)?;
+            info!("Removed certificate of detached domain {}", domain);
+        }
+    }
+    Ok(())
+}
+
+/// Background task: keep custom domain certificates issued and renewed.
+pub async fn certificate_manager(state: AppState) {
+    loop {
+        if let Err(e) = check_domains(&state).await {
+            error!("Certificate check failed: {}", e);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+            _ = state.certs.woken() => {}
+        }
+    }
+}
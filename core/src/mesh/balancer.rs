@@ -0,0 +1,288 @@
+use super::resolver::{Route, Upstream};
+use crate::config::{Balance, HealthCheck, Resilience};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// Active health checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+/// Upstreams no route selected for this long are forgotten.
+const IDLE_AFTER: Duration = Duration::from_secs(600);
+
+/// Upstream selection and health, shared by all requests. Upstreams are
+/// tracked from their first selection on, since routes are resolved per
+/// request.
+#[derive(Clone, Default)]
+pub struct Balancer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    upstreams: HashMap<Upstream, UpstreamState>,
+    cursors: HashMap<String, usize>, // Round-robin position per service
+}
+
+/// Why `select` found no upstream.
+#[derive(Debug)]
+pub enum Unavailable {
+    NoUpstreams,
+    CircuitOpen { retry_after: Duration }, // Every upstream's circuit is open
+}
+
+struct UpstreamState {
+    active: usize,               // Requests in flight
+    failures: u32,               // Consecutive failed requests
+    open_until: Option<Instant>, // Circuit open: no traffic until then
+    half_open: bool,             // Trial after the circuit was open; one failure reopens it
+    healthy: bool,               // Last active check
+    check: Option<HealthCheck>,
+    breaker: (u32, Duration), // Failures opening the circuit, and for how long
+    last_used: Instant,
+}
+
+impl UpstreamState {
+    fn new() -> Self {
+        Self {
+            active: 0,
+            failures: 0,
+            open_until: None,
+            half_open: false,
+            healthy: true,
+            check: None,
+            breaker: (u32::MAX, Duration::ZERO),
+            last_used: Instant::now(),
+        }
+    }
+
+    fn closed(&self, now: Instant) -> bool {
+        self.open_until.is_none_or(|until| until <= now)
+    }
+}
+
+impl Balancer {
+    /// Pick an upstream of `route` and count the request as in flight until
+    /// `release`. Upstreams in `avoid` (already tried) and unhealthy ones
+    /// are skipped unless nothing else is left; upstreams with an open
+    /// circuit never get traffic. `hash_key` is used by consistent hashing.
+    pub fn select(
+        &self,
+        route: &Route,
+        hash_key: &str,
+        avoid: &[Upstream],
+    ) -> Result<Upstream, Unavailable> {
+        if route.upstreams.is_empty() {
+            return Err(Unavailable::NoUpstreams);
+        }
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        let closed: Vec<&Upstream> = route
+            .upstreams
+            .iter()
+            .filter(|u| inner.upstreams.get(*u).is_none_or(|s| s.closed(now)))
+            .collect();
+        if closed.is_empty() {
+            return Err(Unavailable::CircuitOpen {
+                retry_after: inner.retry_after(route, now),
+            });
+        }
+        let preferred: Vec<&Upstream> = closed
+            .iter()
+            .copied()
+            .filter(|u| !avoid.contains(u) && inner.upstreams.get(*u).is_none_or(|s| s.healthy))
+            .collect();
+        let candidates = if preferred.is_empty() {
+            debug!(
+                "No healthy upstream left for {}, trying all of them",
+                route.service
+            );
+            closed
+        } else {
+            preferred
+        };
+
+        let cursor = inner.cursors.entry(route.service.clone()).or_default();
+        let start = *cursor;
+        *cursor = cursor.wrapping_add(1);
+
+        let chosen = match route.lb.strategy {
+            Balance::RoundRobin => candidates[start % candidates.len()],
+            Balance::LeastConn => {
+                // Rotate first so ties don't always go to the same upstream
+                let n = candidates.len();
+                (0..n)
+                    .map(|i| candidates[(start + i) % n])
+                    .min_by_key(|u| inner.upstreams.get(*u).map_or(0, |s| s.active))
+                    .unwrap_or(candidates[0])
+            }
+            // Rendezvous hashing: only keys of a removed upstream move
+            Balance::ConsistentHash => candidates
+                .iter()
+                .copied()
+                .max_by_key(|u| {
+                    crc32fast::hash(format!("{}|{}:{}", hash_key, u.host, u.port).as_bytes())
+                })
+                .unwrap_or(candidates[0]),
+        }
+        .clone();
+
+        let state = inner
+            .upstreams
+            .entry(chosen.clone())
+            .or_insert_with(UpstreamState::new);
+        if state.open_until.take().is_some() {
+            state.half_open = true;
+        }
+        state.active += 1;
+        state.last_used = now;
+        state.check = route.lb.health_check.clone();
+        state.breaker = breaker(&route.resilience);
+        Ok(chosen)
+    }
+
+    /// `Some` with the time until the first circuit closes again when every
+    /// upstream of `route` has an open circuit.
+    pub fn circuit_open(&self, route: &Route) -> Option<Duration> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let all_open = !route.upstreams.is_empty()
+            && route
+                .upstreams
+                .iter()
+                .all(|u| inner.upstreams.get(u).is_some_and(|s| !s.closed(now)));
+        all_open.then(|| inner.retry_after(route, now))
+    }
+
+    /// End a request started by `select`. Consecutive failures open the
+    /// upstream's circuit; a success closes it.
+    pub fn release(&self, upstream: &Upstream, ok: bool) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(state) = inner.upstreams.get_mut(upstream) else {
+            return;
+        };
+        state.active = state.active.saturating_sub(1);
+        if ok {
+            state.failures = 0;
+            state.half_open = false;
+            return;
+        }
+        state.failures += 1;
+        let (max_failures, open_for) = state.breaker;
+        if state.half_open || state.failures >= max_failures {
+            state.failures = 0;
+            state.half_open = false;
+            state.open_until = Some(Instant::now() + open_for);
+            warn!(
+                "Circuit of upstream {}:{} open for {}s after consecutive failures",
+                upstream.host,
+                upstream.port,
+                open_for.as_secs()
+            );
+        }
+    }
+
+    /// Upstreams to check, dropping the idle ones.
+    fn checks_due(&self) -> Vec<(Upstream, HealthCheck)> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        inner
+            .upstreams
+            .retain(|_, s| s.active > 0 || now.duration_since(s.last_used) < IDLE_AFTER);
+        inner
+            .upstreams
+            .iter()
+            .filter_map(|(u, s)| s.check.clone().map(|check| (u.clone(), check)))
+            .collect()
+    }
+
+    fn set_healthy(&self, upstream: &Upstream, healthy: bool) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = inner.upstreams.get_mut(upstream) {
+            if state.healthy != healthy {
+                info!(
+                    "Upstream {}:{} is {}",
+                    upstream.host,
+                    upstream.port,
+                    if healthy {
+                        "healthy again"
+                    } else {
+                        "unhealthy"
+                    }
+                );
+            }
+            state.healthy = healthy;
+        }
+    }
+}
+
+impl Inner {
+    fn retry_after(&self, route: &Route, now: Instant) -> Duration {
+        route
+            .upstreams
+            .iter()
+            .filter_map(|u| self.upstreams.get(u)?.open_until)
+            .map(|until| until.saturating_duration_since(now))
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+fn breaker(resilience: &Resilience) -> (u32, Duration) {
+    (
+        resilience.breaker_failures.max(1),
+        Duration::from_secs(resilience.breaker_open_secs),
+    )
+}
+
+/// Run the active health checks of all tracked upstreams.
+pub async fn health_checker(balancer: Balancer) {
+    let http = match reqwest::Client::builder().timeout(CHECK_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("Mesh health checks disabled: {}", e);
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut checks = tokio::task::JoinSet::new();
+        for (upstream, check) in balancer.checks_due() {
+            let http = http.clone();
+            checks.spawn(async move {
+                let healthy = probe(&http, &upstream, &check).await;
+                (upstream, healthy)
+            });
+        }
+        while let Some(result) = checks.join_next().await {
+            if let Ok((upstream, healthy)) = result {
+                balancer.set_healthy(&upstream, healthy);
+            }
+        }
+    }
+}
+
+async fn probe(http: &reqwest::Client, upstream: &Upstream, check: &HealthCheck) -> bool {
+    match check {
+        HealthCheck::Tcp => matches!(
+            tokio::time::timeout(
+                CHECK_TIMEOUT,
+                TcpStream::connect((upstream.host.as_str(), upstream.port))
+            )
+            .await,
+            Ok(Ok(_))
+        ),
+        HealthCheck::Http { path } => {
+            let url = format!("http://{}:{}{}", upstream.host, upstream.port, path);
+            match http.get(url).send().await {
+                Ok(res) => res.status().is_success() || res.status().is_redirection(),
+                Err(_) => false,
+            }
+        }
+    }
+}
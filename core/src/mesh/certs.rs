@@ -0,0 +1,307 @@
+use crate::crypto::Crypto;
+use crate::tls::parse_chain;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, DnType, KeyPair};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// ALPN protocol of TLS-ALPN-01 validation requests (RFC 8737).
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// How long to wait before retrying a failed issuance.
+const RETRY_AFTER_FAILURE: Duration = Duration::hours(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CertState {
+    Pending, // Being issued
+    Issued,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CertStatus {
+    pub status: CertState,
+    pub issued_at: Option<DateTime<Utc>>,
+    pub not_after: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// `<certs_dir>/<domain>.json`. The key is encrypted with the data key of
+/// the project owning the domain.
+#[derive(Serialize, Deserialize)]
+struct StoredCert {
+    domain: String,
+    project_id: String,
+    chain_pem: String,
+    key: String, // Hex-encoded encrypted PKCS#8 PEM
+    issued_at: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Certs {
+    keys: HashMap<String, Arc<CertifiedKey>>, // Domain -> certificate chain and key
+    status: HashMap<String, CertStatus>,
+    http_challenges: HashMap<String, String>, // Token -> key authorization
+    alpn_challenges: HashMap<String, Arc<CertifiedKey>>, // Domain -> challenge certificate
+}
+
+/// Certificates of custom domains served by the mesh, plus pending ACME
+/// challenges.
+#[derive(Clone)]
+pub struct CertManager {
+    dir: PathBuf,
+    certs: Arc<RwLock<Certs>>,
+    wake: Arc<Notify>, // Domains changed; check issuance now
+}
+
+impl CertManager {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            certs: Arc::new(RwLock::new(Certs::default())),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Certs> {
+        self.certs.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Certs> {
+        self.certs.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn path(&self, domain: &str) -> Result<PathBuf> {
+        if domain.is_empty() || domain.starts_with('.') || domain.contains(['/', '\\']) {
+            bail!("Invalid domain {:?}", domain);
+        }
+        Ok(self.dir.join(format!("{}.json", domain)))
+    }
+
+    /// Load stored certificates. Unreadable ones are skipped (and reissued).
+    pub fn load(&self, crypto: &Crypto) -> Result<()> {
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Err(e) = self.load_file(crypto, &path) {
+                warn!("Skipping certificate {:?}: {}", path, e);
+            }
+        }
+        info!("Loaded {} mesh certificates", self.read().keys.len());
+        Ok(())
+    }
+
+    fn load_file(&self, crypto: &Crypto, path: &Path) -> Result<()> {
+        let stored: StoredCert = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let key_pem = crypto.decrypt_for(&stored.project_id, &hex::decode(&stored.key)?)?;
+        let key = KeyPair::from_pem(std::str::from_utf8(&key_pem)?)?;
+        let certified = certified_key(parse_chain(stored.chain_pem.as_bytes())?, &key)?;
+
+        let mut certs = self.write();
+        certs.keys.insert(stored.domain.clone(), certified);
+        certs.status.insert(
+            stored.domain,
+            CertStatus {
+                status: CertState::Issued,
+                issued_at: Some(stored.issued_at),
+                not_after: Some(stored.not_after),
+                error: None,
+                updated_at: stored.issued_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Save a newly issued certificate and start serving it.
+    pub fn store(
+        &self,
+        crypto: &Crypto,
+        domain: &str,
+        project_id: &str,
+        chain_pem: &str,
+        key: &KeyPair,
+    ) -> Result<()> {
+        let chain = parse_chain(chain_pem.as_bytes())?;
+        let not_after = not_after(&chain[0])?;
+        let certified = certified_key(chain, key)?;
+
+        let stored = StoredCert {
+            domain: domain.to_string(),
+            project_id: project_id.to_string(),
+            chain_pem: chain_pem.to_string(),
+            key: hex::encode(crypto.encrypt_for(project_id, key.serialize_pem().as_bytes())?),
+            issued_at: Utc::now(),
+            not_after,
+        };
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(domain)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&stored)?)?;
+        std::fs::rename(&tmp, &path)?;
+
+        let mut certs = self.write();
+        certs.keys.insert(domain.to_string(), certified);
+        certs.status.insert(
+            domain.to_string(),
+            CertStatus {
+                status: CertState::Issued,
+                issued_at: Some(stored.issued_at),
+                not_after: Some(not_after),
+                error: None,
+                updated_at: stored.issued_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Forget a domain's certificate (the domain was removed).
+    pub fn remove(&self, domain: &str) -> Result<()> {
+        {
+            let mut certs = self.write();
+            certs.keys.remove(domain);
+            certs.status.remove(domain);
+        }
+        let path = self.path(domain)?;
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Domains with a certificate or an issuance status.
+    pub fn domains(&self) -> Vec<String> {
+        self.read().status.keys().cloned().collect()
+    }
+
+    pub fn key(&self, domain: &str) -> Option<Arc<CertifiedKey>> {
+        self.read().keys.get(domain).cloned()
+    }
+
+    pub fn status(&self, domain: &str) -> Option<CertStatus> {
+        self.read().status.get(domain).cloned()
+    }
+
+    /// Whether `domain` needs a (new) certificate: none yet, expiring within
+    /// `renew_before`, or a failure long enough ago to retry.
+    pub fn needs_issuance(&self, domain: &str, renew_before: Duration) -> bool {
+        let now = Utc::now();
+        match self.read().status.get(domain) {
+            None => true,
+            Some(s) => match s.status {
+                CertState::Pending => false,
+                CertState::Failed => s.updated_at + RETRY_AFTER_FAILURE < now,
+                CertState::Issued => s.not_after.is_none_or(|t| t - renew_before < now),
+            },
+        }
+    }
+
+    /// Record the start or failure of an issuance. An existing certificate
+    /// keeps being served meanwhile.
+    pub fn set_state(&self, domain: &str, state: CertState, error: Option<String>) {
+        let mut certs = self.write();
+        let status = certs
+            .status
+            .entry(domain.to_string())
+            .or_insert(CertStatus {
+                status: state,
+                issued_at: None,
+                not_after: None,
+                error: None,
+                updated_at: Utc::now(),
+            });
+        status.status = state;
+        status.error = error;
+        status.updated_at = Utc::now();
+    }
+
+    pub fn http_challenge(&self, token: &str) -> Option<String> {
+        self.read().http_challenges.get(token).cloned()
+    }
+
+    pub fn alpn_challenge(&self, domain: &str) -> Option<Arc<CertifiedKey>> {
+        self.read().alpn_challenges.get(domain).cloned()
+    }
+
+    pub fn set_http_challenge(&self, token: &str, key_authorization: Option<String>) {
+        let mut certs = self.write();
+        match key_authorization {
+            Some(value) => certs.http_challenges.insert(token.to_string(), value),
+            None => certs.http_challenges.remove(token),
+        };
+    }
+
+    pub fn set_alpn_challenge(&self, domain: &str, key_authorization: Option<&str>) -> Result<()> {
+        let certified = key_authorization
+            .map(|value| alpn_challenge_cert(domain, value))
+            .transpose()?;
+        let mut certs = self.write();
+        match certified {
+            Some(certified) => certs.alpn_challenges.insert(domain.to_string(), certified),
+            None => certs.alpn_challenges.remove(domain),
+        };
+        Ok(())
+    }
+
+    /// Ask the certificate manager to check domains now.
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    pub async fn woken(&self) {
+        self.wake.notified().await;
+    }
+}
+
+fn not_after(cert: &Certificate) -> Result<DateTime<Utc>> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0)?;
+    Utc.timestamp_opt(cert.validity().not_after.timestamp(), 0)
+        .single()
+        .ok_or_else(|| anyhow!("Invalid certificate expiry"))
+}
+
+/// `chain` (leaf first) with its key, as served by rustls.
+fn certified_key(chain: Vec<Certificate>, key: &KeyPair) -> Result<Arc<CertifiedKey>> {
+    let signing_key = rustls::sign::any_supported_type(&PrivateKey(key.serialize_der()))
+        .context("Unsupported certificate key")?;
+    Ok(Arc::new(CertifiedKey::new(chain, signing_key)))
+}
+
+pub fn generate_key() -> Result<KeyPair> {
+    Ok(KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?)
+}
+
+/// Self-signed certificate answering a TLS-ALPN-01 challenge: `domain` as
+/// its only name and the key authorization digest in a critical
+/// `acmeIdentifier` extension.
+fn alpn_challenge_cert(domain: &str, key_authorization: &str) -> Result<Arc<CertifiedKey>> {
+    let mut params = CertificateParams::new(vec![domain.to_string()]);
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, domain);
+    let now = time::OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + time::Duration::days(7);
+
+    // id-pe-acmeIdentifier: OCTET STRING holding SHA-256(key authorization)
+    let digest = ring::digest::digest(&ring::digest::SHA256, key_authorization.as_bytes());
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(digest.as_ref())];
+    params.key_pair = Some(generate_key()?);
+    let cert = rcgen::Certificate::from_params(params)?;
+
+    let chain = vec![Certificate(cert.serialize_der()?)];
+    certified_key(chain, cert.get_key_pair())
+}
@@ -0,0 +1,147 @@
+use super::certs::{CertManager, ACME_TLS_ALPN};
+use super::resolver::normalize_host;
+use crate::state::AppState;
+use crate::tls::HANDSHAKE_TIMEOUT;
+use anyhow::{bail, Result};
+use rustls::server::{Acceptor, ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::LazyConfigAcceptor;
+use tracing::{debug, info, warn};
+
+/// Real clients of the connections this listener opens to the mesh, keyed
+/// by their local address, so the mesh can forward the client's address.
+#[derive(Clone, Default)]
+pub struct BridgedClients {
+    clients: Arc<RwLock<HashMap<SocketAddr, SocketAddr>>>,
+}
+
+impl BridgedClients {
+    /// Client behind a loopback connection from this listener.
+    pub fn get(&self, local: &SocketAddr) -> Option<SocketAddr> {
+        self.clients
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(local)
+            .copied()
+    }
+
+    fn insert(&self, local: SocketAddr, client: SocketAddr) {
+        self.clients
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(local, client);
+    }
+
+    fn remove(&self, local: &SocketAddr) {
+        self.clients
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(local);
+    }
+}
+
+/// Terminate TLS for custom domains on `server.mesh_tls_port`, picking the
+/// certificate by SNI, and hand the plaintext to the mesh on `mesh_port`.
+pub async fn serve(state: AppState) -> Result<()> {
+    let port = state.settings.server.mesh_tls_port;
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    let config = server_config(Arc::new(ByServerName(state.certs.clone())), b"http/1.1");
+    let mesh_port = state.settings.server.mesh_port;
+    info!("🔒 Mesh HTTPS listening on 0.0.0.0:{}", port);
+
+    loop {
+        let (tcp, remote) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Mesh HTTPS accept failed: {}", e);
+                continue;
+            }
+        };
+        let config = config.clone();
+        let certs = state.certs.clone();
+        let bridged = state.bridged.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(tcp, remote, config, certs, bridged, mesh_port).await {
+                debug!("Mesh HTTPS connection from {} ended: {}", remote, e);
+            }
+        });
+    }
+}
+
+fn server_config(resolver: Arc<dyn ResolvesServerCert>, alpn: &[u8]) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![alpn.to_vec()];
+    Arc::new(config)
+}
+
+/// Certificate of the requested custom domain.
+struct ByServerName(CertManager);
+
+impl ResolvesServerCert for ByServerName {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.0.key(&normalize_host(hello.server_name()?))
+    }
+}
+
+/// The certificate answering a TLS-ALPN-01 challenge.
+struct Challenge(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for Challenge {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+async fn handle(
+    tcp: TcpStream,
+    remote: SocketAddr,
+    config: Arc<ServerConfig>,
+    certs: CertManager,
+    bridged: BridgedClients,
+    mesh_port: u16,
+) -> Result<()> {
+    let handshake = async {
+        let start = LazyConfigAcceptor::new(Acceptor::default(), tcp).await?;
+        let hello = start.client_hello();
+
+        // TLS-ALPN-01 validation: present the challenge certificate and hang up
+        let challenge = hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN))
+            .then(|| hello.server_name().map(normalize_host))
+            .flatten()
+            .and_then(|name| certs.alpn_challenge(&name));
+        match challenge {
+            Some(key) => {
+                start
+                    .into_stream(server_config(Arc::new(Challenge(key)), ACME_TLS_ALPN))
+                    .await?;
+                Ok(None)
+            }
+            None => Ok::<_, std::io::Error>(Some(start.into_stream(config).await?)),
+        }
+    };
+    // Don't let idle connections hold a task forever
+    let Ok(stream) = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await else {
+        bail!("TLS handshake timed out");
+    };
+    let Some(mut stream) = stream? else {
+        return Ok(());
+    };
+
+    let mut mesh = TcpStream::connect(("127.0.0.1", mesh_port)).await?;
+    let local = mesh.local_addr()?;
+    bridged.insert(local, remote);
+    let result = tokio::io::copy_bidirectional(&mut stream, &mut mesh).await;
+    bridged.remove(&local);
+    result?;
+    Ok(())
+}
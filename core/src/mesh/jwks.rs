@@ -0,0 +1,162 @@
+use anyhow::{bail, Context, Result};
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Refreshes triggered by unknown key ids are at most this frequent.
+const MIN_REFRESH_GAP: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+enum Source {
+    Url(String, reqwest::Client),
+    File(String),
+}
+
+#[derive(Default)]
+struct Cached {
+    keys: HashMap<String, (DecodingKey, Algorithm)>, // By key id; "" when the set has one unnamed key
+    last_attempt: Option<Instant>,
+}
+
+/// Public keys verifying mesh tokens, fetched from a JWKS URL or read from
+/// a file. The cache is replaced wholesale on refresh, so keys removed from
+/// the set stop verifying tokens.
+#[derive(Clone)]
+pub struct Jwks {
+    source: Source,
+    cached: Arc<RwLock<Cached>>,
+}
+
+impl Jwks {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self::new(Source::Url(url.to_string(), http)))
+    }
+
+    pub fn from_file(path: &str) -> Self {
+        Self::new(Source::File(path.to_string()))
+    }
+
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            cached: Arc::new(RwLock::new(Cached::default())),
+        }
+    }
+
+    /// Key and algorithm for `kid`. An unknown id triggers a refresh, for
+    /// keys rotated in since the last one.
+    pub async fn key(&self, kid: Option<&str>) -> Option<(DecodingKey, Algorithm)> {
+        if let Some(key) = self.lookup(kid) {
+            return Some(key);
+        }
+        let due = {
+            let mut cached = self.cached.write().unwrap_or_else(|e| e.into_inner());
+            let due = cached
+                .last_attempt
+                .is_none_or(|t| t.elapsed() >= MIN_REFRESH_GAP);
+            if due {
+                cached.last_attempt = Some(Instant::now());
+            }
+            due
+        };
+        if due {
+            if let Err(e) = self.refresh().await {
+                warn!("Failed to refresh mesh JWKS: {}", e);
+            }
+        }
+        self.lookup(kid)
+    }
+
+    fn lookup(&self, kid: Option<&str>) -> Option<(DecodingKey, Algorithm)> {
+        let cached = self.cached.read().unwrap_or_else(|e| e.into_inner());
+        match kid {
+            Some(kid) => cached.keys.get(kid).cloned(),
+            // Tokens without a key id only work with a single-key set
+            None if cached.keys.len() == 1 => cached.keys.values().next().cloned(),
+            None => None,
+        }
+    }
+
+    /// Fetch the key set and replace the cache. On failure the previous
+    /// keys stay in use.
+    pub async fn refresh(&self) -> Result<()> {
+        let set: JwkSet = match &self.source {
+            Source::Url(url, http) => http
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .context("Invalid JWKS")?,
+            Source::File(path) => {
+                let data = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Failed to read JWKS {}", path))?;
+                serde_json::from_str(&data).context("Invalid JWKS")?
+            }
+        };
+
+        let mut keys = HashMap::new();
+        for jwk in &set.keys {
+            match decoding_key(jwk) {
+                Ok(key) => {
+                    keys.insert(jwk.common.key_id.clone().unwrap_or_default(), key);
+                }
+                Err(e) => warn!(
+                    "Skipping mesh JWKS key {}: {}",
+                    jwk.common.key_id.as_deref().unwrap_or("(no kid)"),
+                    e
+                ),
+            }
+        }
+        if keys.is_empty() {
+            bail!("JWKS has no usable keys");
+        }
+
+        let mut cached = self.cached.write().unwrap_or_else(|e| e.into_inner());
+        if cached.keys.len() != keys.len() || !keys.keys().all(|k| cached.keys.contains_key(k)) {
+            info!("Mesh JWKS loaded: {} keys", keys.len());
+        }
+        cached.keys = keys;
+        cached.last_attempt = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Only asymmetric keys: a shared secret in a key set would let every
+/// node mint tokens again.
+fn decoding_key(jwk: &Jwk) -> Result<(DecodingKey, Algorithm)> {
+    let algorithm = match (&jwk.common.key_algorithm, &jwk.algorithm) {
+        (Some(alg), _) => Algorithm::from_str(&alg.to_string())?,
+        (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+        (None, AlgorithmParameters::OctetKeyPair(_)) => Algorithm::EdDSA,
+        (None, AlgorithmParameters::EllipticCurve(_)) => Algorithm::ES256,
+        (None, AlgorithmParameters::OctetKey(_)) => bail!("symmetric key"),
+    };
+    if matches!(
+        algorithm,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        bail!("symmetric algorithm {:?}", algorithm);
+    }
+    Ok((DecodingKey::from_jwk(jwk)?, algorithm))
+}
+
+/// Refresh the key set every `interval`.
+pub async fn refresher(jwks: Jwks, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = jwks.refresh().await {
+            warn!("Failed to refresh mesh JWKS: {}", e);
+        }
+    }
+}
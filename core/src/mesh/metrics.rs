@@ -0,0 +1,68 @@
+use super::resolver::Route;
+use super::token::Denial;
+use crate::state::AppState;
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Services counted by name. Hosts come from unauthenticated requests, so
+/// any beyond this are counted together under `OTHER_SERVICES`.
+const MAX_SERVICES: usize = 256;
+const OTHER_SERVICES: &str = "(other)";
+
+/// Mesh counters since the agent started.
+#[derive(Clone, Default)]
+pub struct MeshMetrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Counters {
+    pub denied: BTreeMap<&'static str, u64>, // By reason
+    pub denied_by_service: BTreeMap<String, BTreeMap<&'static str, u64>>,
+}
+
+impl MeshMetrics {
+    /// Log and count a denied request.
+    pub fn deny(&self, host: &str, route: &Route, denial: &Denial) {
+        let reason = denial.reason.as_str();
+        warn!(
+            reason,
+            host,
+            service = %route.service,
+            owner_org = %route.owner_org,
+            source_service = denial.source_service.as_deref().unwrap_or("-"),
+            "Mesh request denied: {}",
+            denial.detail
+        );
+
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        *counters.denied.entry(reason).or_default() += 1;
+        let service = if counters.denied_by_service.contains_key(&route.service)
+            || counters.denied_by_service.len() < MAX_SERVICES
+        {
+            route.service.clone()
+        } else {
+            OTHER_SERVICES.to_string()
+        };
+        *counters
+            .denied_by_service
+            .entry(service)
+            .or_default()
+            .entry(reason)
+            .or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> Counters {
+        self.counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+pub async fn mesh_metrics_handler(State(state): State<AppState>) -> Json<Counters> {
+    Json(state.mesh_metrics.snapshot())
+}
@@ -1,5 +1,14 @@
+pub mod access_log;
+pub mod acme;
+pub mod balancer;
+pub mod certs;
 pub mod firewall;
+pub mod https;
+pub mod jwks;
+pub mod metrics;
 pub mod proxy;
+pub mod resolver;
+pub mod token;
 pub mod tunnel;
 pub mod zexio_mesh;
 
@@ -1,4 +1,6 @@
 #![allow(dead_code)]
+use super::balancer::Unavailable;
+use super::resolver::{normalize_host, ServiceResolver};
 use crate::state::AppState;
 use axum::{
     body::Body,
@@ -9,25 +11,8 @@ use axum::{
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use redis::AsyncCommands;
-use serde::{Deserialize, Serialize};
 use tracing::{debug, error};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ServiceTokenClaims {
-    #[serde(rename = "userId")]
-    user_id: String,
-    #[serde(rename = "orgId")]
-    org_id: String,
-    #[serde(rename = "sourceService")]
-    source_service: String,
-    #[serde(rename = "targetService")]
-    target_service: String,
-    #[serde(rename = "workerId")]
-    worker_id: Option<String>,
-}
-
 pub async fn mesh_proxy_handler(
     State(state): State<AppState>,
     mut req: Request,
@@ -36,59 +21,43 @@ pub async fn mesh_proxy_handler(
         .headers()
         .get(header::HOST)
         .and_then(|h| h.to_str().ok())
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .to_string(); // CLONE HERE to release immutable borrow of req
+        .map(normalize_host)
+        .ok_or(StatusCode::BAD_REQUEST)?;
 
     debug!("Mesh Proxy receiving request for host: {}", host);
 
     // --- 1. Resolve Host ---
-    let (target_host, target_port, target_org_id) = match resolve_mesh_dns(&state, &host).await {
-        Ok(res) => res,
-        Err(_) => return Err(StatusCode::NOT_FOUND),
+    let route = match state.resolver.resolve(&host).await {
+        Ok(Some(route)) => route,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Mesh resolution failed for {}: {}", host, e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
     };
 
-    // --- 2. Authenticaton Validation ---
-    // Extract JWT from Authorization header
+    // --- 2. Authentication Validation ---
     let auth_header = req
         .headers()
         .get(header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok());
 
-    let is_authorized = if let Some(auth_str) = auth_header {
-        if let Some(token) = auth_str.strip_prefix("Bearer ") {
-            let mut validation = Validation::new(Algorithm::HS256);
-            validation.set_issuer(&["zexio-service-mesh"]);
-
-            let decoding_key = DecodingKey::from_secret(state.mesh_jwt_secret.as_bytes());
-
-            match decode::<ServiceTokenClaims>(token, &decoding_key, &validation) {
-                Ok(token_data) => {
-                    let token_org_id = token_data.claims.org_id;
-                    debug!("Validated mesh token for org: {}", token_org_id);
-
-                    // CRITICAL: Strict Tenant Isolation Check
-                    // Only allow if token org matches target service org
-                    token_org_id == target_org_id
-                }
-                Err(e) => {
-                    error!("Mesh token validation failed: {}", e);
-                    false
-                }
-            }
-        } else {
-            false
-        }
-    } else {
-        false
-    };
-
-    if !is_authorized {
-        error!(
-            "Tenant Violation: Unauthorized access attempt to {} (Target Org: {})",
-            host, target_org_id
-        );
+    if let Err(denial) = state.mesh_auth.authorize(&route, auth_header).await {
+        state.mesh_metrics.deny(&host, &route, &denial);
         return Err(StatusCode::FORBIDDEN);
     }
+    let client_ip = req
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|info| info.0.ip().to_string())
+        .unwrap_or_default();
+    let upstream = state
+        .balancer
+        .select(&route, &client_ip, &[])
+        .map_err(|e| match e {
+            Unavailable::NoUpstreams => StatusCode::BAD_GATEWAY,
+            Unavailable::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        })?;
 
     // --- 3. Proxy the request ---
     let path_query = req
@@ -96,7 +65,7 @@ pub async fn mesh_proxy_handler(
         .path_and_query()
         .map(|v| v.as_str())
         .unwrap_or("/");
-    let target_uri = format!("http://{}:{}{}", target_host, target_port, path_query);
+    let target_uri = format!("http://{}:{}{}", upstream.host, upstream.port, path_query);
 
     debug!("Proxying {} to {}", host, target_uri);
 
@@ -106,7 +75,9 @@ pub async fn mesh_proxy_handler(
         .parse()
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    match client.request(req).await {
+    let result = client.request(req).await;
+    state.balancer.release(&upstream, result.is_ok());
+    match result {
         Ok(res) => Ok(res.into_response()),
         Err(e) => {
             error!("Proxy error for {}: {}", host, e);
@@ -118,65 +89,3 @@ pub async fn mesh_proxy_handler(
         }
     }
 }
-
-async fn resolve_mesh_dns(state: &AppState, host: &str) -> Result<(String, u16, String), ()> {
-    let host = host.split(':').next().unwrap_or(host);
-
-    // Format: {userId}.{serviceSlug}.zexio.internal
-    if host.ends_with(".zexio.internal") {
-        let parts: Vec<&str> = host.split('.').collect();
-
-        // If it's a namespaced service DNS (3 parts before zexio.internal)
-        if parts.len() >= 4 {
-            // Check Redis for global mapping
-            let mut conn = state
-                .redis
-                .get_multiplexed_async_connection()
-                .await
-                .map_err(|_| ())?;
-            let redis_key = format!("service:{}", host);
-            let service_info: std::collections::HashMap<String, String> =
-                conn.hgetall(redis_key).await.map_err(|_| ())?;
-
-            if let Some(target_ip) = service_info.get("worker_ip") {
-                let port = service_info
-                    .get("port")
-                    .and_then(|p| p.parse().ok())
-                    .unwrap_or(80);
-
-                let owner_id = service_info.get("owner_id").cloned().unwrap_or_default();
-
-                // If it's on this worker, use localhost
-                let is_on_this_worker = match &state.settings.server.public_ip {
-                    Some(ip) => target_ip == ip,
-                    None => target_ip == "127.0.0.1" || target_ip == "localhost",
-                };
-
-                if is_on_this_worker {
-                    return Ok(("127.0.0.1".to_string(), port, owner_id));
-                }
-
-                return Ok((target_ip.clone(), port, owner_id));
-            }
-        }
-
-        // Legacy format: [project-id].zexio.internal
-        let project_id = host.replace(".zexio.internal", "");
-        let port = 8000 + (crc32fast::hash(project_id.as_bytes()) % 1000) as u16;
-        return Ok(("127.0.0.1".to_string(), port, "".to_string())); // Default owner_id for legacy
-    }
-
-    // Handle .zexio.app (Wildcard)
-    if host.ends_with(".zexio.app") {
-        let prefix = host.replace(".zexio.app", "");
-        let project_id = if let Some(uuid) = prefix.split("--").last() {
-            uuid.to_string()
-        } else {
-            prefix
-        };
-        let port = 8000 + (crc32fast::hash(project_id.as_bytes()) % 1000) as u16;
-        return Ok(("127.0.0.1".to_string(), port, "".to_string()));
-    }
-
-    Err(())
-}
@@ -0,0 +1,555 @@
+use crate::config::{Balance, HealthCheck, LoadBalancing, Resilience, StaticRoute};
+use crate::storage::{project_port, ProjectStore};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Service-to-service names, `{userId}.{serviceSlug}.zexio.internal`.
+const INTERNAL_SUFFIX: &str = ".zexio.internal";
+/// Public wildcard names, `[{slug}--]{projectId}.zexio.app`.
+const APP_SUFFIX: &str = ".zexio.app";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Upstream {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoutePolicy {
+    ServiceToken, // Mesh JWT of the owning org required
+    Public,       // Anyone may call it
+}
+
+/// Where a host is proxied to and who may call it.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub service: String, // Registry name, project id or static host
+    pub upstreams: Vec<Upstream>,
+    pub owner_org: String, // Empty for routes without a known owner
+    pub tls: bool,
+    pub policy: RoutePolicy,
+    pub lb: LoadBalancing,
+    pub resilience: Resilience,
+    pub allowed_sources: Option<Vec<String>>, // `sourceService` allow-list; any of the org if unset
+}
+
+impl Route {
+    /// A project app on this node.
+    fn project(project_id: &str, policy: RoutePolicy) -> Self {
+        Self {
+            service: project_id.to_string(),
+            upstreams: vec![Upstream {
+                host: "127.0.0.1".to_string(),
+                port: project_port(project_id),
+            }],
+            owner_org: String::new(),
+            tls: false,
+            policy,
+            lb: LoadBalancing::default(),
+            resilience: Resilience::default(),
+            allowed_sources: None,
+        }
+    }
+}
+
+/// Maps a request host to a route. `host` is already normalized; `Ok(None)`
+/// means "not mine", so the next resolver of a chain is tried.
+#[async_trait]
+pub trait ServiceResolver: Send + Sync {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>>;
+}
+
+/// Lowercase `host` and strip its port and trailing dot. Handles
+/// bracketed IPv6 literals (`[::1]:8082`).
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Resolvers tried in order; the first match wins. Errors stop the chain,
+/// so an unreachable registry doesn't fall through to a wrong route.
+pub struct ChainResolver {
+    resolvers: Vec<Box<dyn ServiceResolver>>,
+}
+
+impl ChainResolver {
+    pub fn new(resolvers: Vec<Box<dyn ServiceResolver>>) -> Self {
+        Self { resolvers }
+    }
+
+    /// The mesh's resolution order: static config, project domains, the
+    /// Redis registry, then the legacy and wildcard names.
+    pub fn standard(
+        routes: HashMap<String, StaticRoute>,
+        domains: DomainTable,
+        redis: redis::Client,
+        public_ip: Option<String>,
+    ) -> Self {
+        Self::new(vec![
+            Box::new(StaticRoutes::new(routes)),
+            Box::new(CustomDomains { domains }),
+            Box::new(RedisRegistry { redis, public_ip }),
+            Box::new(LegacyInternal),
+            Box::new(WildcardApp),
+        ])
+    }
+}
+
+#[async_trait]
+impl ServiceResolver for ChainResolver {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>> {
+        for resolver in &self.resolvers {
+            if let Some(route) = resolver.resolve(host).await? {
+                return Ok(Some(route));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Routes from the `mesh.routes` config section.
+pub struct StaticRoutes {
+    routes: HashMap<String, StaticRoute>,
+}
+
+impl StaticRoutes {
+    pub fn new(routes: HashMap<String, StaticRoute>) -> Self {
+        let routes = routes
+            .into_iter()
+            .map(|(host, route)| (normalize_host(&host), route))
+            .collect();
+        Self { routes }
+    }
+}
+
+#[async_trait]
+impl ServiceResolver for StaticRoutes {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>> {
+        let Some(route) = self.routes.get(host) else {
+            return Ok(None);
+        };
+        let upstreams = route
+            .upstreams
+            .iter()
+            .map(|addr| parse_upstream(addr))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(Route {
+            service: host.to_string(),
+            upstreams,
+            owner_org: route.org_id.clone(),
+            tls: route.tls,
+            policy: if route.public {
+                RoutePolicy::Public
+            } else {
+                RoutePolicy::ServiceToken
+            },
+            lb: route.lb.clone(),
+            resilience: route.resilience.clone(),
+            allowed_sources: route.allowed_sources.clone(),
+        }))
+    }
+}
+
+/// `host:port`, with IPv6 hosts in brackets.
+fn parse_upstream(addr: &str) -> Result<Upstream> {
+    let (host, port) = addr
+        .trim()
+        .rsplit_once(':')
+        .with_context(|| format!("Upstream {} has no port", addr))?;
+    Ok(Upstream {
+        host: host.trim_matches(['[', ']']).to_string(),
+        port: port
+            .parse()
+            .with_context(|| format!("Invalid port in upstream {}", addr))?,
+    })
+}
+
+/// Custom domains of local projects, kept in memory. Reloaded from the
+/// project store at startup and whenever domains change.
+#[derive(Clone, Default)]
+pub struct DomainTable {
+    hosts: Arc<RwLock<HashMap<String, String>>>, // Host -> project id
+}
+
+impl DomainTable {
+    pub async fn reload(&self, store: &ProjectStore) -> Result<()> {
+        let mut hosts = HashMap::new();
+        for project in store.list().await? {
+            for domain in &project.domains {
+                let host = normalize_host(domain);
+                if let Some(other) = hosts.insert(host.clone(), project.id.clone()) {
+                    warn!(
+                        "Domain {} is attached to projects {} and {}",
+                        host, other, project.id
+                    );
+                }
+            }
+        }
+        info!("Mesh domain table: {} custom domains", hosts.len());
+        *self.hosts.write().unwrap_or_else(|e| e.into_inner()) = hosts;
+        Ok(())
+    }
+
+    /// All custom domains, host -> project id.
+    pub fn entries(&self) -> HashMap<String, String> {
+        self.hosts.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Project owning `host`, which must be normalized.
+    pub fn lookup(&self, host: &str) -> Option<String> {
+        self.hosts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(host)
+            .cloned()
+    }
+}
+
+/// Custom domains are public websites: no service token is required.
+pub struct CustomDomains {
+    pub domains: DomainTable,
+}
+
+#[async_trait]
+impl ServiceResolver for CustomDomains {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>> {
+        Ok(self
+            .domains
+            .lookup(host)
+            .map(|id| Route::project(&id, RoutePolicy::Public)))
+    }
+}
+
+/// Services registered in Redis as `service:{host}` hashes with
+/// `worker_ip`, `port` and `owner_id`. Services on several workers list
+/// them in `upstreams` (`ip:port,ip:port`) instead, and may set `lb`
+/// (`round_robin`, `least_conn`, `consistent_hash`), `hash_header` and
+/// `health_check` (`tcp` or `http:/path`). The `Resilience` fields
+/// (`connect_timeout_ms`, `retries`...) override the defaults, and
+/// `allowed_sources` (comma-separated) limits the calling services.
+pub struct RedisRegistry {
+    pub redis: redis::Client,
+    pub public_ip: Option<String>, // This node's address in the registry
+}
+
+#[async_trait]
+impl ServiceResolver for RedisRegistry {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>> {
+        // Namespaced names only: {userId}.{serviceSlug}.zexio.internal
+        if !host.ends_with(INTERNAL_SUFFIX) || host.split('.').count() < 4 {
+            return Ok(None);
+        }
+
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let info: HashMap<String, String> = conn.hgetall(format!("service:{}", host)).await?;
+
+        let addrs: Vec<String> = match (info.get("upstreams"), info.get("worker_ip")) {
+            (Some(list), _) => list
+                .split(',')
+                .filter(|a| !a.trim().is_empty())
+                .map(str::to_string)
+                .collect(),
+            (None, Some(worker_ip)) => {
+                let port = info.get("port").map(String::as_str).unwrap_or("80");
+                vec![format!("{}:{}", worker_ip, port)]
+            }
+            (None, None) => return Ok(None),
+        };
+        let upstreams = addrs
+            .iter()
+            .map(|addr| {
+                let mut upstream = parse_upstream(addr)?;
+                if self.on_this_worker(&upstream.host) {
+                    upstream.host = "127.0.0.1".to_string();
+                }
+                Ok(upstream)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(Route {
+            service: host.to_string(),
+            upstreams,
+            owner_org: info.get("owner_id").cloned().unwrap_or_default(),
+            tls: false,
+            policy: RoutePolicy::ServiceToken,
+            lb: LoadBalancing {
+                strategy: match info.get("lb").map(String::as_str) {
+                    Some("least_conn") => Balance::LeastConn,
+                    Some("consistent_hash") => Balance::ConsistentHash,
+                    _ => Balance::RoundRobin,
+                },
+                hash_header: info.get("hash_header").cloned(),
+                health_check: match info.get("health_check").map(String::as_str) {
+                    Some("tcp") => Some(HealthCheck::Tcp),
+                    Some(check) => check.strip_prefix("http").map(|path| HealthCheck::Http {
+                        path: match path.strip_prefix(':') {
+                            Some(path) if path.starts_with('/') => path.to_string(),
+                            _ => "/".to_string(),
+                        },
+                    }),
+                    None => None,
+                },
+            },
+            resilience: registry_resilience(&info),
+            allowed_sources: info.get("allowed_sources").map(|list| {
+                list.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            }),
+        }))
+    }
+}
+
+fn registry_resilience(info: &HashMap<String, String>) -> Resilience {
+    fn field<T: std::str::FromStr>(info: &HashMap<String, String>, key: &str, default: T) -> T {
+        info.get(key)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    }
+    let d = Resilience::default();
+    Resilience {
+        connect_timeout_ms: field(info, "connect_timeout_ms", d.connect_timeout_ms),
+        read_timeout_ms: field(info, "read_timeout_ms", d.read_timeout_ms),
+        total_timeout_ms: field(info, "total_timeout_ms", d.total_timeout_ms),
+        retries: field(info, "retries", d.retries),
+        breaker_failures: field(info, "breaker_failures", d.breaker_failures),
+        breaker_open_secs: field(info, "breaker_open_secs", d.breaker_open_secs),
+    }
+}
+
+impl RedisRegistry {
+    fn on_this_worker(&self, worker_ip: &str) -> bool {
+        match &self.public_ip {
+            Some(ip) => worker_ip == ip,
+            None => worker_ip == "127.0.0.1" || worker_ip == "localhost",
+        }
+    }
+}
+
+/// Legacy `{projectId}.zexio.internal` names for local projects.
+pub struct LegacyInternal;
+
+#[async_trait]
+impl ServiceResolver for LegacyInternal {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>> {
+        Ok(host
+            .strip_suffix(INTERNAL_SUFFIX)
+            .filter(|id| !id.is_empty())
+            .map(|id| Route::project(id, RoutePolicy::ServiceToken)))
+    }
+}
+
+/// `[{slug}--]{projectId}.zexio.app` for local projects.
+pub struct WildcardApp;
+
+#[async_trait]
+impl ServiceResolver for WildcardApp {
+    async fn resolve(&self, host: &str) -> Result<Option<Route>> {
+        Ok(host
+            .strip_suffix(APP_SUFFIX)
+            .and_then(|prefix| prefix.rsplit("--").next())
+            .filter(|id| !id.is_empty())
+            .map(|id| Route::project(id, RoutePolicy::ServiceToken)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn normalize_host_strips_port_and_trailing_dot() {
+        assert_eq!(normalize_host("Example.COM"), "example.com");
+        assert_eq!(normalize_host("example.com:8080"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("example.com.:443"), "example.com");
+        assert_eq!(normalize_host(" example.com "), "example.com");
+    }
+
+    #[test]
+    fn normalize_host_handles_ipv6_literals() {
+        assert_eq!(normalize_host("[::1]:8082"), "::1");
+        assert_eq!(normalize_host("[2001:DB8::1]"), "2001:db8::1");
+        assert_eq!(normalize_host("127.0.0.1:80"), "127.0.0.1");
+    }
+
+    #[test]
+    fn parse_upstream_accepts_host_and_port() -> Result<()> {
+        let upstream = parse_upstream(" 10.0.0.5:8080 ")?;
+        assert_eq!(upstream.host, "10.0.0.5");
+        assert_eq!(upstream.port, 8080);
+
+        let upstream = parse_upstream("[fd00::5]:9000")?;
+        assert_eq!(upstream.host, "fd00::5");
+        assert_eq!(upstream.port, 9000);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_upstream_rejects_missing_or_invalid_ports() {
+        assert!(parse_upstream("10.0.0.5").is_err());
+        assert!(parse_upstream("10.0.0.5:http").is_err());
+        assert!(parse_upstream("10.0.0.5:70000").is_err());
+    }
+
+    fn static_route(value: serde_json::Value) -> Result<StaticRoute> {
+        Ok(serde_json::from_value(value)?)
+    }
+
+    #[tokio::test]
+    async fn static_routes_match_normalized_hosts() -> Result<()> {
+        let routes = StaticRoutes::new(HashMap::from([
+            (
+                "API.Example.com.".to_string(),
+                static_route(serde_json::json!({
+                    "upstreams": ["10.0.0.5:8080", "10.0.0.6:8080"],
+                    "org_id": "org_1",
+                }))?,
+            ),
+            (
+                "www.example.com".to_string(),
+                static_route(serde_json::json!({ "upstreams": ["10.0.0.7:80"], "public": true }))?,
+            ),
+        ]));
+
+        let route = routes
+            .resolve("api.example.com")
+            .await?
+            .context("no route")?;
+        assert_eq!(route.service, "api.example.com");
+        assert_eq!(route.upstreams.len(), 2);
+        assert_eq!(route.owner_org, "org_1");
+        assert_eq!(route.policy, RoutePolicy::ServiceToken);
+
+        let route = routes
+            .resolve("www.example.com")
+            .await?
+            .context("no route")?;
+        assert_eq!(route.policy, RoutePolicy::Public);
+
+        assert!(routes.resolve("other.example.com").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn static_routes_fail_on_invalid_upstreams() -> Result<()> {
+        let routes = StaticRoutes::new(HashMap::from([(
+            "api.example.com".to_string(),
+            static_route(serde_json::json!({ "upstreams": ["10.0.0.5"] }))?,
+        )]));
+        assert!(routes.resolve("api.example.com").await.is_err());
+        Ok(())
+    }
+
+    async fn wildcard(host: &str) -> Result<Option<String>> {
+        Ok(WildcardApp.resolve(host).await?.map(|route| route.service))
+    }
+
+    #[tokio::test]
+    async fn wildcard_app_takes_the_project_after_the_last_separator() -> Result<()> {
+        assert_eq!(wildcard("proj1.zexio.app").await?.as_deref(), Some("proj1"));
+        assert_eq!(
+            wildcard("web--proj1.zexio.app").await?.as_deref(),
+            Some("proj1")
+        );
+        assert_eq!(
+            wildcard("my--web--proj1.zexio.app").await?.as_deref(),
+            Some("proj1")
+        );
+
+        let route = WildcardApp
+            .resolve("web--proj1.zexio.app")
+            .await?
+            .context("no route")?;
+        assert_eq!(route.upstreams[0].port, project_port("proj1"));
+        assert_eq!(route.policy, RoutePolicy::ServiceToken);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wildcard_app_ignores_empty_and_foreign_names() -> Result<()> {
+        assert_eq!(wildcard("web--.zexio.app").await?, None);
+        assert_eq!(wildcard(".zexio.app").await?, None);
+        assert_eq!(wildcard("zexio.app").await?, None);
+        assert_eq!(wildcard("proj1.example.com").await?, None);
+        Ok(())
+    }
+
+    /// Answers every host with a fixed outcome and counts its calls.
+    struct Fixed {
+        outcome: Option<std::result::Result<&'static str, &'static str>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Fixed {
+        fn boxed(
+            outcome: Option<std::result::Result<&'static str, &'static str>>,
+        ) -> (Box<dyn ServiceResolver>, Arc<AtomicUsize>) {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let resolver = Self {
+                outcome,
+                calls: calls.clone(),
+            };
+            (Box::new(resolver), calls)
+        }
+    }
+
+    #[async_trait]
+    impl ServiceResolver for Fixed {
+        async fn resolve(&self, _host: &str) -> Result<Option<Route>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.outcome {
+                Some(Ok(service)) => Ok(Some(Route::project(service, RoutePolicy::Public))),
+                Some(Err(message)) => Err(anyhow::anyhow!(message)),
+                None => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_returns_the_first_match() -> Result<()> {
+        let (miss, miss_calls) = Fixed::boxed(None);
+        let (first, _) = Fixed::boxed(Some(Ok("first")));
+        let (second, second_calls) = Fixed::boxed(Some(Ok("second")));
+        let chain = ChainResolver::new(vec![miss, first, second]);
+
+        let route = chain.resolve("any.host").await?.context("no route")?;
+        assert_eq!(route.service, "first");
+        assert_eq!(miss_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chain_stops_at_the_first_error() {
+        let (failing, _) = Fixed::boxed(Some(Err("registry unreachable")));
+        let (fallback, fallback_calls) = Fixed::boxed(Some(Ok("fallback")));
+        let chain = ChainResolver::new(vec![failing, fallback]);
+
+        assert!(chain.resolve("any.host").await.is_err());
+        assert_eq!(fallback_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn chain_without_match_returns_none() -> Result<()> {
+        let (miss, _) = Fixed::boxed(None);
+        let chain = ChainResolver::new(vec![miss]);
+        assert!(chain.resolve("any.host").await?.is_none());
+        assert!(ChainResolver::new(Vec::new())
+            .resolve("any.host")
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use super::jwks::Jwks;
+use super::resolver::{Route, RoutePolicy};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+
+/// Issuer of service-to-service tokens.
+const ISSUER: &str = "zexio-service-mesh";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceTokenClaims {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "orgId")]
+    pub org_id: String,
+    #[serde(rename = "sourceService")]
+    pub source_service: String,
+    #[serde(rename = "targetService")]
+    pub target_service: String,
+    #[serde(rename = "workerId")]
+    pub worker_id: Option<String>,
+}
+
+/// Keys mesh tokens are verified with.
+#[derive(Clone)]
+pub enum MeshKeys {
+    Jwks(Jwks),           // RS256/EdDSA public keys; nodes can't mint tokens
+    SharedSecret(String), // HS256 (`MESH_JWT_SECRET`), standalone nodes
+}
+
+/// Verifies service tokens and checks them against routes.
+#[derive(Clone)]
+pub struct MeshAuth {
+    pub keys: MeshKeys,
+    pub audience: String,
+}
+
+/// Why a request was denied; the label used in logs and metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DenyReason {
+    MissingToken,
+    InvalidToken, // Bad signature, issuer, audience or expired
+    UnknownKey,   // No JWKS key for the token's `kid`
+    OrgMismatch,
+    TargetMismatch,
+    SourceNotAllowed,
+}
+
+impl DenyReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DenyReason::MissingToken => "missing_token",
+            DenyReason::InvalidToken => "invalid_token",
+            DenyReason::UnknownKey => "unknown_key",
+            DenyReason::OrgMismatch => "org_mismatch",
+            DenyReason::TargetMismatch => "target_mismatch",
+            DenyReason::SourceNotAllowed => "source_not_allowed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Denial {
+    pub reason: DenyReason,
+    pub detail: String,
+    pub source_service: Option<String>, // When the token could be read
+}
+
+impl Denial {
+    fn new(reason: DenyReason, detail: String) -> Self {
+        Self {
+            reason,
+            detail,
+            source_service: None,
+        }
+    }
+
+    fn by(claims: &ServiceTokenClaims, reason: DenyReason, detail: String) -> Self {
+        Self {
+            reason,
+            detail,
+            source_service: Some(claims.source_service.clone()),
+        }
+    }
+}
+
+impl MeshAuth {
+    /// Verify a `Bearer` service token: signature, mesh issuer, audience
+    /// and expiry.
+    pub async fn verify(&self, authorization: Option<&str>) -> Result<ServiceTokenClaims, Denial> {
+        let invalid = |e: jsonwebtoken::errors::Error| {
+            Denial::new(
+                DenyReason::InvalidToken,
+                format!("Invalid service token: {}", e),
+            )
+        };
+        let token = authorization
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .ok_or_else(|| Denial::new(DenyReason::MissingToken, "Missing service token".into()))?;
+
+        let (key, algorithm) = match &self.keys {
+            MeshKeys::SharedSecret(secret) => (
+                DecodingKey::from_secret(secret.as_bytes()),
+                Algorithm::HS256,
+            ),
+            MeshKeys::Jwks(jwks) => {
+                let header = decode_header(token).map_err(invalid)?;
+                jwks.key(header.kid.as_deref()).await.ok_or_else(|| {
+                    Denial::new(
+                        DenyReason::UnknownKey,
+                        format!(
+                            "Unknown service token key {}",
+                            header.kid.as_deref().unwrap_or("(no kid)")
+                        ),
+                    )
+                })?
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[ISSUER]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+        decode::<ServiceTokenClaims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(invalid)
+    }
+
+    /// Check a request against the route's policy. Returns the verified
+    /// claims, if a token was required, or the reason for denying the request.
+    pub async fn authorize(
+        &self,
+        route: &Route,
+        authorization: Option<&str>,
+    ) -> Result<Option<ServiceTokenClaims>, Denial> {
+        match route.policy {
+            RoutePolicy::Public => Ok(None),
+            RoutePolicy::ServiceToken => {
+                let claims = self.verify(authorization).await?;
+                // Strict tenant isolation: the token's org must own the target
+                if claims.org_id != route.owner_org {
+                    return Err(Denial::by(
+                        &claims,
+                        DenyReason::OrgMismatch,
+                        format!("Token org {} does not own {}", claims.org_id, route.service),
+                    ));
+                }
+                // A token is only good for the service it was issued for
+                if claims.target_service != route.service {
+                    return Err(Denial::by(
+                        &claims,
+                        DenyReason::TargetMismatch,
+                        format!(
+                            "Token is for {}, not {}",
+                            claims.target_service, route.service
+                        ),
+                    ));
+                }
+                // Services may limit which of the org's services call them
+                if let Some(allowed) = &route.allowed_sources {
+                    if !allowed.contains(&claims.source_service) {
+                        return Err(Denial::by(
+                            &claims,
+                            DenyReason::SourceNotAllowed,
+                            format!("{} may not call {}", claims.source_service, route.service),
+                        ));
+                    }
+                }
+                Ok(Some(claims))
+            }
+        }
+    }
+}
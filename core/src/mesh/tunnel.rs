@@ -1,9 +1,9 @@
 use crate::config::Settings;
 use crate::mesh::node_sync::{
-    node_sync_service_client::NodeSyncServiceClient, NodeConnectionRequest, NodeStatsRequest,
-    ServiceStatus, TunnelPacket,
+    node_sync_service_client::NodeSyncServiceClient, InstalledService, NodeConnectionRequest,
+    NodeStatsRequest, ServiceStatus, TunnelPacket,
 };
-use crate::storage::ProjectStore; // Added import
+use crate::storage::{project_port, ProjectStore}; // Added import
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
@@ -27,6 +27,20 @@ pub async fn start_tunnel_client(
     let relay_url =
         std::env::var("RELAY_URL").unwrap_or_else(|_| "http://127.0.0.1:50051".to_string());
 
+    // Reported on connect and with every heartbeat, so the cloud always has
+    // the current key
+    let public_key = match crate::sealed::NodeKey::load_or_generate(&settings.secrets.node_key_path)
+    {
+        Ok(key) => key.public_key().to_string(),
+        Err(e) => {
+            error!(
+                "Failed to load node key, sealed secrets will not work: {:#}",
+                e
+            );
+            String::new()
+        }
+    };
+
     // Retry Loop
     loop {
         info!(
@@ -53,6 +67,7 @@ pub async fn start_tunnel_client(
                     auth_token,
                     os_type: os_info,
                     version: env!("CARGO_PKG_VERSION").to_string(),
+                    public_key: public_key.clone(),
                 });
 
                 match client.on_connect(auth_req).await {
@@ -70,9 +85,13 @@ pub async fn start_tunnel_client(
                         let mut stats_client = client.clone();
                         let node_id_stats = node_id.clone();
                         let settings_for_stats = settings.clone();
+                        let public_key_stats = public_key.clone();
 
                         // We abort this task if we disconnect, to avoid zombie stats
                         let heartbeat_handle = tokio::spawn(async move {
+                            let inventory = crate::inventory::ServiceInventory::new(
+                                &settings_for_stats.storage.services_path,
+                            );
                             let project_store =
                                 ProjectStore::new(settings_for_stats.storage.projects_dir);
                             let mut sys = System::new_all();
@@ -85,7 +104,7 @@ pub async fn start_tunnel_client(
                                     let mut service_statuses = Vec::new();
                                     if let Ok(projects) = project_store.list().await {
                                         for p in projects {
-                                            let port = 8000 + (crc32fast::hash(p.id.as_bytes()) % 1000) as u16;
+                                            let port = project_port(&p.id);
                                             let target = format!("127.0.0.1:{}", port);
 
                                             let start = std::time::Instant::now();
@@ -104,6 +123,24 @@ pub async fn start_tunnel_client(
                                         }
                                     }
 
+                                    // Services installed through the agent, with their last health check
+                                    let installed_services = inventory
+                                        .list()
+                                        .unwrap_or_default()
+                                        .into_iter()
+                                        .map(|s| InstalledService {
+                                            name: s.name,
+                                            version: s.version.unwrap_or_default(),
+                                            recipe: s.recipe.unwrap_or_default(),
+                                            unit: s.unit.unwrap_or_default(),
+                                            ports: s.ports.into_iter().map(u32::from).collect(),
+                                            health: s.health.as_str().to_string(),
+                                            health_message: s.health_message.unwrap_or_default(),
+                                            installed_at: Some(std::time::SystemTime::from(s.installed_at).into()),
+                                            checked_at: s.checked_at.map(|t| std::time::SystemTime::from(t).into()),
+                                        })
+                                        .collect();
+
                                     // Calculate Resource Usage
                                     let cpu_usage = sys.global_cpu_info().cpu_usage();
                                     let memory_usage = (sys.used_memory() as f32 / sys.total_memory() as f32) * 100.0;
@@ -116,6 +153,8 @@ pub async fn start_tunnel_client(
                                         disk_usage: 0.0,
                                         timestamp: Some(std::time::SystemTime::now().into()),
                                         services: service_statuses,
+                                        installed_services,
+                                        public_key: public_key_stats.clone(),
                                     };
                                     tokio::time::sleep(Duration::from_secs(5)).await;
                                 }
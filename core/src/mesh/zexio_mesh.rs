@@ -1,67 +1,133 @@
+use super::access_log::AccessLogEntry;
+use super::balancer::Unavailable;
+use super::resolver::{normalize_host, Route, ServiceResolver, Upstream};
+use super::token::ServiceTokenClaims;
 use crate::state::AppState;
 use async_trait::async_trait;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use bytes::Bytes;
+use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::prelude::*;
-use redis::AsyncCommands;
-use serde::{Deserialize, Serialize};
-
-use tracing::{debug, error, info};
+use pingora::proxy::FailToProxy;
+use pingora::{ErrorSource, ErrorType::HTTPStatus};
+use rand::RngCore;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
 
 pub struct ZexioMeshLogic {
     pub state: AppState,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ServiceTokenClaims {
-    #[serde(rename = "userId")]
-    user_id: String,
-    #[serde(rename = "orgId")]
-    org_id: String,
-    #[serde(rename = "sourceService")]
-    source_service: String,
-    #[serde(rename = "targetService")]
-    target_service: String,
-    #[serde(rename = "workerId")]
-    worker_id: Option<String>,
+/// Per-request state; `mesh` is set once the request is routed and allowed.
+pub struct RequestContext {
+    pub started: Instant,
+    pub host: String,
+    pub mesh: Option<MeshContext>,
 }
 
 pub struct MeshContext {
-    pub target_host: String,
-    pub target_port: u16,
-    #[allow(dead_code)]
-    pub target_org_id: String,
+    pub route: Route,
+    pub upstream: Option<Upstream>, // In flight, released in `logging`
+    pub tried: Vec<Upstream>,       // Upstreams attempted so far
+    pub deadline: Option<Instant>,  // From the route's total timeout
+    pub retry_after: Option<Duration>, // Set when failing fast on open circuits
+    pub claims: Option<ServiceTokenClaims>, // Verified caller, for identity headers
+    pub request_id: String,
+    pub upstream_started: Option<Instant>,  // Current attempt
+    pub upstream_latency: Option<Duration>, // Until the upstream's response headers
+}
+
+impl MeshContext {
+    /// Another attempt is allowed by the route's retry budget and deadline.
+    fn may_retry(&self) -> bool {
+        self.tried.len() <= self.route.resilience.retries
+            && self.deadline.is_none_or(|d| Instant::now() < d)
+    }
+
+    /// `timeout`, shortened to what's left of the total budget.
+    fn clamp(&self, timeout: Duration) -> Duration {
+        match self.deadline {
+            Some(deadline) => timeout.min(deadline.saturating_duration_since(Instant::now())),
+            None => timeout,
+        }
+    }
+}
+
+impl ZexioMeshLogic {
+    /// The client's address and whether it connected over TLS. Connections
+    /// from the mesh HTTPS listener are mapped back to its client.
+    fn client(&self, session: &Session) -> (Option<IpAddr>, bool) {
+        let Some(addr) = session.client_addr().and_then(|a| a.as_inet()).copied() else {
+            return (None, false);
+        };
+        if addr.ip().is_loopback() {
+            if let Some(client) = self.state.bridged.get(&addr) {
+                return (Some(client.ip()), true);
+            }
+        }
+        (Some(addr.ip()), false)
+    }
+
+    /// Finish the request on the current upstream, if any.
+    fn release(&self, ctx: &mut RequestContext, ok: bool) {
+        if let Some(upstream) = ctx.mesh.as_mut().and_then(|c| c.upstream.take()) {
+            self.state.balancer.release(&upstream, ok);
+        }
+    }
 }
 
 #[async_trait]
 impl ProxyHttp for ZexioMeshLogic {
-    type CTX = Option<MeshContext>;
+    type CTX = RequestContext;
 
     fn new_ctx(&self) -> Self::CTX {
-        None
+        RequestContext {
+            started: Instant::now(),
+            host: String::new(),
+            mesh: None,
+        }
     }
 
     async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
-        let host_header = session
+        let host = session
             .req_header()
             .headers
             .get("Host")
-            .map(|v| v.to_str().unwrap_or(""))
-            .unwrap_or("");
-        let host = host_header
-            .split(':')
-            .next()
-            .unwrap_or(host_header)
-            .to_string();
+            .map(|v| normalize_host(v.to_str().unwrap_or("")))
+            .unwrap_or_default();
 
         debug!("ZexioMesh: Receiving request for host: {}", host);
+        ctx.host = host.clone();
+
+        // 0. ACME HTTP-01 validation for custom domain certificates
+        let path = session.req_header().uri.path();
+        if let Some(token) = path.strip_prefix("/.well-known/acme-challenge/") {
+            if let Some(key_authorization) = self.state.certs.http_challenge(token) {
+                let mut response = ResponseHeader::build(200, None)?;
+                response.insert_header("Content-Type", "text/plain")?;
+                response.insert_header("Content-Length", key_authorization.len().to_string())?;
+                session
+                    .write_response_header(Box::new(response), false)
+                    .await?;
+                session
+                    .write_response_body(Some(Bytes::from(key_authorization)), true)
+                    .await?;
+                return Ok(true);
+            }
+        }
 
         // 1. Resolve Host
-        let (target_host, target_port, target_org_id) = match self.resolve_mesh_dns(&host).await {
-            Ok(res) => res,
-            Err(_) => {
+        let route = match self.state.resolver.resolve(&host).await {
+            Ok(Some(route)) => route,
+            Ok(None) => {
                 let _ = session.respond_error(404).await;
                 return Ok(true); // Handled
             }
+            Err(e) => {
+                error!("Mesh resolution failed for {}: {}", host, e);
+                let _ = session.respond_error(502).await;
+                return Ok(true);
+            }
         };
 
         // 2. Auth Validation
@@ -69,40 +135,36 @@ impl ProxyHttp for ZexioMeshLogic {
             .req_header()
             .headers
             .get("Authorization")
-            .map(|v| v.to_str().unwrap_or(""));
+            .and_then(|v| v.to_str().ok());
 
-        let is_authorized = if let Some(auth_str) = auth_header {
-            if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                let mut validation = Validation::new(Algorithm::HS256);
-                validation.set_issuer(&["zexio-service-mesh"]);
-
-                let decoding_key = DecodingKey::from_secret(self.state.mesh_jwt_secret.as_bytes());
-
-                match decode::<ServiceTokenClaims>(token, &decoding_key, &validation) {
-                    Ok(token_data) => token_data.claims.org_id == target_org_id,
-                    Err(e) => {
-                        error!("Mesh token validation failed: {}", e);
-                        false
-                    }
-                }
-            } else {
-                false
+        let claims = match self.state.mesh_auth.authorize(&route, auth_header).await {
+            Ok(claims) => claims,
+            Err(denial) => {
+                self.state.mesh_metrics.deny(&host, &route, &denial);
+                let _ = session.respond_error(403).await;
+                return Ok(true);
             }
-        } else {
-            false
         };
 
-        if !is_authorized {
-            error!("Tenant Violation: Unauthorized access to {}", host);
-            let _ = session.respond_error(403).await;
+        // 3. Fail fast while every upstream's circuit is open
+        if let Some(retry_after) = self.state.balancer.circuit_open(&route) {
+            warn!("All upstreams of {} are failing, rejecting request", host);
+            respond_unavailable(session, retry_after).await;
             return Ok(true);
         }
 
         // Store in context for upstream_peer
-        *ctx = Some(MeshContext {
-            target_host,
-            target_port,
-            target_org_id,
+        let total = route.resilience.total_timeout_ms;
+        ctx.mesh = Some(MeshContext {
+            route,
+            upstream: None,
+            tried: Vec::new(),
+            deadline: (total > 0).then(|| Instant::now() + Duration::from_millis(total)),
+            retry_after: None,
+            claims,
+            request_id: request_id(session.req_header()),
+            upstream_started: None,
+            upstream_latency: None,
         });
 
         Ok(false) // Continue to upstream_peer
@@ -110,79 +172,314 @@ impl ProxyHttp for ZexioMeshLogic {
 
     async fn upstream_peer(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         ctx: &mut Self::CTX,
     ) -> Result<Box<HttpPeer>> {
-        let ctx = ctx
-            .as_ref()
+        // A retry releases the previous upstream first
+        self.release(ctx, false);
+        let mesh = ctx
+            .mesh
+            .as_mut()
             .ok_or_else(|| pingora::Error::new(ErrorType::InternalError))?;
+        if mesh.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(pingora::Error::new(ErrorType::ReadTimedout));
+        }
+        let route = &mesh.route;
 
-        info!("Proxying to {}:{}", ctx.target_host, ctx.target_port);
+        let hash_key = route
+            .lb
+            .hash_header
+            .as_ref()
+            .and_then(|name| session.req_header().headers.get(name.as_str()))
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| self.client(session).0.map(|ip| ip.to_string()))
+            .unwrap_or_default();
+        let upstream = match self.state.balancer.select(route, &hash_key, &mesh.tried) {
+            Ok(upstream) => upstream,
+            Err(Unavailable::NoUpstreams) => {
+                return Err(pingora::Error::new(ErrorType::ConnectNoRoute))
+            }
+            Err(Unavailable::CircuitOpen { retry_after }) => {
+                mesh.retry_after = Some(retry_after);
+                return Err(pingora::Error::new(HTTPStatus(503)));
+            }
+        };
+
+        debug!("Proxying to {}:{}", upstream.host, upstream.port);
 
-        let peer = Box::new(HttpPeer::new(
-            (ctx.target_host.as_str(), ctx.target_port),
-            false,          // TLS? For now false (internal mesh)
-            "".to_string(), // SNI
+        let mut peer = Box::new(HttpPeer::new(
+            (upstream.host.as_str(), upstream.port),
+            route.tls,
+            upstream.host.clone(), // SNI
         ));
+        let resilience = &route.resilience;
+        peer.options.connection_timeout =
+            Some(mesh.clamp(Duration::from_millis(resilience.connect_timeout_ms)));
+        peer.options.read_timeout =
+            Some(mesh.clamp(Duration::from_millis(resilience.read_timeout_ms)));
+        mesh.tried.push(upstream.clone());
+        mesh.upstream_started = Some(Instant::now());
+        mesh.upstream = Some(upstream);
 
         Ok(peer)
     }
-}
-
-impl ZexioMeshLogic {
-    // Helper function (copied logic from proxy.rs but adapted)
-    async fn resolve_mesh_dns(&self, host: &str) -> Result<(String, u16, String), ()> {
-        // Format: {userId}.{serviceSlug}.zexio.internal
-        if host.ends_with(".zexio.internal") {
-            let parts: Vec<&str> = host.split('.').collect();
-
-            if parts.len() >= 4 {
-                let mut conn = self
-                    .state
-                    .redis
-                    .get_multiplexed_async_connection()
-                    .await
-                    .map_err(|_| ())?;
-                let redis_key = format!("service:{}", host);
-                let service_info: std::collections::HashMap<String, String> =
-                    conn.hgetall(redis_key).await.map_err(|_| ())?;
-
-                if let Some(target_ip) = service_info.get("worker_ip") {
-                    let port = service_info
-                        .get("port")
-                        .and_then(|p| p.parse().ok())
-                        .unwrap_or(80);
-
-                    let owner_id = service_info.get("owner_id").cloned().unwrap_or_default();
-
-                    let is_on_this_worker = match &self.state.settings.server.public_ip {
-                        Some(ip) => target_ip == ip,
-                        None => target_ip == "127.0.0.1" || target_ip == "localhost",
-                    };
 
-                    if is_on_this_worker {
-                        return Ok(("127.0.0.1".to_string(), port, owner_id));
-                    }
-                    return Ok((target_ip.clone(), port, owner_id));
-                }
-            }
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        let Some(mesh) = ctx.mesh.as_ref() else {
+            return Ok(());
+        };
 
-            // Legacy
-            let project_id = host.replace(".zexio.internal", "");
-            let port = 8000 + (crc32fast::hash(project_id.as_bytes()) % 1000) as u16;
-            return Ok(("127.0.0.1".to_string(), port, "".to_string()));
+        // Identity headers are only ever set by the mesh
+        let spoofed: Vec<_> = upstream_request
+            .headers
+            .keys()
+            .filter(|name| name.as_str().starts_with("x-zexio-"))
+            .cloned()
+            .collect();
+        for name in spoofed {
+            upstream_request.remove_header(&name);
         }
+        if let Some(claims) = &mesh.claims {
+            upstream_request.insert_header("X-Zexio-User-Id", &claims.user_id)?;
+            upstream_request.insert_header("X-Zexio-Org-Id", &claims.org_id)?;
+            upstream_request.insert_header("X-Zexio-Source-Service", &claims.source_service)?;
+        }
+
+        upstream_request.insert_header("X-Request-Id", &mesh.request_id)?;
+        let traceparent = upstream_request
+            .headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| valid_traceparent(v))
+            .map(str::to_string);
+        upstream_request
+            .insert_header("traceparent", traceparent.unwrap_or_else(new_traceparent))?;
 
-        if host.ends_with(".zexio.app") {
-            let prefix = host.replace(".zexio.app", "");
-            let project_id = if let Some(uuid) = prefix.split("--").last() {
-                uuid.to_string()
-            } else {
-                prefix
+        let (client_ip, tls) = self.client(session);
+        if let Some(ip) = client_ip {
+            let forwarded_for = match upstream_request
+                .headers
+                .get("X-Forwarded-For")
+                .and_then(|v| v.to_str().ok())
+            {
+                Some(previous) => format!("{}, {}", previous, ip),
+                None => ip.to_string(),
             };
-            let port = 8000 + (crc32fast::hash(project_id.as_bytes()) % 1000) as u16;
-            return Ok(("127.0.0.1".to_string(), port, "".to_string()));
+            upstream_request.insert_header("X-Forwarded-For", forwarded_for)?;
+        }
+        upstream_request.insert_header("X-Forwarded-Proto", if tls { "https" } else { "http" })?;
+        let host = session
+            .req_header()
+            .headers
+            .get("Host")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        if let Some(host) = host {
+            upstream_request.insert_header("X-Forwarded-Host", host)?;
+        }
+        Ok(())
+    }
+
+    async fn response_filter(
+        &self,
+        _session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        if let Some(mesh) = ctx.mesh.as_mut() {
+            mesh.upstream_latency = mesh.upstream_started.map(|t| t.elapsed());
+            upstream_response.insert_header("X-Request-Id", &mesh.request_id)?;
+        }
+        Ok(())
+    }
+
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        self.release(ctx, false);
+        // Nothing reached the upstream, so any request can be retried
+        e.set_retry(ctx.mesh.as_ref().is_some_and(MeshContext::may_retry));
+        e
+    }
+
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<pingora::Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<pingora::Error> {
+        self.release(ctx, false);
+        let mut e = e.more_context(format!("Peer: {}", peer));
+        let buffered = !session.as_ref().retry_buffer_truncated();
+        if !ctx.mesh.as_ref().is_some_and(MeshContext::may_retry) {
+            e.set_retry(false);
+        } else if matches!(
+            session.req_header().method.as_str(),
+            "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" | "TRACE"
+        ) {
+            e.set_retry(buffered);
+        } else {
+            // The upstream may have acted on it: only retry when Pingora knows
+            // a reused connection failed before the request was sent
+            e.retry.decide_reuse(client_reused && buffered);
+        }
+        e
+    }
+
+    fn upstream_response_body_filter(
+        &self,
+        _session: &mut Session,
+        _body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Reads are clamped to the budget, but a slowly streamed body is not
+        if let Some(deadline) = ctx.mesh.as_ref().and_then(|m| m.deadline) {
+            if Instant::now() >= deadline {
+                return Err(pingora::Error::explain(
+                    ErrorType::ReadTimedout,
+                    "Total timeout of the route exceeded",
+                ));
+            }
         }
-        Err(())
+        Ok(())
     }
+
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &pingora::Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy {
+        let code = match e.etype() {
+            HTTPStatus(503) => {
+                if let Some(retry_after) = ctx.mesh.as_ref().and_then(|c| c.retry_after) {
+                    respond_unavailable(session, retry_after).await;
+                    return FailToProxy {
+                        error_code: 503,
+                        can_reuse_downstream: false,
+                    };
+                }
+                503
+            }
+            HTTPStatus(code) => *code,
+            ErrorType::ConnectTimedout | ErrorType::ReadTimedout | ErrorType::WriteTimedout => 504,
+            _ => match e.esource() {
+                ErrorSource::Upstream => 502,
+                ErrorSource::Downstream => match e.etype() {
+                    // Client is gone
+                    ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                ErrorSource::Internal | ErrorSource::Unset => 500,
+            },
+        };
+        if code > 0 {
+            let _ = session.respond_error(code).await;
+        }
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
+    async fn logging(
+        &self,
+        session: &mut Session,
+        e: Option<&pingora::Error>,
+        ctx: &mut Self::CTX,
+    ) {
+        // Gateway errors from the upstream count as failures too
+        let upstream_failed = session
+            .response_written()
+            .is_some_and(|r| matches!(r.status.as_u16(), 502..=504));
+        self.release(ctx, e.is_none() && !upstream_failed);
+
+        let request = session.req_header();
+        let mesh = ctx.mesh.as_ref();
+        let claims = mesh.and_then(|m| m.claims.as_ref());
+        self.state.access_log.record(AccessLogEntry {
+            timestamp: chrono::Utc::now(),
+            host: ctx.host.clone(),
+            method: request.method.to_string(),
+            path: request.uri.path().to_string(),
+            status: session.response_written().map_or(0, |r| r.status.as_u16()),
+            bytes_in: session.body_bytes_read(),
+            bytes_out: session.body_bytes_sent(),
+            upstream: mesh
+                .and_then(|m| m.tried.last())
+                .map(|u| format!("{}:{}", u.host, u.port)),
+            upstream_latency_ms: mesh
+                .and_then(|m| m.upstream_latency)
+                .map(|d| d.as_millis() as u64),
+            total_latency_ms: ctx.started.elapsed().as_millis() as u64,
+            org_id: claims.map(|c| c.org_id.clone()),
+            source_service: claims.map(|c| c.source_service.clone()),
+            request_id: mesh.map(|m| m.request_id.clone()),
+            client_ip: self.client(session).0.map(|ip| ip.to_string()),
+            error: e.map(|e| e.to_string()),
+        });
+    }
+}
+
+/// `503` with `Retry-After` in whole seconds, at least one.
+async fn respond_unavailable(session: &mut Session, retry_after: Duration) {
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let response = ResponseHeader::build(503, None).and_then(|mut r| {
+        r.insert_header("Retry-After", secs.max(1).to_string())?;
+        r.insert_header("Content-Length", "0")?;
+        Ok(r)
+    });
+    if let Ok(response) = response {
+        let _ = session
+            .write_response_header(Box::new(response), true)
+            .await;
+    }
+}
+
+/// The client's `X-Request-Id` if it looks sane, else a new one.
+fn request_id(request: &RequestHeader) -> String {
+    request
+        .headers
+        .get("X-Request-Id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// W3C `traceparent`: `00-{trace id}-{parent id}-{flags}`, ids not all zero.
+fn valid_traceparent(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    let hex = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    parts.len() == 4
+        && parts[0] == "00"
+        && hex(parts[1], 32)
+        && parts[1].bytes().any(|b| b != b'0')
+        && hex(parts[2], 16)
+        && parts[2].bytes().any(|b| b != b'0')
+        && hex(parts[3], 2)
+}
+
+/// Start a trace for requests that don't carry one (not sampled).
+fn new_traceparent() -> String {
+    let mut trace_id = [0u8; 16];
+    let mut parent_id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut trace_id);
+    rand::thread_rng().fill_bytes(&mut parent_id);
+    format!("00-{}-{}-00", hex::encode(trace_id), hex::encode(parent_id))
 }
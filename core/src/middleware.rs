@@ -1,27 +1,51 @@
-use crate::{crypto::Crypto, state::AppState};
+use crate::signing::{
+    self, LEGACY_SIGNATURE_HEADER, NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use crate::{admin_socket::LocalPeer, audit::Actor, crypto::Crypto, state::AppState, tokens};
 use axum::{
     body::Body,
     extract::{Request, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
-use tracing::warn;
+use tracing::{debug, warn};
 
 /// Smart authentication middleware that:
-/// - Skips auth in standalone mode (local development)
+/// - Trusts local peers on the admin socket (root, agent user, socket group)
+/// - Accepts scoped API tokens (`Authorization: Bearer zxt_...`) in any mode
+/// - Skips auth in standalone mode (local development) until a token exists
 /// - Requires auth in cloud mode (production)
 pub async fn smart_auth_middleware(
     State(state): State<AppState>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
+    if let Some(peer) = request.extensions().get::<LocalPeer>() {
+        if peer.trusted {
+            debug!(
+                "Trusted local peer uid={} pid={:?} on admin socket",
+                peer.uid, peer.pid
+            );
+            let actor = Actor::socket(peer.uid);
+            request.extensions_mut().insert(actor);
+            return Ok(next.run(request).await);
+        }
+    }
+
+    if let Some(token) = bearer_token(request.headers()) {
+        return token_auth_middleware(State(state), token, request, next).await;
+    }
+
     // Check if we're in cloud mode
-    let is_cloud_mode =
-        state.settings.cloud.token.is_some() && state.settings.cloud.worker_id.is_some();
+    let is_cloud_mode = state.settings.cloud.is_cloud_mode();
 
-    // Standalone mode: no auth required
+    // Standalone mode: no auth required, unless API tokens have been created
     if !is_cloud_mode {
+        if state.tokens.has_active() {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        request.extensions_mut().insert(Actor::anonymous());
         return Ok(next.run(request).await);
     }
 
@@ -29,19 +53,75 @@ pub async fn smart_auth_middleware(
     worker_auth_middleware(State(state), request, next).await
 }
 
-/// Original worker authentication (for cloud mode)
-pub async fn worker_auth_middleware(
+/// API token from the Authorization header (only `zxt_` tokens).
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    header(headers, "Authorization")?
+        .strip_prefix("Bearer ")
+        .filter(|t| t.starts_with(tokens::TOKEN_PREFIX))
+        .map(|t| t.trim().to_string())
+}
+
+/// Scoped API token authentication. The token must be active and grant the
+/// scope of the route group being called.
+async fn token_auth_middleware(
     State(state): State<AppState>,
-    request: Request,
+    token: String,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    // Get signature from header before moving request
-    let signature = request
-        .headers()
-        .get("X-Signature")
+    let api_token = state.tokens.authenticate(&token).ok_or_else(|| {
+        warn!("Rejected unknown, expired or revoked API token");
+        StatusCode::UNAUTHORIZED
+    })?;
+
+    let required = tokens::required_scope(
+        request.method(),
+        request.uri().path(),
+        request.uri().query(),
+    );
+    if !api_token.allows(&required) {
+        warn!(
+            "API token {} lacks scope {} for {} {}",
+            api_token.name,
+            required,
+            request.method(),
+            request.uri().path()
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    request
+        .extensions_mut()
+        .insert(Actor::token(&api_token.name, &api_token.id));
+    request.extensions_mut().insert(api_token);
+    Ok(next.run(request).await)
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+}
+
+/// Worker authentication (for cloud mode).
+///
+/// v2 signatures cover method, path, query, body hash, timestamp and nonce
+/// (see `signing::canonical_string`) and are rejected outside the clock-skew
+/// window or when the nonce was already used. Legacy body-only `X-Signature`
+/// requests are accepted while `security.accept_legacy_signatures` is on.
+pub async fn worker_auth_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let headers = request.headers();
+    let v2_signature = header(headers, SIGNATURE_HEADER);
+    let legacy_signature = header(headers, LEGACY_SIGNATURE_HEADER);
+
+    if v2_signature.is_none() && legacy_signature.is_none() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
 
     // Get body for verification
     let (parts, body) = request.into_parts();
@@ -49,14 +129,70 @@ pub async fn worker_auth_middleware(
         .await
         .map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    // Verify signature
-    if !Crypto::verify_signature(&state.worker_secret, &bytes, &signature) {
-        warn!("Invalid worker signature");
+    let actor;
+    if let Some(signature) = v2_signature {
+        let timestamp = header(&parts.headers, TIMESTAMP_HEADER).ok_or(StatusCode::UNAUTHORIZED)?;
+        let nonce = header(&parts.headers, NONCE_HEADER).ok_or(StatusCode::UNAUTHORIZED)?;
+        let signature = signature
+            .strip_prefix(signing::VERSION_PREFIX)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        if nonce.len() < 16 || nonce.len() > 128 {
+            warn!("Rejected signed request: invalid nonce length");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        let ts: i64 = timestamp.parse().map_err(|_| StatusCode::UNAUTHORIZED)?;
+        let window = state.settings.security.signature_max_skew_secs as i64;
+        if (chrono::Utc::now().timestamp() - ts).abs() > window {
+            warn!("Rejected signed request: timestamp outside the allowed clock skew");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        let canonical = signing::canonical_string(
+            parts.method.as_str(),
+            parts.uri.path(),
+            parts.uri.query(),
+            &bytes,
+            &timestamp,
+            &nonce,
+        );
+        if !Crypto::verify_signature(&state.worker_secret, canonical.as_bytes(), signature) {
+            warn!("Invalid worker signature");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        // Only remember nonces of authentic requests, so forged ones can't fill the cache
+        if !state.nonces.check_and_insert(&nonce, ts, window) {
+            warn!("Rejected replayed request (nonce already used)");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        actor = Actor::signature("v2");
+    } else if let Some(signature) = legacy_signature {
+        if !state.settings.security.accept_legacy_signatures {
+            warn!("Rejected legacy X-Signature request (legacy signatures disabled)");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        // Verify signature
+        if !Crypto::verify_signature(&state.worker_secret, &bytes, &signature) {
+            warn!("Invalid worker signature");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        warn!(
+            "Accepted deprecated X-Signature request for {} {}; switch to {}",
+            parts.method,
+            parts.uri.path(),
+            SIGNATURE_HEADER
+        );
+        actor = Actor::signature("legacy");
+    } else {
         return Err(StatusCode::UNAUTHORIZED);
     }
 
     // Reconstruct request with body
-    let request = Request::from_parts(parts, Body::from(bytes));
+    let mut request = Request::from_parts(parts, Body::from(bytes));
+    request.extensions_mut().insert(actor);
 
     Ok(next.run(request).await)
 }
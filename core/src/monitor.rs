@@ -1,3 +1,4 @@
+use crate::volumes::{self, VolumeUsage};
 use crate::{errors::AppError, state::AppState};
 use axum::{
     extract::{Path, State},
@@ -139,11 +140,19 @@ pub async fn global_stats_stream(
 pub struct ProjectStatus {
     status: String,
     active: bool,
+    volumes: Vec<VolumeUsage>,
+}
+
+async fn project_volume_usage(state: &AppState, project_id: &str) -> Vec<VolumeUsage> {
+    match state.store.read(project_id).await {
+        Ok(config) => volumes::cached_usage(state, &config).await,
+        Err(_) => Vec::new(),
+    }
 }
 
 // JSON endpoint (one-time)
 pub async fn project_monitor_handler(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Path(project_id): Path<String>,
 ) -> Result<Json<ProjectStatus>, AppError> {
     let unit_name = format!("app@{}.service", project_id);
@@ -156,13 +165,18 @@ pub async fn project_monitor_handler(
 
     let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
     let active = status == "active";
+    let volumes = project_volume_usage(&state, &project_id).await;
 
-    Ok(Json(ProjectStatus { status, active }))
+    Ok(Json(ProjectStatus {
+        status,
+        active,
+        volumes,
+    }))
 }
 
 // SSE endpoint (real-time updates every 3 seconds)
 pub async fn project_monitor_stream(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Path(project_id): Path<String>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let stream = async_stream::stream! {
@@ -177,8 +191,9 @@ pub async fn project_monitor_stream(
             if let Ok(output) = output {
                 let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
                 let active = status == "active";
+                let volumes = project_volume_usage(&state, &project_id).await;
 
-                let project_status = ProjectStatus { status, active };
+                let project_status = ProjectStatus { status, active, volumes };
 
                 if let Ok(json) = serde_json::to_string(&project_status) {
                     yield Ok(Event::default().data(json));
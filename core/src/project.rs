@@ -1,14 +1,15 @@
-use crate::storage::ProjectConfig;
-use crate::{errors::AppError, state::AppState};
+use crate::mesh::{certs::CertStatus, resolver::normalize_host};
+use crate::storage::{project_port, ProjectConfig};
+use crate::{databases, errors::AppError, snapshots, state::AppState, volumes};
 use axum::{
-    extract::{Json, Path, State},
+    extract::{Json, Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::process::Command;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 use trust_dns_resolver::TokioAsyncResolver;
 
 #[derive(Deserialize)]
@@ -25,15 +26,19 @@ pub async fn create_project(
     info!("Creating project: {}", req.project_id);
 
     // Determine port
-    let port = 8000 + (crc32fast::hash(req.project_id.as_bytes()) % 1000) as u16;
+    let port = project_port(&req.project_id);
 
     // Create project config
     let config = ProjectConfig {
         id: req.project_id.clone(),
         domains: req.domains.clone(),
         encrypted_env: String::new(), // Empty initially
+        env: Default::default(),
         webhook_secret: req.webhook_secret,
         created_at: chrono::Utc::now(),
+        volumes: Vec::new(),
+        snapshot_policy: None,
+        databases: Vec::new(),
     };
 
     // Save to storage
@@ -42,6 +47,7 @@ pub async fn create_project(
         .create(config)
         .await
         .map_err(|_| AppError::InternalServerError)?;
+    reload_domains(&state).await;
 
     info!(
         "Project {} created successfully on port {}",
@@ -60,7 +66,9 @@ pub async fn create_project(
 
 #[derive(Deserialize)]
 pub struct UpdateEnvRequest {
+    #[serde(default)]
     pub encrypted_env: String, // Hex-encoded encrypted blob
+    pub sealed_env: Option<String>, // Dotenv content sealed to the node public key
 }
 
 pub async fn update_env_handler(
@@ -70,6 +78,11 @@ pub async fn update_env_handler(
 ) -> Result<impl IntoResponse, AppError> {
     info!("Updating environment for project: {}", project_id);
 
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
     // Read existing config
     let mut config = state
         .store
@@ -77,8 +90,17 @@ pub async fn update_env_handler(
         .await
         .map_err(|_| AppError::BadRequest("Project not found".into()))?;
 
-    // Update encrypted env
-    config.encrypted_env = payload.encrypted_env;
+    // Update encrypted env (sealed content is re-encrypted with the project data key)
+    config.encrypted_env = match payload.sealed_env {
+        Some(sealed) => {
+            let env = state
+                .node_key
+                .open(&sealed)
+                .map_err(|e| AppError::BadRequest(e.to_string()))?;
+            hex::encode(state.crypto.encrypt_for(&project_id, &env)?)
+        }
+        None => payload.encrypted_env,
+    };
 
     // Save
     state
@@ -100,7 +122,22 @@ pub async fn add_domain_handler(
     Path(project_id): Path<String>,
     Json(payload): Json<DomainRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let domain = payload.domain;
+    let domain = normalize_host(&payload.domain);
+    if domain.is_empty()
+        || domain.starts_with('.')
+        || !domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        || domain.ends_with(".zexio.internal")
+        || domain.ends_with(".zexio.app")
+    {
+        return Err(AppError::BadRequest("Invalid domain".into()));
+    }
+    if matches!(state.domains.lookup(&domain), Some(owner) if owner != project_id) {
+        return Err(AppError::BadRequest(
+            "Domain is already used by another project".into(),
+        ));
+    }
 
     // 1. Verify Domain (CNAME or A Record)
     let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|_| {
@@ -170,8 +207,13 @@ pub async fn add_domain_handler(
     }
 
     // 2. Determine Port
-    let _port = 8000 + (crc32fast::hash(project_id.as_bytes()) % 1000) as u16;
+    let _port = project_port(&project_id);
 
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
     // 3. Update Config (Append domain)
     let mut config = state
         .store
@@ -186,6 +228,7 @@ pub async fn add_domain_handler(
             .update(&config)
             .await
             .map_err(|_| AppError::InternalServerError)?;
+        reload_domains(&state).await;
     }
 
     Ok((StatusCode::OK, "Domain added").into_response())
@@ -196,8 +239,13 @@ pub async fn remove_domain_handler(
     Path(project_id): Path<String>,
     Json(payload): Json<DomainRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let domain = payload.domain;
+    let domain = normalize_host(&payload.domain);
 
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
     // 1. Update Config (Remove domain)
     let mut config = state
         .store
@@ -205,17 +253,63 @@ pub async fn remove_domain_handler(
         .await
         .map_err(|_| AppError::BadRequest("Project not found".into()))?;
 
-    config.domains.retain(|d| d != &domain);
+    config.domains.retain(|d| normalize_host(d) != domain);
 
     state
         .store
         .update(&config)
         .await
         .map_err(|_| AppError::InternalServerError)?;
+    reload_domains(&state).await;
 
     Ok((StatusCode::OK, "Domain removed").into_response())
 }
 
+/// Rebuild the mesh's domain table after domains changed, let the
+/// certificate manager issue or drop certificates, and update Caddy.
+async fn reload_domains(state: &AppState) {
+    if let Err(e) = state.domains.reload(&state.store).await {
+        error!("Failed to reload mesh domains: {}", e);
+    }
+    state.certs.wake();
+    if let Some(caddy) = &state.caddy {
+        if let Err(e) = caddy.sync(&state.domains.entries()).await {
+            error!("Failed to sync Caddy routes: {}", e);
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DomainInfo {
+    pub domain: String,
+    pub certificate: Option<CertStatus>, // None until ACME is enabled and tried
+}
+
+pub async fn list_domains_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+) -> Result<Json<Vec<DomainInfo>>, AppError> {
+    let config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+
+    Ok(Json(
+        config
+            .domains
+            .iter()
+            .map(|domain| {
+                let domain = normalize_host(domain);
+                DomainInfo {
+                    certificate: state.certs.status(&domain),
+                    domain,
+                }
+            })
+            .collect(),
+    ))
+}
+
 #[derive(serde::Serialize)]
 pub struct FileInfo {
     name: String,
@@ -277,9 +371,16 @@ pub async fn list_projects_handler(
     Ok(Json(summaries))
 }
 
+#[derive(Deserialize)]
+pub struct DeleteProjectQuery {
+    #[serde(default)]
+    pub purge_volumes: bool,
+}
+
 pub async fn delete_project_handler(
     State(state): State<AppState>,
     Path(project_id): Path<String>,
+    Query(query): Query<DeleteProjectQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     info!("Deleting project: {}", project_id);
 
@@ -289,16 +390,39 @@ pub async fn delete_project_handler(
         .arg(format!("app@{}.service", project_id))
         .output();
 
+    // 1.1 Managed databases lose access; their data follows purge_volumes
+    if let Ok(config) = state.store.read(&project_id).await {
+        databases::revoke_all(&state, &config, query.purge_volumes).await;
+    }
+
     // 2. Delete project directory (includes config.json and bundle)
     state
         .store
         .delete(&project_id)
         .await
         .map_err(|_| AppError::InternalServerError)?;
+    reload_domains(&state).await;
 
-    // 3. Remove from Caddy (all domains)
-    // Note: We don't have the domain list anymore, but Caddy cleanup can be manual or we skip
-    // For now, we'll skip Caddy cleanup since domains are gone with the project
+    // 2.1 Volumes (and their local snapshots) live outside the project directory
+    // and are only removed on request
+    if query.purge_volumes {
+        volumes::purge(&state.settings, &project_id)
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        snapshots::purge(&state, &project_id)
+            .await
+            .map_err(|_| AppError::InternalServerError)?;
+        // Nothing encrypted under the project's data key is left
+        state
+            .crypto
+            .delete_data_key(&project_id)
+            .map_err(|_| AppError::InternalServerError)?;
+    } else if volumes::project_volumes_dir(&state.settings, &project_id).exists() {
+        info!(
+            "Retaining volumes of deleted project {} (pass ?purge_volumes=true to remove)",
+            project_id
+        );
+    }
 
     Ok((StatusCode::OK, "Project deleted"))
 }
@@ -0,0 +1,247 @@
+use crate::config::Settings;
+use crate::exec::{self, Execution};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Built-in catalog, versioned with the agent.
+const BUILTIN_CATALOG: &str = include_str!("../recipes/catalog.json");
+
+/// System package manager, used to pick the right steps of a recipe.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Apt,
+    Dnf,
+    Apk,
+}
+
+impl PackageManager {
+    /// Detect the package manager by looking for its binary on `PATH`.
+    pub fn detect() -> Option<Self> {
+        let path = std::env::var_os("PATH")?;
+        let has = |bin: &str| std::env::split_paths(&path).any(|dir| dir.join(bin).is_file());
+
+        if has("apt-get") {
+            Some(Self::Apt)
+        } else if has("dnf") {
+            Some(Self::Dnf)
+        } else if has("apk") {
+            Some(Self::Apk)
+        } else {
+            None
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Self::Apt => "apt",
+            Self::Dnf => "dnf",
+            Self::Apk => "apk",
+        }
+    }
+}
+
+/// Scripts keyed by package manager (`apt`, `dnf`, `apk`). `any` is used when
+/// there is no entry for the detected one.
+pub type Steps = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipeSource {
+    #[default]
+    Builtin,
+    File,   // `<recipes_dir>/<name>.json`
+    Config, // `exec.recipes`
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CatalogRecipe {
+    #[serde(default)]
+    pub description: String,
+    pub install: Steps,
+    #[serde(default)]
+    pub uninstall: Steps,
+    #[serde(default)]
+    pub status: Steps, // Exits 0 when installed and running
+    #[serde(default)]
+    pub version: Steps, // Prints the installed version
+    #[serde(default)]
+    pub unit: Steps, // systemd unit (or OpenRC service) running it
+    #[serde(default)]
+    pub ports: Vec<u16>, // TCP ports it listens on locally
+    #[serde(skip_deserializing)]
+    pub source: RecipeSource,
+}
+
+/// Steps of a recipe for this node's package manager.
+#[derive(Debug, Clone)]
+pub struct ResolvedRecipe {
+    pub install: Option<String>,
+    pub uninstall: Option<String>,
+    pub status: Option<String>,
+    pub version: Option<String>,
+    pub unit: Option<String>,
+    pub ports: Vec<u16>,
+}
+
+#[derive(Deserialize)]
+struct CatalogFile {
+    version: u32,
+    recipes: BTreeMap<String, CatalogRecipe>,
+}
+
+pub struct Catalog {
+    pub version: u32,
+    pub manager: Option<PackageManager>,
+    pub recipes: BTreeMap<String, CatalogRecipe>,
+}
+
+impl Catalog {
+    /// Built-in catalog, overridden by files in `exec.recipes_dir` and then by
+    /// recipes in the `exec.recipes` config section.
+    pub fn load(settings: &Settings) -> Result<Self> {
+        let builtin: CatalogFile =
+            serde_json::from_str(BUILTIN_CATALOG).context("Invalid built-in recipe catalog")?;
+        let mut recipes = builtin.recipes;
+
+        let dir = Path::new(&settings.exec.recipes_dir);
+        if dir.exists() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let json = std::fs::read_to_string(&path)?;
+                let mut recipe: CatalogRecipe = serde_json::from_str(&json)
+                    .with_context(|| format!("Invalid recipe file {:?}", path))?;
+                recipe.source = RecipeSource::File;
+                recipes.insert(name.to_string(), recipe);
+            }
+        }
+
+        let any = |script: &Option<String>| {
+            script
+                .iter()
+                .map(|s| ("any".to_string(), s.clone()))
+                .collect::<Steps>()
+        };
+        for (name, recipe) in &settings.exec.recipes {
+            recipes.insert(
+                name.clone(),
+                CatalogRecipe {
+                    description: String::new(),
+                    install: any(&Some(recipe.install.clone())),
+                    uninstall: any(&recipe.uninstall),
+                    status: any(&recipe.status),
+                    version: any(&recipe.version),
+                    unit: any(&recipe.unit),
+                    ports: recipe.ports.clone(),
+                    source: RecipeSource::Config,
+                },
+            );
+        }
+
+        Ok(Self {
+            version: builtin.version,
+            manager: PackageManager::detect(),
+            recipes,
+        })
+    }
+
+    fn pick(&self, steps: &Steps) -> Option<String> {
+        self.manager
+            .and_then(|m| steps.get(m.key()))
+            .or_else(|| steps.get("any"))
+            .cloned()
+    }
+
+    /// Steps of `name` for this system, or `None` if there is no such recipe.
+    pub fn resolve(&self, name: &str) -> Option<ResolvedRecipe> {
+        let recipe = self.recipes.get(name)?;
+        Some(ResolvedRecipe {
+            install: self.pick(&recipe.install),
+            uninstall: self.pick(&recipe.uninstall),
+            status: self.pick(&recipe.status),
+            version: self.pick(&recipe.version),
+            unit: self.pick(&recipe.unit),
+            ports: recipe.ports.clone(),
+        })
+    }
+}
+
+/// Run a status or version step. Returns its output if it exited 0.
+pub async fn query(settings: &Settings, script: &str) -> Option<String> {
+    let output = exec::run(&settings.exec, &Execution::Shell(script.to_string()))
+        .await
+        .ok()?;
+    output.success().then(|| output.stdout.trim().to_string())
+}
+
+#[derive(Serialize)]
+pub struct RecipeSummary {
+    pub name: String,
+    pub description: String,
+    pub source: RecipeSource,
+    pub available: bool, // Has install steps for this node's package manager
+}
+
+#[derive(Serialize)]
+pub struct CatalogSummary {
+    pub version: u32,
+    pub package_manager: Option<PackageManager>,
+    pub recipes: Vec<RecipeSummary>,
+}
+
+impl From<&Catalog> for CatalogSummary {
+    fn from(catalog: &Catalog) -> Self {
+        Self {
+            version: catalog.version,
+            package_manager: catalog.manager,
+            recipes: catalog
+                .recipes
+                .iter()
+                .map(|(name, recipe)| RecipeSummary {
+                    name: name.clone(),
+                    description: recipe.description.clone(),
+                    source: recipe.source,
+                    available: catalog.pick(&recipe.install).is_some(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `zexio install --list`
+pub async fn list(settings: &Settings) -> Result<()> {
+    let catalog = Catalog::load(settings)?;
+    let manager = catalog
+        .manager
+        .map(|m| m.key())
+        .unwrap_or("unknown package manager");
+    println!("Recipe catalog v{} ({})\n", catalog.version, manager);
+
+    println!(
+        "{:<12} {:<10} {:<10} {:<8} DESCRIPTION",
+        "NAME", "STATUS", "AVAILABLE", "SOURCE"
+    );
+    for recipe in CatalogSummary::from(&catalog).recipes {
+        let installed = match catalog.resolve(&recipe.name).and_then(|r| r.status) {
+            Some(script) => query(settings, &script).await.is_some(),
+            None => false,
+        };
+        println!(
+            "{:<12} {:<10} {:<10} {:<8} {}",
+            recipe.name,
+            if installed { "installed" } else { "-" },
+            if recipe.available { "yes" } else { "no" },
+            format!("{:?}", recipe.source).to_lowercase(),
+            recipe.description
+        );
+    }
+    Ok(())
+}
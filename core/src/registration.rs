@@ -1,4 +1,6 @@
 use crate::config::Settings;
+use crate::sealed::NodeKey;
+use crate::tls;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
@@ -9,6 +11,8 @@ struct ConnectDto {
     token: String,
     name: Option<String>,
     os_info: OsInfo,
+    public_key: String,
+    tls_fingerprint: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -25,6 +29,8 @@ struct RegisterDto {
     hostname: String,
     arch: String,
     os: String,
+    public_key: String,
+    tls_fingerprint: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -47,6 +53,13 @@ pub async fn handshake(settings: &Settings) -> anyhow::Result<()> {
     let identity_path = &settings.secrets.identity_path;
     info!("Checking for existing identity at: {}", identity_path);
 
+    // Public half of the node keypair, so the cloud can seal secrets to us
+    let public_key = NodeKey::load_or_generate(&settings.secrets.node_key_path)?
+        .public_key()
+        .to_string();
+    // Lets the cloud pin the Management API certificate
+    let tls_fingerprint = tls::ensure_certificate(settings)?;
+
     // 1. Check if already registered
     if Path::new(identity_path).exists() {
         info!("Identity found. Verifying with cloud...");
@@ -61,6 +74,8 @@ pub async fn handshake(settings: &Settings) -> anyhow::Result<()> {
             .json(&serde_json::json!({
                 "worker_id": identity.worker_id,
                 "secret": identity.secret_key,
+                "public_key": public_key,
+                "tls_fingerprint": tls_fingerprint,
             }))
             .send()
             .await?;
@@ -121,6 +136,8 @@ pub async fn handshake(settings: &Settings) -> anyhow::Result<()> {
         hostname,
         os,
         arch,
+        public_key,
+        tls_fingerprint,
     };
 
     // 4. Send Registration Request
@@ -286,6 +303,10 @@ pub async fn interactive_login(settings: &Settings) -> anyhow::Result<()> {
             os_type: os,
             os_arch: arch,
         },
+        public_key: NodeKey::load_or_generate(&settings.secrets.node_key_path)?
+            .public_key()
+            .to_string(),
+        tls_fingerprint: tls::ensure_certificate(settings)?,
     };
 
     // Send Registration Request
@@ -377,6 +398,10 @@ pub async fn connect_with_token(settings: &Settings, token: String) -> anyhow::R
             os_type,
             os_arch,
         },
+        public_key: NodeKey::load_or_generate(&settings.secrets.node_key_path)?
+            .public_key()
+            .to_string(),
+        tls_fingerprint: tls::ensure_certificate(settings)?,
     };
 
     let client = reqwest::Client::new();
@@ -0,0 +1,93 @@
+use crate::crypto::Crypto;
+use crate::state::AppState;
+use anyhow::{Context, Result};
+use axum::{extract::State, response::IntoResponse, Json};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use crypto_box::SecretKey;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Name of the sealing scheme, reported together with the public key.
+/// Identical to libsodium's `crypto_box_seal`, so the dashboard can use any
+/// libsodium binding (e.g. `sodium.crypto_box_seal(value, publicKey)`).
+pub const ALGORITHM: &str = "x25519-xsalsa20poly1305-sealedbox";
+
+#[derive(Serialize, Deserialize)]
+struct NodeKeyFile {
+    secret_key: String, // Hex-encoded 32 bytes
+    public_key: String, // Base64
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The node's X25519 keypair. The cloud seals secrets to the public key; only
+/// this node can open them, so plaintext never exists outside the node.
+#[derive(Clone)]
+pub struct NodeKey {
+    secret: Arc<SecretKey>,
+    public_key: String,
+}
+
+impl NodeKey {
+    pub fn load_or_generate(path: &str) -> Result<Self> {
+        if Path::new(path).exists() {
+            let json = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read node key from {}", path))?;
+            let file: NodeKeyFile =
+                serde_json::from_str(&json).context("Failed to parse node key")?;
+            let bytes: [u8; 32] = hex::decode(&file.secret_key)?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Node secret key must be exactly 32 bytes"))?;
+            return Ok(Self::from_secret(SecretKey::from_bytes(bytes)));
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill(&mut bytes);
+        let key = Self::from_secret(SecretKey::from_bytes(bytes));
+
+        let file = NodeKeyFile {
+            secret_key: hex::encode(bytes),
+            public_key: key.public_key.clone(),
+            created_at: chrono::Utc::now(),
+        };
+        Crypto::write_secret_file(Path::new(path), &serde_json::to_vec_pretty(&file)?)?;
+
+        tracing::info!("Generated node keypair at {}", path);
+        Ok(key)
+    }
+
+    fn from_secret(secret: SecretKey) -> Self {
+        let public_key = BASE64.encode(secret.public_key().as_bytes());
+        Self {
+            secret: Arc::new(secret),
+            public_key,
+        }
+    }
+
+    /// Base64-encoded X25519 public key.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Open a base64 sealed box addressed to this node.
+    pub fn open(&self, sealed_b64: &str) -> Result<Vec<u8>> {
+        let sealed = BASE64
+            .decode(sealed_b64.trim())
+            .context("Sealed value is not valid base64")?;
+        self.secret
+            .unseal(&sealed)
+            .map_err(|_| anyhow::anyhow!("Sealed value was not sealed to this node's key"))
+    }
+
+    pub fn open_string(&self, sealed_b64: &str) -> Result<String> {
+        Ok(String::from_utf8(self.open(sealed_b64)?)?)
+    }
+}
+
+pub async fn public_key_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "public_key": state.node_key.public_key(),
+        "algorithm": ALGORITHM
+    }))
+}
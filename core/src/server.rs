@@ -1,20 +1,26 @@
+use crate::mesh::token::MeshKeys;
 use crate::{
-    config::Settings, deploy, middleware, monitor, project, services, state::AppState, streams,
+    admin_socket, audit, config::Settings, databases, deploy, env_vars, inventory, jobs,
+    middleware, monitor, project, sealed, services, snapshots, state::AppState, streams, tls,
+    volumes,
 };
+use axum::http::HeaderValue;
 use axum::middleware as axum_middleware;
 use axum::{
     routing::{delete, get, post},
     Router,
 };
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{error, info, warn};
 
 pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Result<()> {
     // Application state
     info!("📦 Initializing application state...");
     let state = AppState::new(settings.clone())?;
+    state.check_mesh_secret()?;
     info!("✅ Application state ready");
 
     // Protected routes (require authentication in cloud mode, open in standalone)
@@ -24,12 +30,49 @@ pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Resu
             post(project::create_project).get(project::list_projects_handler),
         )
         .route("/projects/:id", delete(project::delete_project_handler))
-        .route("/projects/:id/env", post(project::update_env_handler))
+        .route(
+            "/projects/:id/env",
+            post(project::update_env_handler)
+                .get(env_vars::list_env_handler)
+                .patch(env_vars::patch_env_handler),
+        )
+        .route(
+            "/projects/:id/env/:key",
+            get(env_vars::get_env_handler)
+                .put(env_vars::put_env_handler)
+                .delete(env_vars::delete_env_handler),
+        )
         .route(
             "/projects/:id/domains",
-            post(project::add_domain_handler).delete(project::remove_domain_handler),
+            get(project::list_domains_handler)
+                .post(project::add_domain_handler)
+                .delete(project::remove_domain_handler),
         )
         .route("/projects/:id/files", get(project::list_files_handler))
+        .route(
+            "/projects/:id/volumes",
+            get(volumes::list_volumes_handler).post(volumes::create_volume_handler),
+        )
+        .route(
+            "/projects/:id/volumes/:name",
+            delete(volumes::delete_volume_handler),
+        )
+        .route(
+            "/projects/:id/snapshots",
+            get(snapshots::list_snapshots_handler).post(snapshots::create_snapshot_handler),
+        )
+        .route(
+            "/projects/:id/snapshots/schedule",
+            get(snapshots::get_schedule_handler).put(snapshots::update_schedule_handler),
+        )
+        .route(
+            "/projects/:id/snapshots/:snap",
+            delete(snapshots::delete_snapshot_handler),
+        )
+        .route(
+            "/projects/:id/snapshots/:snap/restore",
+            post(snapshots::restore_snapshot_handler),
+        )
         .route("/projects/:id/stats", get(monitor::project_monitor_handler))
         .route(
             "/projects/:id/stats/stream",
@@ -45,6 +88,36 @@ pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Resu
             "/projects/:id/webhook",
             post(deploy::project_deploy_handler),
         )
+        .route("/services", get(inventory::list_services_handler))
+        .route(
+            "/mesh/metrics",
+            get(crate::mesh::metrics::mesh_metrics_handler),
+        )
+        .route(
+            "/mesh/access-logs/stream",
+            get(crate::mesh::access_log::access_log_stream_handler),
+        ) // SSE
+        .route("/services/recipes", get(services::list_recipes_handler))
+        .route(
+            "/services/postgres/databases",
+            get(databases::list_postgres_handler).post(databases::create_postgres_handler),
+        )
+        .route(
+            "/services/postgres/databases/:name",
+            delete(databases::delete_postgres_handler),
+        )
+        .route(
+            "/services/redis/users",
+            get(databases::list_redis_handler).post(databases::create_redis_handler),
+        )
+        .route(
+            "/services/redis/users/:name",
+            delete(databases::delete_redis_handler),
+        )
+        .route("/jobs", get(jobs::list_jobs_handler))
+        .route("/jobs/:id", get(jobs::get_job_handler))
+        .route("/jobs/:id/stream", get(jobs::job_stream_handler)) // SSE
+        .route("/jobs/:id/cancel", post(jobs::cancel_job_handler))
         .route("/services/install", post(services::install_service_handler))
         .route(
             "/services/uninstall",
@@ -55,6 +128,13 @@ pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Resu
             post(monitor::configure_firewall_handler),
         )
         .route("/sync", post(monitor::sync_handler))
+        .route("/audit", get(audit::list_audit_handler))
+        .route("/audit/export", get(audit::export_audit_handler))
+        // Audit runs inside auth so it sees the authenticated actor
+        .layer(axum_middleware::from_fn_with_state(
+            state.clone(),
+            audit::audit_middleware,
+        ))
         .layer(axum_middleware::from_fn_with_state(
             state.clone(),
             middleware::smart_auth_middleware, // Changed from worker_auth_middleware
@@ -63,14 +143,28 @@ pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Resu
     // Public routes (no auth required - for standalone mode and GUI)
     let public_routes = Router::new()
         .route("/health", get(|| async { "OK" }))
+        .route("/node/public-key", get(sealed::public_key_handler))
         .route("/stats", get(monitor::global_stats_handler))
         .route("/stats/stream", get(monitor::global_stats_stream)) // SSE!
         .route("/system/logs", get(streams::worker_logs_handler)) // JSON (one-time)
         .route("/system/logs/stream", get(streams::worker_logs_stream)); // SSE!
 
-    // CORS configuration for GUI access
+    // CORS configuration for GUI access. Only the Tauri app's origins: the
+    // loopback API trusts local callers, so other web pages must not reach it
+    let origins = settings
+        .server
+        .cors_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin {:?}", origin);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
     let cors = CorsLayer::new()
-        .allow_origin(Any) // Allow all origins (Tauri apps)
+        .allow_origin(AllowOrigin::list(origins))
         .allow_methods(Any) // Allow all HTTP methods
         .allow_headers(Any); // Allow all headers
 
@@ -83,7 +177,15 @@ pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Resu
     let mgmt_addr: SocketAddr =
         format!("{}:{}", settings.server.host, settings.server.port).parse()?;
 
-    info!("🌐 Management API listening on http://{}", mgmt_addr);
+    if settings.server.tcp_enabled {
+        let scheme = if settings.server.tls_enabled {
+            "https"
+        } else {
+            "http"
+        };
+        info!("🌐 Management API listening on {}://{}", scheme, mgmt_addr);
+    }
+    info!("🔌 Admin socket at {}", settings.server.socket_path);
     info!(
         "🔀 Service Mesh Proxy (Pingora) on port {}",
         settings.server.mesh_port
@@ -96,16 +198,100 @@ pub async fn start(settings: Settings, tunnel_port: Option<u16>) -> anyhow::Resu
     info!("");
     info!("✨ Zexio Agent is ready!");
 
-    let mgmt_listener = TcpListener::bind(mgmt_addr).await?;
-    let mgmt_server = axum::serve(mgmt_listener, app);
+    // Custom domains routed by the mesh
+    if let Err(e) = state.domains.reload(&state.store).await {
+        error!("Failed to load mesh domains: {}", e);
+    }
 
-    // 1. Spawn Axum (Management API)
+    // Service token keys: load now, then keep up with rotations
+    if let MeshKeys::Jwks(jwks) = &state.mesh_auth.keys {
+        if let Err(e) = jwks.refresh().await {
+            error!("Failed to load mesh JWKS: {}", e);
+        }
+        tokio::spawn(crate::mesh::jwks::refresher(
+            jwks.clone(),
+            Duration::from_secs(settings.mesh.jwks_refresh_secs.max(30)),
+        ));
+    }
+
+    // Mesh access log file
+    tokio::spawn(crate::mesh::access_log::writer(
+        state.access_log.clone(),
+        settings.mesh.access_log.clone(),
+    ));
+
+    // Active health checks of mesh upstreams
+    tokio::spawn(crate::mesh::balancer::health_checker(
+        state.balancer.clone(),
+    ));
+
+    // Caddy ingress: re-sync all routes, Caddy may have restarted meanwhile
+    if let Some(caddy) = state.caddy.clone() {
+        if settings.acme.enabled {
+            warn!("Both ACME and Caddy are enabled; Caddy should own the custom domains alone");
+        }
+        let domains = state.domains.entries();
+        tokio::spawn(async move {
+            match caddy.sync(&domains).await {
+                Ok(()) => info!("Caddy routes synced ({} domains)", domains.len()),
+                Err(e) => error!("Failed to sync Caddy routes: {}", e),
+            }
+        });
+    }
+
+    // Custom domain certificates (ACME) and the mesh HTTPS listener
+    if settings.acme.enabled {
+        if let Err(e) = state.certs.load(&state.crypto) {
+            error!("Failed to load mesh certificates: {}", e);
+        }
+        tokio::spawn(crate::mesh::acme::certificate_manager(state.clone()));
+        let https_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::mesh::https::serve(https_state).await {
+                error!("Mesh HTTPS listener failed: {}", e);
+            }
+        });
+    }
+
+    // Background: volume quota monitor
+    tokio::spawn(volumes::quota_monitor(state.clone()));
+
+    // Background: scheduled snapshots & retention
+    tokio::spawn(snapshots::scheduler(state.clone()));
+
+    // Background: health of installed services
+    tokio::spawn(inventory::health_monitor(state.clone()));
+
+    // 1. Spawn Axum (Management API) on the admin socket and, unless disabled, TCP
+    let socket_settings = settings.clone();
+    let socket_app = app.clone();
     tokio::spawn(async move {
-        if let Err(e) = mgmt_server.await {
-            tracing::error!("Management API failed: {}", e);
+        if let Err(e) = admin_socket::serve(&socket_settings, socket_app).await {
+            tracing::error!("Admin socket failed: {}", e);
         }
     });
 
+    if settings.server.tcp_enabled && settings.server.tls_enabled {
+        tls::ensure_certificate(&settings)?;
+        let acceptor = tls::acceptor(&settings)?;
+        let mgmt_listener = TcpListener::bind(mgmt_addr).await?;
+
+        tokio::spawn(async move {
+            if let Err(e) = tls::serve(mgmt_listener, acceptor, app).await {
+                tracing::error!("Management API failed: {}", e);
+            }
+        });
+    } else if settings.server.tcp_enabled {
+        let mgmt_listener = TcpListener::bind(mgmt_addr).await?;
+        let mgmt_server = axum::serve(mgmt_listener, app);
+
+        tokio::spawn(async move {
+            if let Err(e) = mgmt_server.await {
+                tracing::error!("Management API failed: {}", e);
+            }
+        });
+    }
+
     // 2. Start Zexio Tunnel Client (Native gRPC) - Only if port is provided
     if let Some(port) = tunnel_port {
         let settings_tunnel = settings.clone();
@@ -1,11 +1,17 @@
+use crate::audit::Actor;
+use crate::config::Settings;
+use crate::exec::{self, ExecError, Execution, OutputStream};
+use crate::recipes::{Catalog, CatalogSummary};
 use crate::{errors::AppError, state::AppState};
 use axum::{
     extract::{Json, State},
     http::StatusCode,
     response::IntoResponse,
+    Extension,
 };
-use serde::{Deserialize, Serialize};
-use std::process::Command;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
 use tracing::{error, info};
 
 #[derive(Deserialize)]
@@ -14,109 +20,84 @@ pub struct InstallServiceRequest {
     pub command: Option<String>,
 }
 
-#[derive(Serialize)]
-pub struct ServiceResponse {
-    pub status: String,
-    pub service: String,
-    pub command: String,
-}
-
-pub async fn install_service_handler(
-    State(_state): State<AppState>,
-    Json(payload): Json<InstallServiceRequest>,
+/// Start an install/uninstall job under the execution policy. Returns
+/// `202 Accepted` with the job; follow it at `/jobs/:id/stream`.
+async fn start_job(
+    state: AppState,
+    actor: Actor,
+    service: String,
+    command: Option<String>,
+    uninstall: bool,
 ) -> Result<impl IntoResponse, AppError> {
-    let service = payload.service.as_str();
-
-    // If a command is provided in the request, use it.
-    // Otherwise, we might eventually pull it from a catalog, but for now we require it or fail.
-    let cmd_to_run = payload.command.clone().ok_or_else(|| {
-        error!("No command provided for service installation: {}", service);
-        AppError::BadRequest("No command provided".to_string())
-    })?;
-
-    info!(
-        "Request to run command for service {}: {}",
-        service, cmd_to_run
-    );
-
-    match run_generic_command(&cmd_to_run).await {
-        Ok(_) => {
-            info!("Command executed successfully for {}", service);
-            Ok((
-                StatusCode::OK,
-                Json(ServiceResponse {
-                    status: "executed".to_string(),
-                    service: service.to_string(),
-                    command: cmd_to_run,
-                }),
-            ))
+    let jobs = state.jobs.clone();
+    match jobs.start(state, actor, service, uninstall, command).await {
+        Ok(job) => {
+            info!("Started job {} ({} {})", job.id, job.kind, job.service);
+            Ok((StatusCode::ACCEPTED, Json(job)))
         }
-        Err(e) => {
-            error!("Execution failed: {}", e);
+        Err(ExecError::Denied(reason)) => Err(AppError::Forbidden(reason)),
+        Err(ExecError::Failed(e)) => {
+            error!("Failed to start job: {}", e);
             Err(AppError::InternalServerError)
         }
     }
 }
 
-pub async fn run_generic_command(cmd: &str) -> Result<String, String> {
-    // Safety check: Filter out potentially destructive commands
-    if is_dangerous_command(cmd) {
-        error!(
-            "🚨 BLOCKED: Potential destructive command detected: {}",
-            cmd
-        );
-        return Err(
-            "Command blocked: Potential system destruction detected for safety reasons."
-                .to_string(),
-        );
-    }
-
-    let output = if cfg!(target_os = "windows") {
-        Command::new("powershell")
-            .arg("-Command")
-            .arg(cmd)
-            .output()
-            .map_err(|e| e.to_string())?
-    } else {
-        Command::new("bash")
-            .arg("-c")
-            .arg(cmd)
-            .output()
-            .map_err(|e| e.to_string())?
-    };
-
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).to_string());
-    }
-
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+pub async fn install_service_handler(
+    State(state): State<AppState>,
+    actor: Option<Extension<Actor>>,
+    Json(payload): Json<InstallServiceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let actor = actor.map(|a| a.0).unwrap_or_else(Actor::anonymous);
+    info!("Request to install service {}", payload.service);
+    start_job(state, actor, payload.service, payload.command, false).await
 }
 
-/// Simple safety filter to catch accidental or malicious system destruction
-fn is_dangerous_command(cmd: &str) -> bool {
-    let cmd_lower = cmd.to_lowercase();
+/// Recipe catalog available on this node (`GET /services/recipes`).
+pub async fn list_recipes_handler(
+    State(state): State<AppState>,
+) -> Result<Json<CatalogSummary>, AppError> {
+    let catalog = Catalog::load(&state.settings)?;
+    Ok(Json(CatalogSummary::from(&catalog)))
+}
 
-    // Patterns that are highly likely to cause irreversible system damage
-    // Note: This is a guardrail, not an absolute sandbox.
-    let extreme_danger = [
-        "rm -rf / ",
-        "rm -rf /\"",
-        "rm -rf /*",
-        "rm -rf /etc",
-        "rm -rf /bin",
-        "rm -rf /boot",
-        "rm -rf /dev",
-        "rm -rf /sbin",
-        "rm -rf /usr",
-        "mkfs",
-        "dd if=/dev/",
-        "> /dev/sd",
-        "> /dev/nvme",
-        "chmod -r 777 /",
-        "chown -r 777 /",
-    ];
+/// Run a command typed by the local user (CLI without a running daemon),
+/// printing its output live. Ctrl-C stops the command and its children.
+/// Not subject to the remote policy mode, but to its limits and `run_as`.
+pub async fn run_generic_command(settings: &Settings, cmd: &str) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let printer = tokio::spawn(async move {
+        while let Some((stream, line)) = rx.recv().await {
+            match stream {
+                OutputStream::Stdout => println!("{}", line),
+                OutputStream::Stderr => eprintln!("{}", line),
+            }
+        }
+    });
+
+    let cancel = Arc::new(Notify::new());
+    let interrupt = {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.notify_one();
+            }
+        })
+    };
 
-    extreme_danger.iter().any(|&p| cmd_lower.contains(p))
+    let execution = Execution::Shell(cmd.to_string());
+    let output = exec::run_with(&settings.exec, &execution, Some(tx), Some(&cancel)).await;
+    interrupt.abort();
+    let _ = printer.await;
+
+    let output = output?;
+    if !output.success() {
+        return Err(match output.exit_code {
+            Some(code) => format!("exited with code {}", code),
+            None => output.stderr,
+        });
+    }
+    Ok(())
 }
 
 #[derive(Deserialize)]
@@ -126,37 +107,11 @@ pub struct UninstallServiceRequest {
 }
 
 pub async fn uninstall_service_handler(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    actor: Option<Extension<Actor>>,
     Json(payload): Json<UninstallServiceRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let service = payload.service.as_str();
-
-    // If a command is provided in the request, use it.
-    let cmd_to_run = payload.command.clone().ok_or_else(|| {
-        error!(
-            "No command provided for service uninstallation: {}",
-            service
-        );
-        AppError::BadRequest("No command provided".to_string())
-    })?;
-
-    info!("Request to UNINSTALL service {}: {}", service, cmd_to_run);
-
-    match run_generic_command(&cmd_to_run).await {
-        Ok(_) => {
-            info!("Uninstall command executed successfully for {}", service);
-            Ok((
-                StatusCode::OK,
-                Json(ServiceResponse {
-                    status: "uninstalled".to_string(),
-                    service: service.to_string(),
-                    command: cmd_to_run,
-                }),
-            ))
-        }
-        Err(e) => {
-            error!("Uninstallation failed: {}", e);
-            Err(AppError::InternalServerError)
-        }
-    }
+    let actor = actor.map(|a| a.0).unwrap_or_else(Actor::anonymous);
+    info!("Request to UNINSTALL service {}", payload.service);
+    start_job(state, actor, payload.service, payload.command, true).await
 }
@@ -0,0 +1,72 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Signature scheme v2 headers.
+pub const SIGNATURE_HEADER: &str = "X-Zexio-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Zexio-Timestamp";
+pub const NONCE_HEADER: &str = "X-Zexio-Nonce";
+
+/// Legacy body-only HMAC header, accepted during the transition period.
+pub const LEGACY_SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Prefix of v2 signature header values: `X-Zexio-Signature: v2=<hex>`.
+pub const VERSION_PREFIX: &str = "v2=";
+
+/// String signed by v2 signatures (HMAC-SHA256 with the worker secret):
+///
+/// ```text
+/// v2\n<METHOD>\n<path>\n<sorted query>\n<hex sha256(body)>\n<timestamp>\n<nonce>
+/// ```
+///
+/// The query is split on `&` and the raw `key=value` pairs are sorted
+/// byte-wise, so clients don't need to agree on parameter order.
+pub fn canonical_string(
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+    timestamp: &str,
+    nonce: &str,
+) -> String {
+    let mut pairs: Vec<&str> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .collect();
+    pairs.sort_unstable();
+
+    format!(
+        "v2\n{}\n{}\n{}\n{}\n{}\n{}",
+        method.to_ascii_uppercase(),
+        path,
+        pairs.join("&"),
+        hex::encode(Sha256::digest(body)),
+        timestamp,
+        nonce
+    )
+}
+
+/// Nonces seen within the clock-skew window. A nonce only has to be
+/// remembered as long as its timestamp would still be accepted.
+#[derive(Clone, Default)]
+pub struct NonceCache {
+    seen: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl NonceCache {
+    /// Record a nonce; returns `false` if it was already used.
+    pub fn check_and_insert(&self, nonce: &str, timestamp: i64, window_secs: i64) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        let Ok(mut seen) = self.seen.lock() else {
+            return false;
+        };
+
+        seen.retain(|_, ts| (now - *ts).abs() <= window_secs);
+        if seen.contains_key(nonce) {
+            return false;
+        }
+        seen.insert(nonce.to_string(), timestamp);
+        true
+    }
+}
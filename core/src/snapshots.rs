@@ -0,0 +1,622 @@
+use crate::crypto::{Crypto, StreamEncryptor};
+use crate::s3::S3Client;
+use crate::storage::SnapshotPolicy;
+use crate::{errors::AppError, state::AppState, volumes};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read};
+use std::path::{Path as FsPath, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    pub id: String,
+    pub project_id: String,
+    pub created_at: DateTime<Utc>,
+    pub size_bytes: u64,
+    pub sha256: String,  // Of the encrypted archive
+    pub trigger: String, // "manual" | "scheduled"
+    #[serde(default)]
+    pub uploaded: bool,
+}
+
+fn project_snapshots_dir(state: &AppState, project_id: &str) -> PathBuf {
+    PathBuf::from(&state.settings.storage.snapshots_dir).join(project_id)
+}
+
+fn archive_path(state: &AppState, project_id: &str, snap_id: &str) -> PathBuf {
+    project_snapshots_dir(state, project_id).join(format!("{}.tar.gz.enc", snap_id))
+}
+
+fn meta_path(state: &AppState, project_id: &str, snap_id: &str) -> PathBuf {
+    project_snapshots_dir(state, project_id).join(format!("{}.json", snap_id))
+}
+
+fn remote_key(project_id: &str, snap_id: &str) -> String {
+    format!("{}/{}.tar.gz.enc", project_id, snap_id)
+}
+
+/// Where an archive is written or downloaded before it is complete.
+fn partial_path(path: &FsPath) -> PathBuf {
+    PathBuf::from(format!("{}.part", path.to_string_lossy()))
+}
+
+fn file_sha256(path: &FsPath) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Encrypt what `fill` writes into `path` (via a partial file, so a failure
+/// leaves nothing behind). Returns the size and SHA-256 of the archive.
+fn write_encrypted(
+    crypto: &Crypto,
+    project_id: &str,
+    path: &FsPath,
+    fill: impl FnOnce(&mut StreamEncryptor<BufWriter<File>>) -> Result<()>,
+) -> Result<(u64, String)> {
+    let partial = partial_path(path);
+    let result = (|| -> Result<(u64, String)> {
+        let file = File::create(&partial)?;
+        let mut out = crypto.encrypt_stream_for(project_id, BufWriter::new(file))?;
+        fill(&mut out)?;
+        out.finish()?
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        Ok((std::fs::metadata(&partial)?.len(), file_sha256(&partial)?))
+    })();
+
+    match result {
+        Ok(written) => {
+            std::fs::rename(&partial, path)?;
+            Ok(written)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Decrypting reader over an archive. Archives from before streamed
+/// encryption are a single ciphertext and are decrypted in memory.
+fn open_archive(crypto: &Crypto, project_id: &str, path: &FsPath) -> Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if Crypto::is_stream_ciphertext(reader.fill_buf()?) {
+        return Ok(Box::new(crypto.decrypt_stream_for(project_id, reader)?));
+    }
+
+    let mut encrypted = Vec::new();
+    reader.read_to_end(&mut encrypted)?;
+    Ok(Box::new(std::io::Cursor::new(
+        crypto.decrypt_for(project_id, &encrypted)?,
+    )))
+}
+
+/// Path of the archive, downloading it to a partial file when only the
+/// uploaded copy is left. The flag tells whether the path is temporary.
+async fn fetch(
+    state: &AppState,
+    s3: Option<&S3Client>,
+    meta: &SnapshotMeta,
+) -> Result<Option<(PathBuf, bool)>> {
+    let path = archive_path(state, &meta.project_id, &meta.id);
+    if tokio::fs::try_exists(&path).await? {
+        return Ok(Some((path, false)));
+    }
+    let (Some(s3), true) = (s3, meta.uploaded) else {
+        return Ok(None);
+    };
+
+    info!("Fetching snapshot {} from S3", meta.id);
+    let partial = partial_path(&path);
+    if let Err(e) = s3
+        .get_file(&remote_key(&meta.project_id, &meta.id), &partial)
+        .await
+    {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(e);
+    }
+    Ok(Some((partial, true)))
+}
+
+fn validate_snapshot_id(snap_id: &str) -> Result<(), AppError> {
+    if snap_id.is_empty()
+        || !snap_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(AppError::BadRequest("Invalid snapshot id".into()));
+    }
+    Ok(())
+}
+
+/// List snapshots of a project, newest first.
+pub async fn list(state: &AppState, project_id: &str) -> Result<Vec<SnapshotMeta>> {
+    let dir = project_snapshots_dir(state, project_id);
+    let mut snapshots = Vec::new();
+
+    let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+        return Ok(snapshots);
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        if let Ok(json) = tokio::fs::read_to_string(&path).await {
+            if let Ok(meta) = serde_json::from_str::<SnapshotMeta>(&json) {
+                snapshots.push(meta);
+            }
+        }
+    }
+
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+    Ok(snapshots)
+}
+
+/// Archive, compress and encrypt the project's volumes.
+pub async fn create(
+    state: &AppState,
+    project_id: &str,
+    trigger: &str,
+    upload: bool,
+) -> Result<SnapshotMeta> {
+    let source = volumes::project_volumes_dir(&state.settings, project_id);
+    tokio::fs::create_dir_all(&source).await?;
+
+    let now = Utc::now();
+    let id = format!(
+        "{}-{}",
+        now.format("%Y%m%dT%H%M%SZ"),
+        &uuid::Uuid::new_v4().simple().to_string()[..6]
+    );
+
+    tokio::fs::create_dir_all(project_snapshots_dir(state, project_id)).await?;
+    let path = archive_path(state, project_id, &id);
+
+    // Archive, compress and encrypt in one pass straight to disk
+    let (size_bytes, sha256) = tokio::task::spawn_blocking({
+        let crypto = state.crypto.clone();
+        let project_id = project_id.to_string();
+        let path = path.clone();
+        move || {
+            write_encrypted(&crypto, &project_id, &path, |out| {
+                let encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+                let mut tar = tar::Builder::new(encoder);
+                tar.follow_symlinks(false);
+                tar.append_dir_all(".", &source)
+                    .context("Failed to archive volumes")?;
+                tar.into_inner()?.finish()?;
+                Ok(())
+            })
+        }
+    })
+    .await??;
+
+    let mut meta = SnapshotMeta {
+        id: id.clone(),
+        project_id: project_id.to_string(),
+        created_at: now,
+        size_bytes,
+        sha256,
+        trigger: trigger.to_string(),
+        uploaded: false,
+    };
+
+    if upload {
+        match S3Client::from_settings(&state.settings.snapshots)? {
+            Some(s3) => match s3
+                .put_file(&remote_key(project_id, &id), &path, &meta.sha256)
+                .await
+            {
+                Ok(()) => meta.uploaded = true,
+                Err(e) => error!("Snapshot {} upload failed: {}", id, e),
+            },
+            None => warn!("Snapshot upload requested but no S3 endpoint is configured"),
+        }
+    }
+
+    tokio::fs::write(
+        meta_path(state, project_id, &id),
+        serde_json::to_string_pretty(&meta)?,
+    )
+    .await?;
+
+    info!(
+        "Created snapshot {} of project {} ({} bytes)",
+        id, project_id, meta.size_bytes
+    );
+    Ok(meta)
+}
+
+/// Delete a snapshot locally and, if uploaded, remotely.
+pub async fn remove(state: &AppState, meta: &SnapshotMeta) -> Result<()> {
+    let _ = tokio::fs::remove_file(archive_path(state, &meta.project_id, &meta.id)).await;
+    let _ = tokio::fs::remove_file(meta_path(state, &meta.project_id, &meta.id)).await;
+
+    if meta.uploaded {
+        if let Some(s3) = S3Client::from_settings(&state.settings.snapshots)? {
+            s3.delete_object(&remote_key(&meta.project_id, &meta.id))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Select which snapshots survive the retention policy. `snapshots` must be
+/// sorted newest first. The newest snapshot is always kept.
+fn retained_ids(snapshots: &[SnapshotMeta], policy: &SnapshotPolicy) -> HashSet<String> {
+    let mut keep: HashSet<String> = snapshots
+        .iter()
+        .take(policy.keep_last.max(1))
+        .map(|s| s.id.clone())
+        .collect();
+
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    for snapshot in snapshots {
+        let day = snapshot.created_at.date_naive();
+        if days.len() < policy.keep_daily && days.insert(day) {
+            keep.insert(snapshot.id.clone());
+        }
+
+        let week = snapshot.created_at.iso_week();
+        if weeks.len() < policy.keep_weekly && weeks.insert((week.year(), week.week())) {
+            keep.insert(snapshot.id.clone());
+        }
+    }
+
+    keep
+}
+
+/// Apply the retention policy of a project.
+pub async fn prune(state: &AppState, project_id: &str, policy: &SnapshotPolicy) -> Result<()> {
+    let snapshots = list(state, project_id).await?;
+    let keep = retained_ids(&snapshots, policy);
+
+    for snapshot in snapshots.iter().filter(|s| !keep.contains(&s.id)) {
+        info!(
+            "Pruning snapshot {} of project {} (retention)",
+            snapshot.id, project_id
+        );
+        if let Err(e) = remove(state, snapshot).await {
+            warn!("Failed to prune snapshot {}: {}", snapshot.id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Stop the app, replace its volumes with the snapshot content and restart it.
+pub async fn restore(state: &AppState, meta: &SnapshotMeta) -> Result<()> {
+    let project_id = &meta.project_id;
+    let s3 = S3Client::from_settings(&state.settings.snapshots)?;
+    let (path, temporary) = match fetch(state, s3.as_ref(), meta).await? {
+        Some(found) => found,
+        None if meta.uploaded => anyhow::bail!("Snapshot is remote but S3 is not configured"),
+        None => anyhow::bail!("Snapshot archive missing"),
+    };
+
+    let archive = tokio::task::spawn_blocking({
+        let crypto = state.crypto.clone();
+        let project_id = project_id.clone();
+        let path = path.clone();
+        let expected = meta.sha256.clone();
+        move || -> Result<Box<dyn Read + Send>> {
+            if file_sha256(&path)? != expected {
+                anyhow::bail!("Snapshot failed integrity check");
+            }
+            open_archive(&crypto, &project_id, &path)
+        }
+    })
+    .await?;
+    let result = match archive {
+        Ok(archive) => restore_archive(state, meta, archive).await,
+        Err(e) => Err(e.context(format!("Cannot read snapshot {}", meta.id))),
+    };
+
+    if temporary {
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+    result
+}
+
+/// The part of a restore that touches the app and its volumes.
+async fn restore_archive(
+    state: &AppState,
+    meta: &SnapshotMeta,
+    archive: Box<dyn Read + Send>,
+) -> Result<()> {
+    let project_id = &meta.project_id;
+
+    let unit = format!("app@{}.service", project_id);
+    let _ = Command::new("systemctl").arg("stop").arg(&unit).output();
+
+    let target = volumes::project_volumes_dir(&state.settings, project_id);
+    let previous = PathBuf::from(format!("{}.pre-restore", target.to_string_lossy()));
+
+    let result = tokio::task::spawn_blocking({
+        let target = target.clone();
+        let previous = previous.clone();
+        move || -> Result<()> {
+            if previous.exists() {
+                std::fs::remove_dir_all(&previous)?;
+            }
+            if target.exists() {
+                std::fs::rename(&target, &previous)?;
+            }
+            std::fs::create_dir_all(&target)?;
+
+            let decoder = flate2::read::GzDecoder::new(archive);
+            let mut tar = tar::Archive::new(decoder);
+            tar.set_preserve_permissions(true);
+            tar.unpack(&target).context("Failed to extract snapshot")
+        }
+    })
+    .await?;
+
+    match result {
+        Ok(()) => {
+            let _ = tokio::fs::remove_dir_all(&previous).await;
+        }
+        Err(e) => {
+            // Roll back to the data we had before the restore attempt
+            let _ = tokio::fs::remove_dir_all(&target).await;
+            let _ = tokio::fs::rename(&previous, &target).await;
+            let _ = Command::new("systemctl").arg("start").arg(&unit).output();
+            return Err(e);
+        }
+    }
+
+    let output = Command::new("systemctl").arg("start").arg(&unit).output()?;
+    if !output.status.success() {
+        warn!(
+            "Restored snapshot {} but {} failed to start: {}",
+            meta.id,
+            unit,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    info!("Restored snapshot {} of project {}", meta.id, project_id);
+    Ok(())
+}
+
+/// Re-encrypt archives still under a master key with the project's data key,
+/// so key rotation can retire old master keys. Returns the number rewritten.
+pub async fn reencrypt_legacy(state: &AppState, project_id: &str) -> Result<usize> {
+    let s3 = S3Client::from_settings(&state.settings.snapshots)?;
+    let mut rewritten = 0;
+
+    for mut meta in list(state, project_id).await? {
+        let Some((source, temporary)) = fetch(state, s3.as_ref(), &meta).await? else {
+            continue;
+        };
+        let path = archive_path(state, project_id, &meta.id);
+
+        let result = tokio::task::spawn_blocking({
+            let crypto = state.crypto.clone();
+            let project_id = project_id.to_string();
+            let source = source.clone();
+            let path = path.clone();
+            move || -> Result<Option<(u64, String)>> {
+                let mut header = Vec::new();
+                File::open(&source)?.take(260).read_to_end(&mut header)?;
+                if Crypto::is_stream_ciphertext(&header) || Crypto::is_data_key_ciphertext(&header)
+                {
+                    return Ok(None);
+                }
+
+                // Legacy archives are one ciphertext, so this one pass is in memory
+                let archive = crypto.decrypt_for(&project_id, &std::fs::read(&source)?)?;
+                let written = write_encrypted(&crypto, &project_id, &path, |out| {
+                    std::io::Write::write_all(out, &archive)?;
+                    Ok(())
+                })?;
+                Ok(Some(written))
+            }
+        })
+        .await?;
+        if temporary {
+            let _ = tokio::fs::remove_file(&source).await;
+        }
+        let Some((size_bytes, sha256)) = result? else {
+            continue;
+        };
+
+        meta.size_bytes = size_bytes;
+        meta.sha256 = sha256;
+        if meta.uploaded {
+            if let Some(s3) = &s3 {
+                s3.put_file(&remote_key(project_id, &meta.id), &path, &meta.sha256)
+                    .await?;
+            }
+        }
+        tokio::fs::write(
+            meta_path(state, project_id, &meta.id),
+            serde_json::to_string_pretty(&meta)?,
+        )
+        .await?;
+        rewritten += 1;
+    }
+
+    Ok(rewritten)
+}
+
+/// Purge all snapshots of a project, including uploaded copies.
+pub async fn purge(state: &AppState, project_id: &str) -> Result<()> {
+    if let Some(s3) = S3Client::from_settings(&state.settings.snapshots)? {
+        for meta in list(state, project_id).await?.iter().filter(|m| m.uploaded) {
+            if let Err(e) = s3.delete_object(&remote_key(project_id, &meta.id)).await {
+                warn!("Failed to delete uploaded snapshot {}: {}", meta.id, e);
+            }
+        }
+    }
+
+    let dir = project_snapshots_dir(state, project_id);
+    if dir.exists() {
+        tokio::fs::remove_dir_all(&dir).await?;
+    }
+    Ok(())
+}
+
+/// Background task: take scheduled snapshots and apply retention.
+pub async fn scheduler(state: AppState) {
+    loop {
+        if let Ok(projects) = state.store.list().await {
+            for project in projects {
+                let Some(policy) = project.snapshot_policy.clone() else {
+                    continue;
+                };
+                if policy.interval_hours == 0 {
+                    continue;
+                }
+
+                let latest = list(&state, &project.id)
+                    .await
+                    .ok()
+                    .and_then(|s| s.first().map(|m| m.created_at));
+                let due = latest
+                    .map(|t| {
+                        Utc::now() - t >= chrono::Duration::hours(policy.interval_hours as i64)
+                    })
+                    .unwrap_or(true);
+
+                if !due {
+                    continue;
+                }
+
+                if let Err(e) = create(&state, &project.id, "scheduled", policy.upload).await {
+                    error!("Scheduled snapshot of {} failed: {}", project.id, e);
+                    continue;
+                }
+                if let Err(e) = prune(&state, &project.id, &policy).await {
+                    warn!("Snapshot retention for {} failed: {}", project.id, e);
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    }
+}
+
+async fn find(state: &AppState, project_id: &str, snap_id: &str) -> Result<SnapshotMeta, AppError> {
+    validate_snapshot_id(snap_id)?;
+    let json = tokio::fs::read_to_string(meta_path(state, project_id, snap_id))
+        .await
+        .map_err(|_| AppError::BadRequest("Snapshot not found".into()))?;
+    serde_json::from_str(&json).map_err(|_| AppError::InternalServerError)
+}
+
+pub async fn list_snapshots_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+) -> Result<Json<Vec<SnapshotMeta>>, AppError> {
+    Ok(Json(list(&state, &project_id).await?))
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateSnapshotRequest {
+    pub upload: Option<bool>,
+}
+
+pub async fn create_snapshot_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    payload: Option<Json<CreateSnapshotRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let upload = payload.upload.unwrap_or_else(|| {
+        config
+            .snapshot_policy
+            .as_ref()
+            .map(|p| p.upload)
+            .unwrap_or(false)
+    });
+
+    let meta = create(&state, &project_id, "manual", upload).await?;
+    Ok((StatusCode::CREATED, Json(meta)))
+}
+
+pub async fn delete_snapshot_handler(
+    State(state): State<AppState>,
+    Path((project_id, snap_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let meta = find(&state, &project_id, &snap_id).await?;
+    remove(&state, &meta).await?;
+    Ok((StatusCode::OK, "Snapshot deleted"))
+}
+
+pub async fn restore_snapshot_handler(
+    State(state): State<AppState>,
+    Path((project_id, snap_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let meta = find(&state, &project_id, &snap_id).await?;
+    info!("Restoring snapshot {} of project {}", snap_id, project_id);
+    restore(&state, &meta).await?;
+    Ok((StatusCode::OK, format!("Restored snapshot: {}", snap_id)))
+}
+
+pub async fn get_schedule_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+) -> Result<Json<Option<SnapshotPolicy>>, AppError> {
+    let config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    Ok(Json(config.snapshot_policy))
+}
+
+pub async fn update_schedule_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    Json(policy): Json<SnapshotPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+
+    let s3_configured = state.settings.snapshots.s3_endpoint.is_some()
+        && state.settings.snapshots.s3_bucket.is_some();
+    if policy.upload && !s3_configured {
+        return Err(AppError::BadRequest(
+            "upload requires snapshots.s3_endpoint and snapshots.s3_bucket to be configured".into(),
+        ));
+    }
+
+    config.snapshot_policy = Some(policy);
+    state
+        .store
+        .update(&config)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+
+    Ok((StatusCode::OK, "Snapshot schedule updated"))
+}
@@ -1,16 +1,54 @@
-use crate::{config::Settings, crypto::Crypto, storage::ProjectStore};
-use anyhow::Result;
+use crate::{
+    audit::AuditLog,
+    caddy::Caddy,
+    config::Settings,
+    crypto::Crypto,
+    inventory::ServiceInventory,
+    jobs::JobManager,
+    mesh::access_log::AccessLog,
+    mesh::balancer::Balancer,
+    mesh::certs::CertManager,
+    mesh::https::BridgedClients,
+    mesh::jwks::Jwks,
+    mesh::metrics::MeshMetrics,
+    mesh::resolver::{ChainResolver, DomainTable},
+    mesh::token::{MeshAuth, MeshKeys},
+    sealed::NodeKey,
+    signing::NonceCache,
+    storage::ProjectStore,
+    tokens::TokenStore,
+    volumes::UsageCache,
+};
+use anyhow::{bail, Result};
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+
+/// Fallback of `MESH_JWT_SECRET`, for standalone nodes only.
+const DEFAULT_MESH_SECRET: &str = "zexio-mesh-secret-key";
 
 #[derive(Clone)]
 pub struct AppState {
     pub store: ProjectStore,
     pub settings: Settings,
     pub crypto: Crypto,
+    pub node_key: NodeKey,
     pub worker_secret: String,
-    pub redis: redis::Client,
-    pub mesh_jwt_secret: String,
+    pub mesh_auth: MeshAuth, // Service token verification
+    pub mesh_metrics: MeshMetrics,
+    pub access_log: AccessLog, // Mesh requests
+    pub nonces: NonceCache,
+    pub tokens: TokenStore,
+    pub audit: AuditLog,
+    pub jobs: JobManager,
+    pub inventory: ServiceInventory,
+    pub resolver: Arc<ChainResolver>, // Mesh host resolution
+    pub domains: DomainTable,         // Custom domains routed by the mesh
+    pub balancer: Balancer,           // Upstream selection and health
+    pub certs: CertManager,           // TLS certificates of custom domains
+    pub bridged: BridgedClients,      // Clients of the mesh HTTPS listener
+    pub caddy: Option<Arc<Caddy>>,    // Optional Caddy ingress for custom domains
+    pub volume_usage: UsageCache,     // Refreshed by the quota monitor
 }
 
 impl AppState {
@@ -18,32 +56,107 @@ impl AppState {
         // Ensure all required directories exist
         Self::ensure_directories(&settings)?;
 
-        // Initialize crypto (auto-generates master key if needed)
-        let crypto = Crypto::new(&settings.secrets.master_key_path)?;
+        // Initialize crypto (loads or bootstraps the master keyring)
+        let crypto = Crypto::new(&settings.secrets)?;
+
+        // Load or generate the node keypair used to open sealed secrets
+        let node_key = NodeKey::load_or_generate(&settings.secrets.node_key_path)?;
 
         // Load or generate worker secret
         let worker_secret =
             Self::load_or_generate_worker_secret(&settings.secrets.worker_secret_path)?;
 
-        // Initialize Redis
+        // Mesh service registry
         let redis_url =
             std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string());
         let redis = redis::Client::open(redis_url)?;
 
-        // Mesh JWT Secret
-        let mesh_jwt_secret = std::env::var("MESH_JWT_SECRET")
-            .unwrap_or_else(|_| "zexio-mesh-secret-key".to_string());
+        let mesh_auth = Self::mesh_auth(&settings)?;
+
+        // API tokens (re-read when the CLI changes them)
+        let tokens = TokenStore::new(&settings.secrets.tokens_path);
+        let audit = AuditLog::new(&settings.storage.audit_log_path);
+        let inventory = ServiceInventory::new(&settings.storage.services_path);
+        let certs = CertManager::new(&settings.storage.certs_dir);
+
+        let caddy = if settings.caddy.enabled {
+            Some(Arc::new(Caddy::new(settings.caddy.clone())?))
+        } else {
+            None
+        };
+
+        let access_log = AccessLog::new(&settings.mesh.access_log);
+        let store = crate::storage::ProjectStore::new(&settings.storage.projects_dir);
+        let domains = DomainTable::default();
+        let resolver = Arc::new(ChainResolver::standard(
+            settings.mesh.routes.clone(),
+            domains.clone(),
+            redis,
+            settings.server.public_ip.clone(),
+        ));
 
         Ok(Self {
-            store: crate::storage::ProjectStore::new(&settings.storage.projects_dir),
+            store,
             settings,
             crypto,
+            node_key,
             worker_secret,
-            redis,
-            mesh_jwt_secret,
+            mesh_auth,
+            mesh_metrics: MeshMetrics::default(),
+            access_log,
+            nonces: NonceCache::default(),
+            tokens,
+            audit,
+            jobs: JobManager::default(),
+            inventory,
+            resolver,
+            domains,
+            balancer: Balancer::default(),
+            certs,
+            bridged: BridgedClients::default(),
+            caddy,
+            volume_usage: UsageCache::default(),
+        })
+    }
+
+    /// Service token keys: the JWKS if configured, else the shared secret.
+    /// A cloud node must not run with the well-known default secret, which
+    /// would let anyone mint tokens for it.
+    fn mesh_auth(settings: &Settings) -> Result<MeshAuth> {
+        let mesh = &settings.mesh;
+        let keys = if let Some(path) = &mesh.jwks_path {
+            MeshKeys::Jwks(Jwks::from_file(path))
+        } else if let Some(url) = &mesh.jwks_url {
+            MeshKeys::Jwks(Jwks::from_url(url)?)
+        } else {
+            MeshKeys::SharedSecret(
+                std::env::var("MESH_JWT_SECRET")
+                    .unwrap_or_else(|_| DEFAULT_MESH_SECRET.to_string()),
+            )
+        };
+        Ok(MeshAuth {
+            keys,
+            audience: mesh.audience.clone(),
         })
     }
 
+    /// Refuse to serve the mesh of a cloud node with the well-known default
+    /// secret. Only the daemon checks this: CLI commands such as `zexio keys
+    /// rotate` don't verify tokens and may lack the service environment.
+    pub fn check_mesh_secret(&self) -> Result<()> {
+        let default = matches!(
+            &self.mesh_auth.keys,
+            MeshKeys::SharedSecret(secret) if secret == DEFAULT_MESH_SECRET
+        );
+        if default && self.settings.cloud.is_cloud_mode() {
+            bail!(
+                "Refusing to start in cloud mode with the default mesh secret: \
+                 set mesh.jwks_url (or mesh.jwks_path), or MESH_JWT_SECRET"
+            );
+        }
+        Ok(())
+    }
+
     /// Ensure all required directories exist
     fn ensure_directories(settings: &Settings) -> Result<()> {
         // Create projects directory
@@ -55,6 +168,20 @@ impl AppState {
             );
         }
 
+        // Create volumes directory (kept outside project releases)
+        if !Path::new(&settings.storage.volumes_dir).exists() {
+            fs::create_dir_all(&settings.storage.volumes_dir)?;
+            tracing::info!(
+                "Created volumes directory: {}",
+                settings.storage.volumes_dir
+            );
+        }
+
+        // Create snapshots directory
+        if !Path::new(&settings.storage.snapshots_dir).exists() {
+            fs::create_dir_all(&settings.storage.snapshots_dir)?;
+        }
+
         // Create secrets directory (parent of all secret files)
         if let Some(parent) = Path::new(&settings.secrets.master_key_path).parent() {
             if !parent.exists() {
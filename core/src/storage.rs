@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
@@ -7,10 +8,81 @@ use tokio::fs;
 pub struct ProjectConfig {
     pub id: String,
     pub domains: Vec<String>,
-    pub encrypted_env: String, // Hex-encoded encrypted blob
+    pub encrypted_env: String, // Hex-encoded encrypted blob (legacy, replaced wholesale)
+    #[serde(default)]
+    pub env: BTreeMap<String, String>, // Per-key values, each hex-encoded & encrypted
     pub webhook_secret: String,
     #[serde(default = "chrono::Utc::now")]
     pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub volumes: Vec<VolumeConfig>,
+    #[serde(default)]
+    pub snapshot_policy: Option<SnapshotPolicy>,
+    #[serde(default)]
+    pub databases: Vec<DatabaseBinding>,
+}
+
+/// Local port a project's app listens on, derived from its id.
+pub fn project_port(project_id: &str) -> u16 {
+    8000 + (crc32fast::hash(project_id.as_bytes()) % 1000) as u16
+}
+
+/// Schedule and retention for volume snapshots. A snapshot is kept if it is
+/// among the `keep_last` newest, or the newest of one of the last `keep_daily`
+/// days or `keep_weekly` ISO weeks that have snapshots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotPolicy {
+    pub interval_hours: u32, // 0 = manual snapshots only
+    #[serde(default = "default_keep_last")]
+    pub keep_last: usize,
+    #[serde(default)]
+    pub keep_daily: usize,
+    #[serde(default)]
+    pub keep_weekly: usize,
+    #[serde(default)]
+    pub upload: bool, // Also push to the configured S3 endpoint
+}
+
+fn default_keep_last() -> usize {
+    7
+}
+
+/// A named persistent volume. Data lives under `storage.volumes_dir` and is
+/// symlinked into the release directory on every deploy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeConfig {
+    pub name: String,
+    pub mount_path: String, // Relative to the release (bundle) directory
+    pub size_limit_mb: Option<u64>,
+    #[serde(default = "chrono::Utc::now")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseEngine {
+    Postgres,
+    Redis,
+}
+
+/// A Postgres database or Redis ACL user provisioned for the project. Its
+/// connection URL is written to the project's `.env` on deploy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseBinding {
+    pub engine: DatabaseEngine,
+    pub name: String, // Postgres database / Redis user, unique on the node
+    pub username: String,
+    pub password: String, // Hex-encoded, encrypted with the project's data key
+    pub env_key: String,  // e.g. DATABASE_URL
+    #[serde(default = "chrono::Utc::now")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Exclusive lock on a project's config, released on drop. It is a file
+/// lock, so the daemon and CLI commands (`zexio keys rotate`) exclude each
+/// other while they read, modify and write `config.json`.
+pub struct ConfigLock {
+    _file: std::fs::File,
 }
 
 #[derive(Clone)]
@@ -29,6 +101,23 @@ impl ProjectStore {
         self.base_dir.join(project_id).join("config.json")
     }
 
+    /// Wait for exclusive access to a project's config. Don't nest: a second
+    /// lock on the same project blocks even within one process.
+    pub async fn lock(&self, project_id: &str) -> Result<ConfigLock> {
+        let path = self.base_dir.join(project_id).join("config.lock");
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {:?}", path))?;
+            file.lock().context("Failed to lock project config")?;
+            Ok(ConfigLock { _file: file })
+        })
+        .await?
+    }
+
     pub async fn create(&self, config: ProjectConfig) -> Result<()> {
         let config_path = self.config_path(&config.id);
 
@@ -0,0 +1,225 @@
+use crate::config::Settings;
+use anyhow::{bail, Context, Result};
+use axum::{body::Body, Router};
+use rcgen::{CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, SanType};
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// Clients must finish the TLS handshake within this time.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Make sure a certificate exists when TLS is enabled, generating a
+/// self-signed one on first boot. Returns its SHA-256 fingerprint.
+pub fn ensure_certificate(settings: &Settings) -> Result<Option<String>> {
+    if !settings.server.tls_enabled {
+        return Ok(None);
+    }
+
+    let cert_path = Path::new(&settings.server.tls_cert_path);
+    let key_path = Path::new(&settings.server.tls_key_path);
+    if !cert_path.exists() || !key_path.exists() {
+        generate_self_signed(settings, cert_path, key_path)?;
+    }
+
+    let pem = std::fs::read(cert_path)
+        .with_context(|| format!("Failed to read TLS certificate {:?}", cert_path))?;
+    let chain = parse_chain(&pem)?;
+    Ok(Some(fingerprint(&chain[0])))
+}
+
+/// SHA-256 fingerprint as uppercase hex pairs (`AB:CD:...`).
+pub fn fingerprint(cert: &Certificate) -> String {
+    ring::digest::digest(&ring::digest::SHA256, &cert.0)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Certificates of a PEM chain, leaf first.
+pub fn parse_chain(pem: &[u8]) -> Result<Vec<Certificate>> {
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(pem))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if chain.is_empty() {
+        bail!("No certificate found");
+    }
+    Ok(chain)
+}
+
+/// First private key (PKCS#8, SEC1 or PKCS#1) of a PEM file.
+pub fn parse_key(pem: &[u8]) -> Result<PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut BufReader::new(pem))? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key)
+            | rustls_pemfile::Item::RSAKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    bail!("No private key found")
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to load {}", path))
+}
+
+fn generate_self_signed(settings: &Settings, cert_path: &Path, key_path: &Path) -> Result<()> {
+    let hostname = hostname::get()?.to_string_lossy().to_string();
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, hostname.as_str());
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "Zexio Agent");
+    let now = time::OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + time::Duration::days(825);
+    params.serial_number = Some(rand::random::<[u8; 16]>().to_vec().into());
+
+    params.subject_alt_names = vec![
+        SanType::DnsName(hostname),
+        SanType::DnsName("localhost".into()),
+        SanType::IpAddress([127, 0, 0, 1].into()),
+        SanType::IpAddress(std::net::Ipv6Addr::LOCALHOST.into()),
+    ];
+    if let Some(public_hostname) = &settings.server.public_hostname {
+        params
+            .subject_alt_names
+            .push(SanType::DnsName(public_hostname.clone()));
+    }
+    if let Some(public_ip) = &settings.server.public_ip {
+        params.subject_alt_names.push(SanType::IpAddress(
+            public_ip.parse().context("Invalid server.public_ip")?,
+        ));
+    }
+    params.is_ca = IsCa::ExplicitNoCa;
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let cert = rcgen::Certificate::from_params(params)?;
+
+    if let Some(parent) = cert_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if let Some(parent) = key_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(cert_path, cert.serialize_pem()?)?;
+    std::fs::write(key_path, cert.serialize_private_key_pem())?;
+
+    // Secure the key (Unix only)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(key_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    tracing::info!(
+        "Generated self-signed TLS certificate at {:?} ({})",
+        cert_path,
+        fingerprint(&Certificate(cert.serialize_der()?))
+    );
+    Ok(())
+}
+
+/// TLS acceptor for the Management API. With `tls_client_ca_path` set,
+/// clients must present a certificate issued by that CA.
+pub fn acceptor(settings: &Settings) -> Result<TlsAcceptor> {
+    let chain = parse_chain(&read(&settings.server.tls_cert_path)?)
+        .with_context(|| format!("Failed to load {}", settings.server.tls_cert_path))?;
+    let key = parse_key(&read(&settings.server.tls_key_path)?)
+        .with_context(|| format!("Failed to load {}", settings.server.tls_key_path))?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &settings.server.tls_client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in parse_chain(&read(ca_path)?)
+                .with_context(|| format!("Failed to load client CA {}", ca_path))?
+            {
+                roots.add(&cert)?;
+            }
+            tracing::info!("🔐 Client certificates required (CA: {})", ca_path);
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(chain, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Serve `app` over TLS on `listener` until the process exits.
+pub async fn serve(listener: TcpListener, acceptor: TlsAcceptor, app: Router) -> Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use tower::ServiceExt;
+
+    loop {
+        let (tcp, remote) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("Management API accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            // Don't let idle connections hold a task forever
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    tracing::debug!("TLS handshake with {} failed: {}", remote, e);
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!("TLS handshake with {} timed out", remote);
+                    return;
+                }
+            };
+
+            if let Some(cert) = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|c| c.first())
+            {
+                tracing::debug!(
+                    "Client certificate from {}: {} ({})",
+                    remote,
+                    subject(cert),
+                    fingerprint(cert)
+                );
+            }
+
+            let service = hyper::service::service_fn(move |req: hyper::Request<_>| {
+                app.clone().oneshot(req.map(Body::new))
+            });
+
+            if let Err(e) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("TLS connection from {} ended: {}", remote, e);
+            }
+        });
+    }
+}
+
+fn subject(cert: &Certificate) -> String {
+    x509_parser::parse_x509_certificate(&cert.0)
+        .map(|(_, cert)| cert.subject().to_string())
+        .unwrap_or_default()
+}
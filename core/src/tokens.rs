@@ -0,0 +1,323 @@
+use crate::config::Settings;
+use crate::crypto::Crypto;
+use anyhow::{Context, Result};
+use axum::http::Method;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+pub const TOKEN_PREFIX: &str = "zxt_";
+
+/// Route groups a token can be scoped to. `<group>` grants read and write,
+/// `<group>:read` only safe methods (GET/HEAD), `*` everything.
+pub const SCOPE_GROUPS: &[&str] = &[
+    "projects",  // projects, domains, files, stats, logs
+    "env",       // environment variables (`env:reveal` to read plaintext)
+    "deploy",    // deployments and webhooks
+    "volumes",   // persistent volumes
+    "snapshots", // snapshots and schedules
+    "services",  // system package install/uninstall and their jobs
+    "system",    // firewall and sync
+    "audit",     // audit log
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub hash: String, // Hex sha256 of the full token; the token itself is never stored
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at.map(|e| e > Utc::now()).unwrap_or(true)
+    }
+
+    /// Whether any of the token's scopes grants `required` (e.g. `env:write`).
+    /// Plaintext env values need `env:reveal` itself; the `env` group doesn't
+    /// grant it.
+    pub fn allows(&self, required: &str) -> bool {
+        let group = required.split(':').next().unwrap_or(required);
+        let by_group = required != "env:reveal";
+        self.scopes
+            .iter()
+            .any(|s| s == "*" || s == required || (by_group && s == group))
+    }
+}
+
+/// Scope required for a protected Management API request.
+pub fn required_scope(method: &Method, path: &str, query: Option<&str>) -> String {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let group = match segments.as_slice() {
+        ["projects", _, "env", ..] => "env",
+        ["projects", _, "deploy" | "webhook", ..] => "deploy",
+        ["projects", _, "volumes", ..] => "volumes",
+        ["projects", _, "snapshots", ..] => "snapshots",
+        ["projects", ..] => "projects",
+        ["services", ..] | ["jobs", ..] => "services",
+        ["audit", ..] => "audit",
+        _ => "system",
+    };
+
+    let reveal = query
+        .map(|q| q.split('&').any(|p| p == "reveal=true"))
+        .unwrap_or(false);
+    let action = if group == "env" && reveal {
+        "reveal"
+    } else if method == Method::GET || method == Method::HEAD {
+        "read"
+    } else {
+        "write"
+    };
+
+    format!("{}:{}", group, action)
+}
+
+fn validate_scope(scope: &str) -> Result<()> {
+    if scope == "*" {
+        return Ok(());
+    }
+    let (group, action) = match scope.split_once(':') {
+        Some((g, a)) => (g, Some(a)),
+        None => (scope, None),
+    };
+    if !SCOPE_GROUPS.contains(&group) {
+        anyhow::bail!(
+            "Unknown scope group '{}' (known: {})",
+            group,
+            SCOPE_GROUPS.join(", ")
+        );
+    }
+    match action {
+        None | Some("read") | Some("write") => Ok(()),
+        Some("reveal") if group == "env" => Ok(()),
+        Some(a) => anyhow::bail!("Unknown scope action '{}' in '{}'", a, scope),
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Parse a duration like `30d`, `12h` or `45m`.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration> {
+    let (num, unit) = s.split_at(s.len().saturating_sub(1));
+    let n: i64 = num
+        .parse()
+        .with_context(|| format!("Invalid duration '{}' (use e.g. 30d, 12h, 45m)", s))?;
+    match unit {
+        "d" => Ok(chrono::Duration::days(n)),
+        "h" => Ok(chrono::Duration::hours(n)),
+        "m" => Ok(chrono::Duration::minutes(n)),
+        _ => anyhow::bail!("Invalid duration '{}' (use e.g. 30d, 12h, 45m)", s),
+    }
+}
+
+/// Hashed API tokens in `tokens.json`. The file is re-read when it changes,
+/// so tokens created or revoked with the CLI apply to a running daemon.
+#[derive(Clone)]
+pub struct TokenStore {
+    path: PathBuf,
+    cache: Arc<RwLock<(Vec<ApiToken>, Option<SystemTime>)>>,
+}
+
+impl TokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            cache: Arc::new(RwLock::new((Vec::new(), None))),
+        }
+    }
+
+    fn mtime(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn list(&self) -> Result<Vec<ApiToken>> {
+        let current = Self::mtime(&self.path);
+        {
+            let guard = self
+                .cache
+                .read()
+                .map_err(|_| anyhow::anyhow!("Token cache lock poisoned"))?;
+            if guard.1 == current {
+                return Ok(guard.0.clone());
+            }
+        }
+
+        let tokens: Vec<ApiToken> = if current.is_some() {
+            let json = std::fs::read_to_string(&self.path)
+                .with_context(|| format!("Failed to read tokens from {:?}", self.path))?;
+            serde_json::from_str(&json).context("Failed to parse tokens file")?
+        } else {
+            Vec::new()
+        };
+
+        let mut guard = self
+            .cache
+            .write()
+            .map_err(|_| anyhow::anyhow!("Token cache lock poisoned"))?;
+        *guard = (tokens.clone(), current);
+        Ok(tokens)
+    }
+
+    fn save(&self, tokens: &[ApiToken]) -> Result<()> {
+        Crypto::write_secret_file(&self.path, &serde_json::to_vec_pretty(tokens)?)
+    }
+
+    /// Whether any usable token exists. In standalone mode this locks the API.
+    pub fn has_active(&self) -> bool {
+        self.list()
+            .map(|tokens| tokens.iter().any(|t| t.is_active()))
+            .unwrap_or(true) // Fail closed if the file is unreadable
+    }
+
+    /// Look up an active token by its plaintext value.
+    pub fn authenticate(&self, token: &str) -> Option<ApiToken> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        let hash = hash_token(token);
+        self.list()
+            .ok()?
+            .into_iter()
+            .find(|t| t.hash == hash && t.is_active())
+    }
+
+    /// Create a token. Returns the stored record and the plaintext token,
+    /// which is shown once and cannot be recovered.
+    pub fn create(
+        &self,
+        name: &str,
+        scopes: Vec<String>,
+        expires_in: Option<chrono::Duration>,
+    ) -> Result<(ApiToken, String)> {
+        if scopes.is_empty() {
+            anyhow::bail!("At least one scope is required");
+        }
+        for scope in &scopes {
+            validate_scope(scope)?;
+        }
+
+        let mut tokens = self.list()?;
+        if tokens
+            .iter()
+            .any(|t| t.name == name && t.revoked_at.is_none())
+        {
+            anyhow::bail!("A token named '{}' already exists", name);
+        }
+
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill(&mut secret);
+        let plaintext = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+
+        let now = Utc::now();
+        let token = ApiToken {
+            id: format!("tok_{}", &uuid::Uuid::new_v4().simple().to_string()[..12]),
+            name: name.to_string(),
+            hash: hash_token(&plaintext),
+            scopes,
+            created_at: now,
+            expires_at: expires_in.map(|d| now + d),
+            revoked_at: None,
+        };
+
+        tokens.push(token.clone());
+        self.save(&tokens)?;
+        Ok((token, plaintext))
+    }
+
+    /// Revoke a token by id or name.
+    pub fn revoke(&self, id_or_name: &str) -> Result<ApiToken> {
+        let mut tokens = self.list()?;
+        let token = tokens
+            .iter_mut()
+            .find(|t| t.revoked_at.is_none() && (t.id == id_or_name || t.name == id_or_name))
+            .ok_or_else(|| anyhow::anyhow!("No active token '{}'", id_or_name))?;
+        token.revoked_at = Some(Utc::now());
+        let revoked = token.clone();
+
+        self.save(&tokens)?;
+        Ok(revoked)
+    }
+}
+
+pub fn create(
+    settings: &Settings,
+    name: &str,
+    scope: &str,
+    expires_in: Option<&str>,
+) -> Result<()> {
+    let store = TokenStore::new(&settings.secrets.tokens_path);
+    let was_open = !store.has_active();
+
+    let scopes = scope
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let expires_in = expires_in.map(parse_duration).transpose()?;
+    let (token, plaintext) = store.create(name, scopes, expires_in)?;
+
+    println!("✅ Created token {} ({})", token.name, token.id);
+    println!("   Scopes:  {}", token.scopes.join(", "));
+    match token.expires_at {
+        Some(at) => println!("   Expires: {}", at.to_rfc3339()),
+        None => println!("   Expires: never"),
+    }
+    println!("\n   {}\n", plaintext);
+    println!("   Use it as `Authorization: Bearer <token>`. It will not be shown again.");
+    if was_open {
+        println!("   ⚠️  Standalone mode: the Management API now requires a token.");
+    }
+    Ok(())
+}
+
+pub fn list(settings: &Settings) -> Result<()> {
+    let tokens = TokenStore::new(&settings.secrets.tokens_path).list()?;
+    if tokens.is_empty() {
+        println!("No API tokens.");
+        return Ok(());
+    }
+
+    println!(
+        "{:<18} {:<20} {:<10} {:<26} SCOPES",
+        "ID", "NAME", "STATUS", "EXPIRES"
+    );
+    for t in tokens {
+        let status = if t.revoked_at.is_some() {
+            "revoked"
+        } else if !t.is_active() {
+            "expired"
+        } else {
+            "active"
+        };
+        let expires = t
+            .expires_at
+            .map(|e| e.to_rfc3339())
+            .unwrap_or_else(|| "never".to_string());
+        println!(
+            "{:<18} {:<20} {:<10} {:<26} {}",
+            t.id,
+            t.name,
+            status,
+            expires,
+            t.scopes.join(",")
+        );
+    }
+    Ok(())
+}
+
+pub fn revoke(settings: &Settings, id_or_name: &str) -> Result<()> {
+    let token = TokenStore::new(&settings.secrets.tokens_path).revoke(id_or_name)?;
+    println!("🗑️  Revoked token {} ({})", token.name, token.id);
+    Ok(())
+}
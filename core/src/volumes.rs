@@ -0,0 +1,497 @@
+use crate::config::Settings;
+use crate::storage::{ProjectConfig, VolumeConfig};
+use crate::{errors::AppError, state::AppState};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often volumes are measured and their quotas checked
+const USAGE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Clone)]
+pub struct VolumeUsage {
+    pub name: String,
+    pub mount_path: String,
+    pub host_path: String,
+    pub used_bytes: u64,
+    pub limit_bytes: Option<u64>,
+    pub over_quota: bool,
+}
+
+/// Host directory holding all volumes of a project.
+pub fn project_volumes_dir(settings: &Settings, project_id: &str) -> PathBuf {
+    PathBuf::from(&settings.storage.volumes_dir).join(project_id)
+}
+
+/// Host directory of a single volume.
+pub fn volume_dir(settings: &Settings, project_id: &str, name: &str) -> PathBuf {
+    project_volumes_dir(settings, project_id).join(name)
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        && name
+            .chars()
+            .next()
+            .map(|c| c.is_ascii_alphanumeric())
+            .unwrap_or(false);
+
+    if !valid {
+        return Err(AppError::BadRequest(
+            "Volume name must be 1-63 chars of [a-z0-9_-] starting with a letter or digit".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a mount path and return it normalized, e.g. `data//uploads/`
+/// becomes `data/uploads`.
+fn normalize_mount_path(mount_path: &str) -> Result<String, AppError> {
+    let path = std::path::Path::new(mount_path);
+    let valid = !mount_path.is_empty()
+        && path.is_relative()
+        && path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+
+    if !valid {
+        return Err(AppError::BadRequest(
+            "mount_path must be a relative path inside the release without '..'".into(),
+        ));
+    }
+    let parts: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+    Ok(parts.join("/"))
+}
+
+/// Whether two mount paths are equal or one is nested under the other
+/// (compared by path component, so `data/up` and `data/uploads` don't overlap).
+fn mount_paths_overlap(a: &str, b: &str) -> bool {
+    let (a, b) = (std::path::Path::new(a), std::path::Path::new(b));
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// Environment variables exposing each volume's host path to the app,
+/// e.g. `ZEXIO_VOLUME_UPLOADS=/var/lib/zexio/volumes/<project>/uploads`.
+pub fn env_vars(settings: &Settings, config: &ProjectConfig) -> Vec<(String, String)> {
+    config
+        .volumes
+        .iter()
+        .map(|v| {
+            let value = volume_dir(settings, &config.id, &v.name)
+                .to_string_lossy()
+                .to_string();
+            (env_key(&v.name), value)
+        })
+        .collect()
+}
+
+/// `ZEXIO_VOLUME_<NAME>`; `-` and `_` map to the same key.
+fn env_key(name: &str) -> String {
+    format!(
+        "ZEXIO_VOLUME_{}",
+        name.to_ascii_uppercase().replace('-', "_")
+    )
+}
+
+/// Refuse a bundle that ships files at a volume's mount path, given its
+/// entry names (`unzip -Z1`). Runs before extraction so a rejected bundle
+/// leaves the release untouched.
+pub fn check_bundle(config: &ProjectConfig, entries: &str) -> Result<(), AppError> {
+    for entry in entries.lines() {
+        let entry = entry.trim_start_matches("./").trim_end_matches('/');
+        if let Some(volume) = config.volumes.iter().find(|v| {
+            let mount = v.mount_path.trim_end_matches('/');
+            entry == mount
+                || entry
+                    .strip_prefix(mount)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        }) {
+            return Err(AppError::BadRequest(format!(
+                "Bundle contains '{}' which is reserved for volume '{}'",
+                volume.mount_path, volume.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Remove our symlinks from the release so `unzip -o` cannot write through
+/// them into volume data.
+pub async fn unlink_from_release(config: &ProjectConfig, release_dir: &str) -> Result<()> {
+    for volume in &config.volumes {
+        let link = PathBuf::from(release_dir).join(&volume.mount_path);
+        if let Ok(meta) = tokio::fs::symlink_metadata(&link).await {
+            if meta.file_type().is_symlink() {
+                tokio::fs::remove_file(&link)
+                    .await
+                    .with_context(|| format!("Failed to unlink volume at {:?}", link))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create missing volume directories and symlink them into the release.
+/// Anything else at a mount path (left by a release from before the volume
+/// existed; bundles are checked by `check_bundle`) is moved aside.
+pub async fn link_into_release(
+    settings: &Settings,
+    config: &ProjectConfig,
+    release_dir: &str,
+) -> Result<(), AppError> {
+    for volume in &config.volumes {
+        let target = volume_dir(settings, &config.id, &volume.name);
+        tokio::fs::create_dir_all(&target).await?;
+
+        let link = PathBuf::from(release_dir).join(&volume.mount_path);
+        if let Some(parent) = link.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        match tokio::fs::symlink_metadata(&link).await {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                tokio::fs::remove_file(&link).await?;
+            }
+            Ok(meta) => {
+                let aside = PathBuf::from(format!("{}.pre-volume", link.to_string_lossy()));
+                if let Ok(old) = tokio::fs::symlink_metadata(&aside).await {
+                    if old.is_dir() {
+                        tokio::fs::remove_dir_all(&aside).await?;
+                    } else {
+                        tokio::fs::remove_file(&aside).await?;
+                    }
+                }
+                tokio::fs::rename(&link, &aside).await?;
+                warn!(
+                    "Moved {} {:?} aside to {:?} to mount volume '{}'",
+                    if meta.is_dir() { "directory" } else { "file" },
+                    link,
+                    aside,
+                    volume.name
+                );
+            }
+            Err(_) => {}
+        }
+
+        #[cfg(unix)]
+        tokio::fs::symlink(&target, &link).await?;
+        #[cfg(windows)]
+        tokio::fs::symlink_dir(&target, &link).await?;
+    }
+    Ok(())
+}
+
+/// Recursively sum file sizes under `path` (blocking).
+fn dir_size(path: &std::path::Path) -> u64 {
+    let mut total = 0;
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                stack.push(entry.path());
+            } else {
+                total += meta.len();
+            }
+        }
+    }
+    total
+}
+
+fn volume_usage(v: VolumeConfig, host_path: String, used_bytes: u64) -> VolumeUsage {
+    let limit_bytes = v.size_limit_mb.map(|mb| mb * 1024 * 1024);
+    VolumeUsage {
+        name: v.name,
+        mount_path: v.mount_path,
+        host_path,
+        used_bytes,
+        limit_bytes,
+        over_quota: limit_bytes.map(|l| used_bytes > l).unwrap_or(false),
+    }
+}
+
+/// Current usage of every volume of a project.
+pub async fn usage(settings: &Settings, config: &ProjectConfig) -> Vec<VolumeUsage> {
+    let volumes: Vec<(VolumeConfig, PathBuf)> = config
+        .volumes
+        .iter()
+        .map(|v| (v.clone(), volume_dir(settings, &config.id, &v.name)))
+        .collect();
+
+    tokio::task::spawn_blocking(move || {
+        volumes
+            .into_iter()
+            .map(|(v, path)| {
+                let used_bytes = dir_size(&path);
+                volume_usage(v, path.to_string_lossy().to_string(), used_bytes)
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Last measured size of every volume, keyed by host path. Refreshed by
+/// `quota_monitor`, so frequent readers don't walk the volumes themselves.
+#[derive(Clone, Default)]
+pub struct UsageCache {
+    sizes: Arc<RwLock<HashMap<String, u64>>>,
+}
+
+impl UsageCache {
+    fn get(&self, host_path: &str) -> Option<u64> {
+        let sizes = self.sizes.read().unwrap_or_else(|e| e.into_inner());
+        sizes.get(host_path).copied()
+    }
+
+    fn insert(&self, usage: &[VolumeUsage]) {
+        let mut sizes = self.sizes.write().unwrap_or_else(|e| e.into_inner());
+        for v in usage {
+            sizes.insert(v.host_path.clone(), v.used_bytes);
+        }
+    }
+
+    fn replace(&self, new: HashMap<String, u64>) {
+        *self.sizes.write().unwrap_or_else(|e| e.into_inner()) = new;
+    }
+}
+
+/// Usage of every volume of a project as last measured. Volumes not measured
+/// yet (e.g. just created) are measured now.
+pub async fn cached_usage(state: &AppState, config: &ProjectConfig) -> Vec<VolumeUsage> {
+    let cached: Option<Vec<VolumeUsage>> = config
+        .volumes
+        .iter()
+        .map(|v| {
+            let host_path = volume_dir(&state.settings, &config.id, &v.name)
+                .to_string_lossy()
+                .to_string();
+            let used_bytes = state.volume_usage.get(&host_path)?;
+            Some(volume_usage(v.clone(), host_path, used_bytes))
+        })
+        .collect();
+
+    match cached {
+        Some(usage) => usage,
+        None => {
+            let usage = usage(&state.settings, config).await;
+            state.volume_usage.insert(&usage);
+            usage
+        }
+    }
+}
+
+/// Delete all volume data of a project. Only called on explicit opt-in.
+pub async fn purge(settings: &Settings, project_id: &str) -> Result<()> {
+    let dir = project_volumes_dir(settings, project_id);
+    if dir.exists() {
+        tokio::fs::remove_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to delete volumes at {:?}", dir))?;
+        info!("Purged volumes for project {}", project_id);
+    }
+    Ok(())
+}
+
+/// Periodically measure all volumes, refreshing the usage cache, and warn
+/// about volumes that go over quota.
+pub async fn quota_monitor(state: AppState) {
+    loop {
+        if let Ok(projects) = state.store.list().await {
+            let mut sizes = HashMap::new();
+            for project in projects.iter().filter(|p| !p.volumes.is_empty()) {
+                for v in usage(&state.settings, project).await {
+                    let was_over = state
+                        .volume_usage
+                        .get(&v.host_path)
+                        .zip(v.limit_bytes)
+                        .is_some_and(|(used, limit)| used > limit);
+                    if v.over_quota && !was_over {
+                        warn!(
+                            "Volume {} of project {} is over quota ({} / {} bytes)",
+                            v.name,
+                            project.id,
+                            v.used_bytes,
+                            v.limit_bytes.unwrap_or(0)
+                        );
+                    }
+                    sizes.insert(v.host_path, v.used_bytes);
+                }
+            }
+            state.volume_usage.replace(sizes);
+        }
+        tokio::time::sleep(USAGE_INTERVAL).await;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateVolumeRequest {
+    pub name: String,
+    pub mount_path: Option<String>,
+    pub size_limit_mb: Option<u64>,
+}
+
+pub async fn list_volumes_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+) -> Result<Json<Vec<VolumeUsage>>, AppError> {
+    let config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+
+    Ok(Json(usage(&state.settings, &config).await))
+}
+
+pub async fn create_volume_handler(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    Json(payload): Json<CreateVolumeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    validate_name(&payload.name)?;
+    let mount_path = normalize_mount_path(
+        &payload
+            .mount_path
+            .unwrap_or_else(|| format!("data/{}", payload.name)),
+    )?;
+
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+
+    if config.volumes.iter().any(|v| v.name == payload.name) {
+        return Err(AppError::BadRequest(format!(
+            "Volume {} already exists",
+            payload.name
+        )));
+    }
+    if let Some(other) = config
+        .volumes
+        .iter()
+        .find(|v| env_key(&v.name) == env_key(&payload.name))
+    {
+        return Err(AppError::BadRequest(format!(
+            "Volume {} would share {} with volume {}",
+            payload.name,
+            env_key(&payload.name),
+            other.name
+        )));
+    }
+    if let Some(other) = config
+        .volumes
+        .iter()
+        .find(|v| mount_paths_overlap(&v.mount_path, &mount_path))
+    {
+        return Err(AppError::BadRequest(format!(
+            "mount_path {} overlaps {} of volume {}",
+            mount_path, other.mount_path, other.name
+        )));
+    }
+
+    let dir = volume_dir(&state.settings, &project_id, &payload.name);
+    tokio::fs::create_dir_all(&dir).await?;
+
+    info!(
+        "Created volume {} for project {} at {:?}",
+        payload.name, project_id, dir
+    );
+
+    config.volumes.push(VolumeConfig {
+        name: payload.name.clone(),
+        mount_path,
+        size_limit_mb: payload.size_limit_mb,
+        created_at: chrono::Utc::now(),
+    });
+
+    state
+        .store
+        .update(&config)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "name": payload.name,
+            "host_path": dir.to_string_lossy(),
+            "status": "created"
+        })),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteVolumeQuery {
+    #[serde(default)]
+    pub purge: bool,
+}
+
+pub async fn delete_volume_handler(
+    State(state): State<AppState>,
+    Path((project_id, name)): Path<(String, String)>,
+    Query(query): Query<DeleteVolumeQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let _lock = state
+        .store
+        .lock(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+    let mut config = state
+        .store
+        .read(&project_id)
+        .await
+        .map_err(|_| AppError::BadRequest("Project not found".into()))?;
+
+    let before = config.volumes.len();
+    config.volumes.retain(|v| v.name != name);
+    if config.volumes.len() == before {
+        return Err(AppError::BadRequest(format!("Volume {} not found", name)));
+    }
+
+    state
+        .store
+        .update(&config)
+        .await
+        .map_err(|_| AppError::InternalServerError)?;
+
+    // Detaching keeps the data unless the caller explicitly opts in
+    if query.purge {
+        let dir = volume_dir(&state.settings, &project_id, &name);
+        if dir.exists() {
+            tokio::fs::remove_dir_all(&dir).await?;
+        }
+        info!("Deleted volume {} of project {}", name, project_id);
+        Ok((StatusCode::OK, "Volume deleted"))
+    } else {
+        info!(
+            "Detached volume {} of project {} (data retained)",
+            name, project_id
+        );
+        Ok((StatusCode::OK, "Volume detached"))
+    }
+}